@@ -8,11 +8,12 @@
 
 use anyhow::{Context, Result};
 use customlog::{ConsoleLogger, FileLogger, FlushGuard, RotateOptions, RotateSize};
-use getopts::Options;
+use getopts::{Options, ParsingStyle};
 use log::{LevelFilter, error, info};
 use std::env;
+use sys::oneshot;
 use sys::sysmod::SystemModules;
-use sys::taskserver::{Control, RunResult, TaskServer};
+use sys::taskserver::{RunResult, TaskServer, admin};
 
 /// ログのファイル出力先。
 const FILE_LOG: &str = "shanghai.log";
@@ -76,40 +77,17 @@ fn init_log(verbose: bool) -> Result<FlushGuard> {
     Ok(guard)
 }
 
-/// 起動時に一度だけブートメッセージをツイートするタスク。
-async fn boot_msg_task(ctrl: Control) -> Result<()> {
-    let build_info = verinfo::version_info();
-    // 同一テキストをツイートしようとするとエラーになるので日時を含める
-    let now = chrono::Local::now();
-    let now = now.format("%F %T %:z");
-    let msg = format!("[{now}] Boot...\n{build_info}");
-
-    /*{
-        let mut twitter = ctrl.sysmods().twitter.lock().await;
-        if let Err(why) = twitter.tweet(&msg).await {
-            error!("error on tweet");
-            error!("{why:#?}");
-        }
-    }*/
-    {
-        let mut discord = ctrl.sysmods().discord.lock().await;
-        if let Err(why) = discord.say(&msg).await {
-            error!("error on discord notification");
-            error!("{why:#?}");
-        }
-    }
-
-    Ok(())
-}
-
 /// システムメイン処理。
 /// コマンドラインとデーモン化、ログの初期化の後に入る。
 ///
 /// 設定データをロードする。
 /// その後、システムモジュールとタスクサーバを初期化し、システムの実行を開始する。
 ///
+/// * SIGHUP: 設定の再ロード
 /// * SIGUSR1: ログのフラッシュ
 /// * SIGUSR2: なし
+///
+/// 実行中のシステムは `ctl` サブコマンド ([sys::taskserver::admin]) からも操作できる。
 fn system_main() -> Result<()> {
     let config_dir = utils::dir::config_dir()?;
 
@@ -130,7 +108,6 @@ fn system_main() -> Result<()> {
         let sysmods = SystemModules::new()?;
         let ts = TaskServer::new(sysmods);
 
-        ts.spawn_oneshot_task("boot_msg", boot_msg_task);
         let run_result = ts.run(sigusr1, sigusr2);
 
         info!("task server dropped");
@@ -170,6 +147,8 @@ fn create_systemd_files() -> Result<()> {
         .context("Invalid UTF-8 in group name")?;
 
     const TIMEOUT_STOP_SEC: u32 = 20;
+    const TIMEOUT_START_SEC: u32 = 90;
+    const WATCHDOG_SEC: u32 = 60;
 
     let src = format!(
         "\
@@ -184,7 +163,8 @@ Wants = network-online.target
 After = network-online.target
 
 [Service]
-Type=simple
+# READY=1, WATCHDOG=1, STOPPING=1 are sent to $NOTIFY_SOCKET
+Type=notify
 Restart=always
 User={user}
 Group={group}
@@ -192,8 +172,10 @@ Environment=HOME={home}
 WorkingDirectory={cd}
 ExecStart={exe}
 # ExecStop default: SYGTERM
+TimeoutStartSec={TIMEOUT_START_SEC}
 TimeoutStopSec={TIMEOUT_STOP_SEC}
-ExecReload=/bin/kill -s SIGUSR1 $MAINPID
+WatchdogSec={WATCHDOG_SEC}
+ExecReload=/bin/kill -s SIGHUP $MAINPID
 
 [Install]
 WantedBy=multi-user.target
@@ -212,8 +194,79 @@ WantedBy=multi-user.target
 /// * `program` - プログラム名 (argv\[0\])。
 /// * `opts` - パーサオブジェクト。
 fn print_help(program: &str, opts: Options) {
-    let brief = format!("Usage: {program} [options]");
+    let brief = format!(
+        "Usage: {program} [options]\n       \
+         {program} [options] <one-shot command>\n       \
+         {program} ctl <command>"
+    );
     print!("{}", opts.usage(&brief));
+    println!();
+    print!("{}", oneshot::COMMAND_HELP);
+    println!();
+    print!("{}", admin::COMMAND_HELP);
+}
+
+/// `ctl` サブコマンド。
+///
+/// 実行中のシステムの管理用ソケットにコマンドを送り、結果の JSON を表示する。
+/// 失敗した場合は終了コード 1 で終了する。
+///
+/// * `args` - `ctl` より後のコマンドライン引数。
+fn ctl_main(args: &[String]) -> Result<()> {
+    let req = admin::parse_command(args)?;
+    let resp = admin::request(&req)?;
+    println!("{}", serde_json::to_string_pretty(&resp)?);
+    if !resp.ok {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// ワンショットのサブコマンド ([sys::oneshot])。
+///
+/// ログは stderr にのみ出力し、デーモンのログファイルには書かない。
+/// 設定データをロードしてからコマンドを1回だけ実行する。
+///
+/// * `args` - サブコマンド名以降のコマンドライン引数。
+fn oneshot_main(args: &[String], verbose: bool) -> Result<()> {
+    let cmd = oneshot::parse_command(args)?;
+
+    let filter = if verbose {
+        LevelFilter::Trace
+    } else {
+        LevelFilter::Warn
+    };
+    let console_log = ConsoleLogger::new_boxed(
+        customlog::Console::Stderr,
+        filter,
+        log_target_filter,
+        customlog::default_formatter,
+    );
+    let _flush = customlog::init(vec![console_log], filter);
+
+    sys::config::load(&utils::dir::config_dir()?)?;
+    oneshot::run(cmd)
+}
+
+/// `--check-config` オプション。
+///
+/// 設定ファイルを検証し、見つかった問題をすべて表示する。
+/// 問題があれば終了コード 1 で終了する。
+fn check_config_main() -> Result<()> {
+    let config_dir = utils::dir::config_dir()?;
+    let report = sys::config::check(&config_dir)?;
+    for problem in report.problems() {
+        println!("{problem}");
+    }
+    if report.is_empty() {
+        println!("OK: no problems found");
+    } else {
+        println!("{} problem(s) found", report.problems().len());
+        std::process::exit(1);
+    }
+
+    Ok(())
 }
 
 /// エントリポイント。
@@ -225,8 +278,16 @@ pub fn main() -> Result<()> {
     let program = &args[0];
 
     let mut opts = Options::new();
+    // サブコマンド以降の引数はサブコマンド側で解釈する
+    opts.parsing_style(ParsingStyle::StopAtFirstFree);
     opts.optflag("h", "help", "Print this help");
     opts.optflag("v", "verbose", "Print verbose logs on stdout");
+    opts.optflag("", "check-config", "Validate the config file and exit");
+    opts.optflag(
+        "",
+        "dry-run",
+        "Do not send any outbound posts (log them instead)",
+    );
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(fail) => {
@@ -241,7 +302,24 @@ pub fn main() -> Result<()> {
         std::process::exit(0);
     }
 
+    // --check-config がある場合は検証して終了
+    if matches.opt_present("check-config") {
+        return check_config_main();
+    }
+
     let verbose = matches.opt_present("v");
+    // 設定ファイルの dry_run.enabled によらず有効にする
+    if matches.opt_present("dry-run") {
+        sys::dryrun::force_enable();
+    }
+
+    // サブコマンドはデーモンの初期化を行わない
+    if let Some((cmd, rest)) = matches.free.split_first() {
+        if cmd == "ctl" {
+            return ctl_main(rest);
+        }
+        return oneshot_main(&matches.free, verbose);
+    }
 
     let _flush = init_log(verbose)?;
     create_systemd_files()?;
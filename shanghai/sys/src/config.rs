@@ -1,16 +1,30 @@
 //! 設定データの管理。
+//!
+//! 設定ファイル `config.toml` に `config.d/*.toml` を重ね合わせたものが設定データとなる。
+//! 詳細は [layer] を参照。
+//!
+//! 秘密情報は設定ファイルに直接書く代わりに、環境変数やファイルを参照できる。
+//! 詳細は [secret] を参照。
 
-use anyhow::{Context, Result, ensure};
+mod layer;
+mod secret;
+mod validate;
+
+pub use validate::{ConfigProblem, ValidationReport};
+
+use anyhow::{Context, Result, bail, ensure};
 use log::info;
 use log::warn;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::OpenOptions;
 use std::fs::remove_file;
 use std::io::{Read, Write};
 use std::os::unix::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
+use crate::dryrun::DryRunConfig;
 use crate::sysmod::camera::CameraConfig;
 use crate::sysmod::discord::DiscordConfig;
 use crate::sysmod::health::HealthConfig;
@@ -18,9 +32,14 @@ use crate::sysmod::http::HttpConfig;
 use crate::sysmod::line::LineConfig;
 use crate::sysmod::openai::OpenAiConfig;
 use crate::sysmod::twitter::TwitterConfig;
+use crate::sysmod::uptime::UptimeConfig;
+use crate::sysmod::watchdog::WatchdogConfig;
+use crate::taskserver::{TaskPolicy, TaskServerConfig};
 
 /// ロードする設定ファイル。
 const CONFIG_FILE: &str = "config.toml";
+/// [CONFIG_FILE] に重ね合わせる設定ファイルのディレクトリ。
+const CONFIG_DROP_IN_DIR: &str = "config.d";
 /// デフォルト設定の出力ファイル名。
 const CONFIG_DEF_FILE: &str = "config_default.toml";
 /// 現在設定の出力ファイル名。
@@ -28,6 +47,8 @@ const CONFIG_CUR_FILE: &str = "config_current.toml";
 
 /// 設定データ(グローバル変数)。
 static CONFIG: RwLock<Option<Config>> = RwLock::new(None);
+/// 最後に [load] に成功したディレクトリ。[reload] で使う。
+static CONFIG_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
 
 /// 設定データ。
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -35,6 +56,10 @@ pub struct Config {
     #[serde(default)]
     pub health: HealthConfig,
     #[serde(default)]
+    pub uptime: UptimeConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
     pub camera: CameraConfig,
     #[serde(default)]
     pub twitter: TwitterConfig,
@@ -46,11 +71,20 @@ pub struct Config {
     pub openai: OpenAiConfig,
     #[serde(default)]
     pub http: HttpConfig,
+    #[serde(default)]
+    pub taskserver: TaskServerConfig,
+    #[serde(default)]
+    pub dry_run: DryRunConfig,
+    /// 周期タスクの実行ポリシー。タスク名をキーとする。
+    #[serde(default)]
+    pub tasks: BTreeMap<String, TaskPolicy>,
 }
 
 /// 設定データをロードする。
+///
+/// 何度でも呼び出せる。
+/// 失敗した場合、グローバル変数の設定データは変更されない。
 pub fn load(dir: &Path) -> Result<()> {
-    let config_path = dir.join(CONFIG_FILE);
     let config_def_path = dir.join(CONFIG_DEF_FILE);
     let config_cur_path = dir.join(CONFIG_CUR_FILE);
 
@@ -82,39 +116,24 @@ pub fn load(dir: &Path) -> Result<()> {
         // close
     }
 
-    let toml_str = {
-        // 設定ファイルを読む
-        // open 後パーミッションを確認し、危険ならエラーとする
-        info!("loading config: {CONFIG_FILE}");
-        let mut f = OpenOptions::new()
-            .read(true)
-            .open(&config_path)
-            .with_context(|| format!("Failed to open {CONFIG_FILE} (the first execution?)"))
-            .with_context(|| {
-                format!("HINT: Copy {CONFIG_DEF_FILE} to {CONFIG_FILE} and try again")
-            })?;
-
-        let metadata = f.metadata()?;
-        let permissions = metadata.permissions();
-        let masked = permissions.mode() & 0o777;
-        ensure!(
-            masked == 0o600,
-            "Config file permission is not 600: {:03o}",
-            permissions.mode()
-        );
-
-        let mut toml_str = String::new();
-        f.read_to_string(&mut toml_str)
-            .with_context(|| format!("Failed to read {CONFIG_FILE}"))?;
-        info!("OK: {CONFIG_FILE} loaded");
+    let layered = read_layers(dir)?;
+    let mut table = layered.table;
 
-        toml_str
-        // close f
-    };
+    // 秘密情報の参照を解決する
+    let mut report = ValidationReport::default();
+    secret::resolve(&mut table, &mut report);
+    if !report.is_empty() {
+        for problem in report.problems() {
+            warn!("config: {problem}");
+        }
+        bail!("Failed to resolve secrets in config");
+    }
+    let new_config: Config = table.clone().try_into()?;
 
-    // グローバル変数に設定する
-    let mut config = CONFIG.write().unwrap();
-    *config = Some(toml::from_str(&toml_str)?);
+    // 起動を妨げない問題は警告のみ
+    for problem in validate::validate(&table, &new_config).problems() {
+        warn!("config: {problem}");
+    }
 
     {
         // 現在設定ファイルを削除する
@@ -122,10 +141,17 @@ pub fn load(dir: &Path) -> Result<()> {
         if let Err(e) = remove_file(&config_cur_path) {
             warn!("removing {CONFIG_CUR_FILE} failed (the first time execution?): {e}");
         }
-        // 現在設定を書き出す
+        // 秘密情報を伏せ、各値の出所を付けた現在設定を書き出す
         // permission=600 でアトミックに必ず新規作成する、失敗したらエラー
         info!("writing current config to {CONFIG_CUR_FILE}");
-        let main_toml = toml::to_string(&*config)?;
+        let mut cur_table = toml::Table::try_from(&new_config)?;
+        redact_table(&mut cur_table);
+        let main_toml = format!(
+            "# Effective config merged from: {}\n\
+             # Values without a comment are defaults. Secrets are redacted.\n\n{}",
+            layered.files.join(", "),
+            layer::to_annotated_string(&cur_table, &layered.sources)
+        );
         let mut f = OpenOptions::new()
             .write(true)
             .create_new(true)
@@ -138,9 +164,191 @@ pub fn load(dir: &Path) -> Result<()> {
         // close
     }
 
+    // グローバル変数に設定する
+    *CONFIG.write().unwrap() = Some(new_config);
+    *CONFIG_DIR.write().unwrap() = Some(dir.to_path_buf());
+
     Ok(())
 }
 
+/// `dir` の [CONFIG_FILE] に [CONFIG_DROP_IN_DIR] 内のファイルをファイル名順にマージする。
+fn read_layers(dir: &Path) -> Result<layer::Layered> {
+    let mut layered = layer::Layered::default();
+
+    let toml_str = read_config_file(&dir.join(CONFIG_FILE), CONFIG_FILE)
+        .with_context(|| format!("HINT: Copy {CONFIG_DEF_FILE} to {CONFIG_FILE} and try again"))?;
+    layered.merge(parse_table(&toml_str, CONFIG_FILE)?, CONFIG_FILE);
+
+    for path in layer::drop_in_files(&dir.join(CONFIG_DROP_IN_DIR))? {
+        let name = format!(
+            "{CONFIG_DROP_IN_DIR}/{}",
+            path.file_name().unwrap().to_string_lossy()
+        );
+        let toml_str = read_config_file(&path, &name)?;
+        layered.merge(parse_table(&toml_str, &name)?, &name);
+    }
+
+    Ok(layered)
+}
+
+fn parse_table(toml_str: &str, name: &str) -> Result<toml::Table> {
+    toml::from_str(toml_str).with_context(|| format!("Failed to parse {name}"))
+}
+
+/// 設定ファイルを読む。
+///
+/// open 後パーミッションを確認し、危険ならエラーとする。
+///
+/// * `name` - ログやエラーメッセージ用のファイル名。
+fn read_config_file(path: &Path, name: &str) -> Result<String> {
+    info!("loading config: {name}");
+    let mut f = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {name}"))?;
+
+    let metadata = f.metadata()?;
+    let permissions = metadata.permissions();
+    let masked = permissions.mode() & 0o777;
+    ensure!(
+        masked == 0o600,
+        "Config file permission is not 600: {name}: {:03o}",
+        permissions.mode()
+    );
+
+    let mut toml_str = String::new();
+    f.read_to_string(&mut toml_str)
+        .with_context(|| format!("Failed to read {name}"))?;
+    info!("OK: {name} loaded");
+
+    Ok(toml_str)
+    // close f
+}
+
+/// `dir` の設定ファイルを検証する。`--check-config` 用。
+///
+/// グローバル変数の設定データは変更しない。
+/// パーミッション、TOML の構文や型のエラーは Err として返す。
+/// それ以外の問題はすべて集めて返す。
+pub fn check(dir: &Path) -> Result<ValidationReport> {
+    validate::validate_table(read_layers(dir)?.table)
+}
+
+impl Config {
+    /// 全設定データを検証し、問題を `report` に追加する。
+    fn validate(&self, report: &mut ValidationReport) {
+        self.health.validate(report);
+        self.uptime.validate(report);
+        self.watchdog.validate(report);
+        self.camera.validate(report);
+        self.twitter.validate(report);
+        self.discord.validate(report);
+        self.line.validate(report);
+        self.openai.validate(report);
+        self.http.validate(report);
+        self.dry_run.validate(report);
+
+        // 画像の返信に一時公開 URL を使う
+        if self.line.is_enabled() {
+            report.require(
+                "http.server_url",
+                self.http.server_url(),
+                "line.enabled = true",
+            );
+        }
+    }
+}
+
+/// 最後に [load] したディレクトリから設定データを再ロードする。
+///
+/// 失敗した場合、現在の設定データは変更されない。
+pub fn reload() -> Result<ConfigChanges> {
+    let dir = CONFIG_DIR
+        .read()
+        .unwrap()
+        .clone()
+        .context("config is not loaded yet")?;
+
+    let old = get(|cfg| cfg.clone());
+    load(&dir)?;
+    let new = get(|cfg| cfg.clone());
+
+    ConfigChanges::new(old, new)
+}
+
+/// 再ロード前後の設定データと、その差分。
+#[derive(Debug, Clone)]
+pub struct ConfigChanges {
+    /// 再ロード前の設定データ。
+    pub old: Config,
+    /// 再ロード後の設定データ。
+    pub new: Config,
+    /// 変更のあったトップレベルのセクション名。
+    sections: BTreeSet<String>,
+}
+
+impl ConfigChanges {
+    /// `old` と `new` をセクションごとに比較する。
+    pub fn new(old: Config, new: Config) -> Result<Self> {
+        let old_table = toml::Table::try_from(&old)?;
+        let new_table = toml::Table::try_from(&new)?;
+
+        let sections = old_table
+            .keys()
+            .chain(new_table.keys())
+            .filter(|&key| old_table.get(key) != new_table.get(key))
+            .cloned()
+            .collect();
+
+        Ok(Self { old, new, sections })
+    }
+
+    /// `section` ("discord" 等) に変更があれば true を返す。
+    pub fn is_changed(&self, section: &str) -> bool {
+        self.sections.contains(section)
+    }
+
+    /// 変更のあったセクション名を名前順で返す。
+    pub fn sections(&self) -> impl Iterator<Item = &str> {
+        self.sections.iter().map(|s| s.as_str())
+    }
+}
+
+/// 秘密情報を伏せた表示用の文字列。
+const REDACTED: &str = "<redacted>";
+
+/// 秘密情報を伏せた現在の設定データを返す。
+///
+/// キー名が秘密情報らしいもの ([is_secret_key]) のうち、空でない文字列を置き換える。
+pub fn redacted() -> Result<toml::Table> {
+    let mut table = toml::Table::try_from(get(|cfg| cfg.clone()))?;
+    redact_table(&mut table);
+
+    Ok(table)
+}
+
+/// `token`, `*_token`, `*secret`, `*_key` を秘密情報とみなす。
+fn is_secret_key(key: &str) -> bool {
+    key == "token" || key.ends_with("_token") || key.ends_with("secret") || key.ends_with("_key")
+}
+
+fn redact_table(table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        match value {
+            toml::Value::String(s) if is_secret_key(key) && !s.is_empty() => {
+                *s = REDACTED.to_string();
+            }
+            toml::Value::Table(t) => redact_table(t),
+            toml::Value::Array(array) => {
+                for t in array.iter_mut().filter_map(|v| v.as_table_mut()) {
+                    redact_table(t);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 pub struct ConfigGuard;
 
 impl Drop for ConfigGuard {
@@ -182,4 +390,74 @@ mod tests {
         let _unset = set(Default::default());
         get(|cfg| println!("{cfg:?}"));
     }
+
+    /// 以前のデフォルト設定をコピーした config.toml も、追加された項目はデフォルト値で読めること。
+    #[test]
+    fn baseline_config() {
+        let toml_str = include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/res/test/config/baseline_default.toml"
+        ));
+        let config: Config = toml::from_str(toml_str).unwrap();
+
+        let config = toml::Table::try_from(config).unwrap();
+        let def = toml::Table::try_from(Config::default()).unwrap();
+        for (section, key) in [
+            ("camera", "schedule"),
+            ("health", "check_schedule"),
+            ("health", "tweet_schedule"),
+            ("twitter", "tlcheck_schedule"),
+            ("discord", "autodel_schedule"),
+        ] {
+            assert_eq!(config[section][key], def[section][key], "{section}.{key}");
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let old: Config = Default::default();
+        let mut new = old.clone();
+        new.taskserver.shutdown_timeout_sec += 1;
+        new.tasks
+            .insert("camera-auto".to_string(), Default::default());
+
+        let changes = ConfigChanges::new(old.clone(), new).unwrap();
+        assert!(changes.is_changed("taskserver"));
+        assert!(changes.is_changed("tasks"));
+        assert!(!changes.is_changed("twitter"));
+        assert_eq!(
+            changes.sections().collect::<Vec<_>>(),
+            ["tasks", "taskserver"]
+        );
+
+        let changes = ConfigChanges::new(old.clone(), old).unwrap();
+        assert_eq!(changes.sections().count(), 0);
+    }
+
+    #[test]
+    fn redact() {
+        let mut table: toml::Table = toml::from_str(
+            r##"
+            [discord]
+            token = "abc"
+            notif_channel = 123
+            [twitter]
+            consumer_key = "ck"
+            access_secret = "as"
+            ai_hashtag = "#ai"
+            [openai]
+            api_key = ""
+            "##,
+        )
+        .unwrap();
+        redact_table(&mut table);
+
+        assert_eq!(table["discord"]["token"].as_str(), Some(REDACTED));
+        assert_eq!(table["discord"]["notif_channel"].as_integer(), Some(123));
+        assert_eq!(table["twitter"]["consumer_key"].as_str(), Some(REDACTED));
+        assert_eq!(table["twitter"]["access_secret"].as_str(), Some(REDACTED));
+        assert_eq!(table["twitter"]["ai_hashtag"].as_str(), Some("#ai"));
+        // 未設定であることは分かるようにする
+        assert_eq!(table["openai"]["api_key"].as_str(), Some(""));
+    }
 }
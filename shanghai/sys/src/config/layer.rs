@@ -0,0 +1,288 @@
+//! 設定ファイルの重ね合わせ。
+//!
+//! `config.toml` をベースに、`config.d/*.toml` をファイル名順に深くマージする。
+//! テーブルはキーごとに再帰的にマージし、それ以外の値 (配列を含む) は後のもので置き換える。
+//!
+//! ```text
+//! config.toml             共通の設定
+//! config.d/10-pi.toml     ホスト固有の上書き
+//! config.d/20-dev.toml    [camera] fake_camera = true 等
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// 値のパス (e.g. `camera.fake_camera`) から、その値を設定したファイル名へのマップ。
+pub(super) type Sources = BTreeMap<String, String>;
+
+/// マージ結果。
+#[derive(Debug, Default)]
+pub(super) struct Layered {
+    /// マージされたテーブル。
+    pub table: toml::Table,
+    /// 各値の出所。
+    pub sources: Sources,
+    /// マージしたファイル名のリスト。マージ順。
+    pub files: Vec<String>,
+}
+
+impl Layered {
+    /// `overlay` をマージする。
+    ///
+    /// * `source` - `overlay` のファイル名。
+    pub fn merge(&mut self, overlay: toml::Table, source: &str) {
+        merge(&mut self.table, overlay, source, &mut self.sources);
+        self.files.push(source.to_string());
+    }
+}
+
+/// `dir` にある `*.toml` をファイル名順に返す。
+///
+/// ディレクトリが存在しなければ空を返す。
+pub(super) fn drop_in_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "toml") {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// `base` に `overlay` を深くマージし、上書きした値の出所を `sources` に記録する。
+///
+/// * `source` - `overlay` のファイル名。
+pub(super) fn merge(
+    base: &mut toml::Table,
+    overlay: toml::Table,
+    source: &str,
+    sources: &mut Sources,
+) {
+    merge_at(base, overlay, "", source, sources);
+}
+
+fn merge_at(
+    base: &mut toml::Table,
+    overlay: toml::Table,
+    path: &str,
+    source: &str,
+    sources: &mut Sources,
+) {
+    for (key, value) in overlay {
+        let path = join_path(path, &key);
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_at(base, overlay, &path, source, sources);
+            }
+            (_, value) => {
+                record_sources(&value, &path, source, sources);
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn record_sources(value: &toml::Value, path: &str, source: &str, sources: &mut Sources) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                record_sources(value, &join_path(path, key), source, sources);
+            }
+        }
+        _ => {
+            sources.insert(path.to_string(), source.to_string());
+        }
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+/// `table` を TOML 文字列にし、各値の行末に `sources` による出所をコメントで付ける。
+///
+/// 出所のない値はデフォルト値である。
+pub(super) fn to_annotated_string(table: &toml::Table, sources: &Sources) -> String {
+    let mut out = String::new();
+    write_table(&mut out, table, "", "", Some(sources));
+
+    out
+}
+
+/// `table` の中身を書く。ヘッダは呼び出し側で書く。
+///
+/// * `header` - `table` のヘッダ名。キーは必要に応じてクォート済み。
+/// * `path` - `sources` を引くための `table` のパス。
+/// * `sources` - テーブルの配列はまとめて置き換えられるため、出所は `[[...]]` にのみ付け、
+///   要素内は None として書く。
+fn write_table(
+    out: &mut String,
+    table: &toml::Table,
+    header: &str,
+    path: &str,
+    sources: Option<&Sources>,
+) {
+    let source_of = |key: &str| sources.and_then(|sources| sources.get(&join_path(path, key)));
+    let is_table_array = |value: &toml::Value| {
+        value
+            .as_array()
+            .is_some_and(|a| !a.is_empty() && a.iter().all(|v| v.is_table()))
+    };
+
+    // 値を先に書き、テーブルとテーブルの配列は後で書く
+    for (key, value) in table {
+        if value.is_table() || is_table_array(value) {
+            continue;
+        }
+        write!(out, "{} = {value}", format_key(key)).unwrap();
+        if let Some(source) = source_of(key) {
+            write!(out, " # {source}").unwrap();
+        }
+        out.push('\n');
+    }
+    for (key, value) in table {
+        let header = join_path(header, &format_key(key));
+        if let toml::Value::Table(sub) = value {
+            writeln!(out, "\n[{header}]").unwrap();
+            write_table(out, sub, &header, &join_path(path, key), sources);
+        } else if is_table_array(value) {
+            for elem in value.as_array().unwrap() {
+                write!(out, "\n[[{header}]]").unwrap();
+                if let Some(source) = source_of(key) {
+                    write!(out, " # {source}").unwrap();
+                }
+                out.push('\n');
+                write_table(out, elem.as_table().unwrap(), &header, "", None);
+            }
+        }
+    }
+}
+
+/// 必要ならクォートしたキー。
+fn format_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        toml::Value::String(key.to_string()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deep_merge() {
+        let main: toml::Table = toml::from_str(
+            r#"
+            [camera]
+            enabled = true
+            fake_camera = false
+            [discord]
+            auto_del_chs = [1, 2]
+            [line.id_name_map]
+            U1 = "a"
+            "#,
+        )
+        .unwrap();
+        let mut base = toml::Table::new();
+        let mut sources = Sources::new();
+        merge(&mut base, main, "config.toml", &mut sources);
+
+        let overlay: toml::Table = toml::from_str(
+            r#"
+            [camera]
+            fake_camera = true
+            [discord]
+            auto_del_chs = [3]
+            [line.id_name_map]
+            U2 = "b"
+            "#,
+        )
+        .unwrap();
+        merge(&mut base, overlay, "config.d/20-dev.toml", &mut sources);
+
+        let expected: toml::Table = toml::from_str(
+            r#"
+            [camera]
+            enabled = true
+            fake_camera = true
+            [discord]
+            auto_del_chs = [3]
+            [line.id_name_map]
+            U1 = "a"
+            U2 = "b"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+
+        assert_eq!(sources["camera.enabled"], "config.toml");
+        assert_eq!(sources["camera.fake_camera"], "config.d/20-dev.toml");
+        assert_eq!(sources["discord.auto_del_chs"], "config.d/20-dev.toml");
+        assert_eq!(sources["line.id_name_map.U1"], "config.toml");
+        assert_eq!(sources["line.id_name_map.U2"], "config.d/20-dev.toml");
+    }
+
+    #[test]
+    fn annotated() {
+        let table: toml::Table = toml::from_str(
+            r#"
+            top = 1
+            [camera]
+            enabled = true
+            fake_camera = true
+            [tasks.camera-auto]
+            retry = 3
+            [[twitter.rules]]
+            user_names = ["a"]
+            [twitter.rules.option]
+            ignore_case = true
+            [[twitter.rules]]
+            user_names = ["b"]
+            [line.id_name_map]
+            "U 1" = "a"
+            ["a.b".c]
+            d = 1
+            "#,
+        )
+        .unwrap();
+        let sources: Sources = [
+            ("camera.enabled", "config.toml"),
+            ("camera.fake_camera", "config.d/20-dev.toml"),
+            ("twitter.rules", "config.d/10-pi.toml"),
+            ("line.id_name_map.U 1", "config.toml"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let s = to_annotated_string(&table, &sources);
+        assert!(s.contains("\nenabled = true # config.toml\n"));
+        assert!(s.contains("\nfake_camera = true # config.d/20-dev.toml\n"));
+        assert!(s.contains("\nretry = 3\n"));
+        assert!(s.contains("\n[[twitter.rules]] # config.d/10-pi.toml\n"));
+        assert!(s.contains("\n\"U 1\" = \"a\" # config.toml\n"));
+
+        // コメントを付けても同じ内容として読める
+        let reparsed: toml::Table = toml::from_str(&s).unwrap();
+        assert_eq!(reparsed, table);
+    }
+}
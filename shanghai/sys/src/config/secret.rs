@@ -0,0 +1,148 @@
+//! 秘密情報の外部参照。
+//!
+//! 秘密情報のキー ([super::is_secret_key]) の値には、値そのものの代わりに
+//! 以下の参照を書くことができ、ロード時に解決される。
+//! 設定ファイルをバージョン管理する場合、秘密情報をその外に置くために使う。
+//!
+//! * `env:NAME` - 環境変数 `NAME` の値。
+//! * `file:PATH` - ファイル `PATH` の内容。末尾の改行は取り除く。
+//!
+//! ```toml
+//! [discord]
+//! token = "file:/run/secrets/discord"
+//!
+//! [openai]
+//! api_key = "env:OPENAI_API_KEY"
+//! ```
+
+use super::{ValidationReport, is_secret_key};
+
+const ENV_PREFIX: &str = "env:";
+const FILE_PREFIX: &str = "file:";
+
+/// `table` 中の秘密情報の参照を解決する。
+///
+/// 解決できなかったものは値を変更せず、`report` に追加する。
+pub(super) fn resolve(table: &mut toml::Table, report: &mut ValidationReport) {
+    resolve_with(table, "", &|name| std::env::var(name).ok(), report);
+}
+
+fn resolve_with(
+    table: &mut toml::Table,
+    path: &str,
+    env: &dyn Fn(&str) -> Option<String>,
+    report: &mut ValidationReport,
+) {
+    for (key, value) in table.iter_mut() {
+        let path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        match value {
+            toml::Value::String(s) if is_secret_key(key) => match resolve_value(s, env) {
+                Ok(Some(resolved)) => *s = resolved,
+                Ok(None) => {}
+                Err(msg) => report.add(&path, msg),
+            },
+            toml::Value::Table(t) => resolve_with(t, &path, env, report),
+            toml::Value::Array(array) => {
+                for (i, elem) in array.iter_mut().enumerate() {
+                    if let toml::Value::Table(t) = elem {
+                        resolve_with(t, &format!("{path}[{i}]"), env, report);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 参照を解決する。参照でなければ None を返す。
+fn resolve_value(
+    value: &str,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Option<String>, String> {
+    if let Some(name) = value.strip_prefix(ENV_PREFIX) {
+        match env(name) {
+            Some(v) if !v.is_empty() => Ok(Some(v)),
+            Some(_) => Err(format!("environment variable is empty: {name}")),
+            None => Err(format!("environment variable is not set: {name}")),
+        }
+    } else if let Some(file) = value.strip_prefix(FILE_PREFIX) {
+        let content = std::fs::read_to_string(file)
+            .map_err(|e| format!("cannot read secret file: {file} ({e})"))?;
+        let content = content.trim_end_matches(['\r', '\n']);
+        if content.is_empty() {
+            return Err(format!("secret file is empty: {file}"));
+        }
+        Ok(Some(content.to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        let tmp = tempfile::tempdir().unwrap();
+        let secret_file = tmp.path().join("discord");
+        std::fs::write(&secret_file, "discord-token\n").unwrap();
+        let empty_file = tmp.path().join("empty");
+        std::fs::write(&empty_file, "\n").unwrap();
+
+        let mut table: toml::Table = toml::from_str(&format!(
+            r#"
+            [discord]
+            token = "file:{}"
+            [openai]
+            api_key = "env:OPENAI_API_KEY"
+            model = "env:NOT_A_SECRET"
+            [twitter]
+            consumer_key = "inline"
+            consumer_secret = "env:UNSET"
+            access_token = "env:EMPTY"
+            access_secret = "file:{}"
+            [line]
+            channel_secret = "file:{}/none"
+            "#,
+            secret_file.display(),
+            empty_file.display(),
+            tmp.path().display(),
+        ))
+        .unwrap();
+        let env = |name: &str| match name {
+            "OPENAI_API_KEY" => Some("sk-xxx".to_string()),
+            "EMPTY" => Some("".to_string()),
+            _ => None,
+        };
+        let mut report = ValidationReport::default();
+        resolve_with(&mut table, "", &env, &mut report);
+
+        assert_eq!(table["discord"]["token"].as_str(), Some("discord-token"));
+        assert_eq!(table["openai"]["api_key"].as_str(), Some("sk-xxx"));
+        // 秘密情報以外は解決しない
+        assert_eq!(table["openai"]["model"].as_str(), Some("env:NOT_A_SECRET"));
+        assert_eq!(table["twitter"]["consumer_key"].as_str(), Some("inline"));
+        // 失敗したものはそのまま
+        assert_eq!(
+            table["twitter"]["consumer_secret"].as_str(),
+            Some("env:UNSET")
+        );
+
+        let mut paths = report.paths();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "line.channel_secret",
+                "twitter.access_secret",
+                "twitter.access_token",
+                "twitter.consumer_secret",
+            ]
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! 設定データの検証。
+//!
+//! TOML のパースだけでは見つからない問題 (未知のキー、有効な機能に必要な値の欠落等) を
+//! TOML 上のパスとともにすべて集める。
+//! 各設定データは `validate(&self, report)` でそれぞれの問題を [ValidationReport] に追加する。
+
+use super::Config;
+use crate::taskserver::schedule::Schedule;
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// 設定の問題点1つ分。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// TOML 上のパス。e.g. `discord.token`
+    pub path: String,
+    /// 内容。
+    pub message: String,
+}
+
+impl Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// 検証結果。
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    problems: Vec<ConfigProblem>,
+}
+
+impl ValidationReport {
+    /// 見つかった問題のリスト。
+    pub fn problems(&self) -> &[ConfigProblem] {
+        &self.problems
+    }
+
+    /// 見つかった問題の TOML 上のパスのリスト。
+    #[cfg(test)]
+    pub fn paths(&self) -> Vec<&str> {
+        self.problems.iter().map(|p| p.path.as_str()).collect()
+    }
+
+    /// 問題がなければ true を返す。
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// 問題を追加する。
+    pub fn add(&mut self, path: &str, message: impl Into<String>) {
+        self.problems.push(ConfigProblem {
+            path: path.to_string(),
+            message: message.into(),
+        });
+    }
+
+    /// `value` が空ならば報告する。
+    ///
+    /// * `reason` - 必要な理由。e.g. `"discord.enabled = true"`
+    pub fn require(&mut self, path: &str, value: &str, reason: &str) {
+        if value.is_empty() {
+            self.add(path, format!("must not be empty ({reason})"));
+        }
+    }
+
+    /// スケジュール書式として正しくなければ報告する。
+    pub fn check_schedule(&mut self, path: &str, value: &str) {
+        if let Err(e) = value.parse::<Schedule>() {
+            self.add(path, format!("invalid schedule: {e:#}"));
+        }
+    }
+
+    /// 監視機能 ([crate::sysmod::uptime], [crate::sysmod::watchdog]) の共通設定を検証する。
+    ///
+    /// `section` 以下の `check_schedule` と `flap_threshold`、
+    /// および `targets` の名前 `names` の重複を調べる。
+    pub fn check_monitor<'a>(
+        &mut self,
+        section: &str,
+        check_schedule: &str,
+        flap_threshold: u32,
+        names: impl IntoIterator<Item = &'a str>,
+    ) {
+        self.check_schedule(&format!("{section}.check_schedule"), check_schedule);
+        if flap_threshold == 0 {
+            self.add(&format!("{section}.flap_threshold"), "must be 1 or more");
+        }
+        let mut seen = BTreeSet::new();
+        for (i, name) in names.into_iter().enumerate() {
+            if !seen.insert(name) {
+                self.add(&format!("{section}.targets[{i}].name"), "duplicate name");
+            }
+        }
+    }
+
+    /// ファイルが存在しなければ報告する。
+    pub fn check_file(&mut self, path: &str, file: &str) {
+        if !Path::new(file).is_file() {
+            self.add(path, format!("file not found: {file}"));
+        }
+    }
+
+    /// ディレクトリに書き込めなければ報告する。
+    ///
+    /// 存在しない場合は作成できるか (存在する最も近い親に書き込めるか) を調べる。
+    /// 設定の読み込みのたびに呼ばれるので、ファイルは作らずに権限だけを調べる。
+    pub fn check_dir_writable(&mut self, path: &str, dir: &str) {
+        let Some(existing) = Path::new(dir).ancestors().find(|p| p.exists()) else {
+            // 相対パスの場合はカレントディレクトリ
+            return self.check_dir_writable(path, ".");
+        };
+        if !existing.is_dir() {
+            self.add(path, format!("not a directory: {}", existing.display()));
+            return;
+        }
+        if let Err(e) = check_access_write(existing) {
+            self.add(
+                path,
+                format!("directory is not writable: {} ({e})", existing.display()),
+            );
+        }
+    }
+}
+
+/// access(2) で書き込み権限を確認する。
+fn check_access_write(dir: &Path) -> std::io::Result<()> {
+    let cpath = CString::new(dir.as_os_str().as_bytes())?;
+    // SAFETY: cpath は NUL 終端文字列
+    let ret = unsafe { libc::access(cpath.as_ptr(), libc::W_OK) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// パース前の設定データを検証する。
+///
+/// 型エラーは Err として返す。
+/// 秘密情報の参照を解決できないものは問題として報告する。
+pub(super) fn validate_table(mut table: toml::Table) -> Result<ValidationReport> {
+    let mut report = ValidationReport::default();
+    super::secret::resolve(&mut table, &mut report);
+    let config: Config = table.clone().try_into()?;
+
+    validate_into(&table, &config, &mut report);
+    Ok(report)
+}
+
+/// パース前の `table` と、それをパースした `config` を検証する。
+pub(super) fn validate(table: &toml::Table, config: &Config) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    validate_into(table, config, &mut report);
+
+    report
+}
+
+fn validate_into(table: &toml::Table, config: &Config, report: &mut ValidationReport) {
+    check_unknown_keys(table, config, report);
+    config.validate(report);
+}
+
+/// [Config] に存在しないキーを報告する。
+///
+/// `#[serde(default)]` のため、綴りを間違えたキーは黙って無視されてしまう。
+fn check_unknown_keys(table: &toml::Table, config: &Config, report: &mut ValidationReport) {
+    // `table` をパースした結果をシリアライズしたものをスキーマとして使う。
+    // 構造体のフィールドとして読まれたキーだけが残る。
+    // デフォルト値では None の Option フィールドやマップの要素も、設定されていれば含まれる。
+    let schema = match toml::Table::try_from(config) {
+        Ok(schema) => schema,
+        Err(e) => return report.add("", format!("cannot serialize config: {e}")),
+    };
+    compare_keys(table, &schema, "", report);
+}
+
+fn compare_keys(
+    table: &toml::Table,
+    schema: &toml::Table,
+    path: &str,
+    report: &mut ValidationReport,
+) {
+    for (key, value) in table {
+        let path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{path}.{key}")
+        };
+        match (schema.get(key), value) {
+            (None, _) => report.add(&path, "unknown key"),
+            (Some(toml::Value::Table(schema)), toml::Value::Table(table)) => {
+                compare_keys(table, schema, &path, report);
+            }
+            (Some(toml::Value::Array(schema)), toml::Value::Array(array)) => {
+                // テーブルの配列は同じ位置の要素と比較する
+                for (i, (schema, elem)) in schema.iter().zip(array).enumerate() {
+                    if let (toml::Value::Table(schema), toml::Value::Table(elem)) = (schema, elem) {
+                        compare_keys(elem, schema, &format!("{path}[{i}]"), report);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate_str(toml_str: &str) -> Result<ValidationReport> {
+        validate_table(toml::from_str(toml_str)?)
+    }
+
+    /// デフォルト設定の書き込み先ディレクトリを `dir` 以下に向けたもの。
+    fn base_table(dir: &Path) -> toml::Table {
+        let mut table = toml::Table::try_from(Config::default()).unwrap();
+        let dir = dir.display();
+        set(
+            &mut table,
+            "camera.pic_history_dir",
+            format!("{dir}/history"),
+        );
+        set(
+            &mut table,
+            "camera.pic_archive_dir",
+            format!("{dir}/archive"),
+        );
+        set(&mut table, "openai.storage_dir", "");
+        table
+    }
+
+    /// `path` ("a.b.c") に値を設定する。
+    fn set(table: &mut toml::Table, path: &str, value: impl Into<toml::Value>) {
+        let (parents, key) = path.rsplit_once('.').unwrap_or(("", path));
+        let mut table = table;
+        for parent in parents.split('.').filter(|s| !s.is_empty()) {
+            table = table
+                .entry(parent)
+                .or_insert_with(|| toml::Table::new().into())
+                .as_table_mut()
+                .unwrap();
+        }
+        table.insert(key.to_string(), value.into());
+    }
+
+    fn problem_paths(table: &toml::Table) -> Vec<String> {
+        let report = validate_str(&toml::to_string(table).unwrap()).unwrap();
+        report.paths().into_iter().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn no_problem() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(problem_paths(&base_table(tmp.path())), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut table = base_table(tmp.path());
+        set(&mut table, "discord.tokne", "abc");
+        set(&mut table, "line.id_name_map.U0123", "name");
+        set(&mut table, "tasks.camera-auto.retry", 3);
+        set(&mut table, "tasks.camera-auto.retyr", 3);
+        set(&mut table, "unknown.key", 1);
+        let rules = table["twitter"]["tlcheck"]["rules"]
+            .as_array()
+            .unwrap()
+            .clone();
+        let mut rule = rules[0].as_table().unwrap().clone();
+        rule.insert("patern".to_string(), toml::Value::Array(vec![]));
+        set(
+            &mut table,
+            "twitter.tlcheck.rules",
+            toml::Value::Array(vec![rules[0].clone(), rule.into()]),
+        );
+        // デフォルト値では None の Option フィールド
+        let alert: toml::Table = toml::from_str(
+            r#"
+            name = "Low disk"
+            metric = "disk_avail_percent"
+            below = 5.0
+            recover = 10.0
+            recovr = 10.0
+            "#,
+        )
+        .unwrap();
+        set(
+            &mut table,
+            "health.alerts",
+            toml::Value::Array(vec![alert.into()]),
+        );
+
+        let mut paths = problem_paths(&table);
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                "discord.tokne",
+                "health.alerts[0].recovr",
+                "tasks.camera-auto.retyr",
+                "twitter.tlcheck.rules[1].patern",
+                "unknown",
+            ]
+        );
+    }
+
+    #[test]
+    fn semantic() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("file");
+        std::fs::write(&file, "").unwrap();
+
+        let mut table = base_table(tmp.path());
+        set(&mut table, "camera.schedule", "0 0 * *");
+        set(
+            &mut table,
+            "camera.pic_archive_dir",
+            format!("{}/archive", file.display()),
+        );
+        set(&mut table, "camera.page_by", 0);
+        set(&mut table, "camera.motion.enabled", true);
+        set(&mut table, "discord.enabled", true);
+        set(&mut table, "discord.auto_del_chs", vec![1, 0]);
+        set(&mut table, "line.enabled", true);
+        set(&mut table, "openai.model", "no-such-model");
+
+        assert_eq!(
+            problem_paths(&table),
+            [
+                "camera.schedule",
+                "camera.pic_archive_dir",
+                "camera.page_by",
+                "camera.motion.enabled",
+                "discord.token",
+                "discord.auto_del_chs[1]",
+                "line.token",
+                "line.channel_secret",
+                "openai.model",
+                "http.server_url",
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_error() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut table = base_table(tmp.path());
+        set(&mut table, "camera.page_by", "100");
+        assert!(validate_str(&toml::to_string(&table).unwrap()).is_err());
+        assert!(validate_str("[camera").is_err());
+    }
+}
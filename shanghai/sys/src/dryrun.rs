@@ -0,0 +1,144 @@
+//! ドライランモード。
+//!
+//! 有効な場合、外部への投稿や API の書き込みを送信せず、
+//! 送るはずだった内容をすべてログに出力する。
+//! 本番環境の横でステージング環境を安全に動かすために使う。
+//!
+//! 設定 [DryRunConfig::enabled] または起動オプション `--dry-run` ([force_enable]) で有効になる。
+//!
+//! [DryRunConfig::fixture_dir] を設定すると、送らなかったリクエストの応答や読み込みを
+//! 記録済みのファイル (フィクスチャ) から返す。
+//! ファイルのパスは `<fixture_dir>/<METHOD>/<host><path>` である。
+//!
+//! ```text
+//! fixtures/POST/api.openai.com/v1/responses
+//! fixtures/GET/api.line.me/v2/bot/profile/U0123
+//! ```
+//!
+//! 各システムモジュールでの扱い:
+//! * twitter - [crate::sysmod::twitter] の `fake_tweet` と同じ動作になる。
+//! * discord - Gateway に接続しない。発言はログのみ。
+//! * line - 返信と push は送信しない。
+//! * openai - API を呼ばない。フィクスチャがなければ固定の応答を返す。
+//! * watchdog - 再起動コマンドを実行しない。
+
+use crate::config;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 起動オプションにより強制的に有効化されているか。
+static FORCED: AtomicBool = AtomicBool::new(false);
+
+/// ドライラン設定データ。toml 設定に対応する。
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct DryRunConfig {
+    /// ドライランモードを有効にする。
+    pub enabled: bool,
+    /// フィクスチャのディレクトリ。空文字列ならば使わない。
+    pub fixture_dir: String,
+}
+
+impl DryRunConfig {
+    pub(crate) fn validate(&self, report: &mut config::ValidationReport) {
+        if !self.fixture_dir.is_empty() && !Path::new(&self.fixture_dir).is_dir() {
+            report.add(
+                "dry_run.fixture_dir",
+                format!("directory not found: {}", self.fixture_dir),
+            );
+        }
+    }
+}
+
+/// 設定によらずドライランモードを有効にする。起動オプション用。
+pub fn force_enable() {
+    FORCED.store(true, Ordering::Relaxed);
+}
+
+/// ドライランモードが有効ならば true を返す。
+pub fn is_enabled() -> bool {
+    FORCED.load(Ordering::Relaxed) || config::get(|cfg| cfg.dry_run.enabled)
+}
+
+/// 書き込みを送信する代わりにログに出力する。
+///
+/// 記録済みの応答があればそれを返す。
+///
+/// * `target` - ログ用のシステムモジュール名。
+pub fn write(target: &str, method: &str, url: &str, payload: &impl Serialize) -> Option<Vec<u8>> {
+    let payload = serde_json::to_string(payload).unwrap_or_else(|e| format!("<{e}>"));
+    info!("[dry-run] {target}: {method} {url} {payload}");
+
+    fixture(method, url)
+}
+
+/// ドライランモードならば、読み込みの記録済み応答を返す。
+///
+/// ドライランでない場合やフィクスチャがない場合は None を返し、実際に読み込むこと。
+pub fn read(target: &str, url: &str) -> Option<Vec<u8>> {
+    if !is_enabled() {
+        return None;
+    }
+    let bin = fixture("GET", url)?;
+    info!("[dry-run] {target}: GET {url} (fixture)");
+
+    Some(bin)
+}
+
+fn fixture(method: &str, url: &str) -> Option<Vec<u8>> {
+    let dir = config::get(|cfg| cfg.dry_run.fixture_dir.clone());
+    if dir.is_empty() {
+        return None;
+    }
+    let path = fixture_path(Path::new(&dir), method, url)?;
+    match std::fs::read(&path) {
+        Ok(bin) => Some(bin),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => {
+            warn!("[dry-run] cannot read {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// `url` に対応するフィクスチャのパス。クエリは無視する。
+fn fixture_path(dir: &Path, method: &str, url: &str) -> Option<PathBuf> {
+    let url = reqwest::Url::parse(url).ok()?;
+    let mut path = dir.join(method).join(url.host_str()?);
+    for seg in url.path_segments()?.filter(|seg| !seg.is_empty()) {
+        // ディレクトリの外を指さないようにする
+        if seg == ".." || seg == "." {
+            return None;
+        }
+        path.push(seg);
+    }
+
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path() {
+        let dir = Path::new("fixtures");
+        assert_eq!(
+            fixture_path(dir, "POST", "https://api.openai.com/v1/responses"),
+            Some(PathBuf::from("fixtures/POST/api.openai.com/v1/responses"))
+        );
+        assert_eq!(
+            fixture_path(dir, "GET", "https://api.line.me/v2/bot/profile/U0123?x=1"),
+            Some(PathBuf::from(
+                "fixtures/GET/api.line.me/v2/bot/profile/U0123"
+            ))
+        );
+        // ".." は URL のパースで解決される
+        assert_eq!(
+            fixture_path(dir, "GET", "https://example.com/a/../../b"),
+            Some(PathBuf::from("fixtures/GET/example.com/b"))
+        );
+        assert_eq!(fixture_path(dir, "GET", "not a url"), None);
+    }
+}
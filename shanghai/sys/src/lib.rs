@@ -1,6 +1,9 @@
 //! 基本的なシステム関連。
 
 pub mod config;
+pub mod dryrun;
+pub mod oneshot;
 pub mod rpienv;
+pub mod state;
 pub mod sysmod;
 pub mod taskserver;
@@ -0,0 +1,227 @@
+//! ワンショットのサブコマンド。
+//!
+//! デーモンを起動せず、必要なシステムモジュールだけを作成して1回だけ実行し、終了する。
+//! bot を経由せずに各機能を試すために使う。
+//!
+//! ```text
+//! $ shanghai tweet "Hello"
+//! $ shanghai take-pic --archive
+//! $ shanghai chat "今日の広島の天気は?"
+//! ```
+//!
+//! システムモジュールの [crate::sysmod::SystemModule::on_start] は呼ばないため、
+//! bot や周期タスクは動かず、イベント通知も行われない。
+//! 実行中のデーモンとは状態を共有しない
+//! (e.g. `take-pic` の画像がデーモンの画像リストに現れるのは次回起動時)。
+
+use crate::sysmod::SystemModules;
+use crate::sysmod::camera::{self, Camera, TakePicOption};
+use crate::sysmod::discord::Discord;
+use crate::sysmod::line::Line;
+use crate::sysmod::openai::basicfuncs;
+use crate::sysmod::openai::chat_history::ChatHistory;
+use crate::sysmod::openai::function::FunctionTable;
+use crate::sysmod::openai::{Role, Tool};
+use crate::sysmod::twitter::Twitter;
+use crate::taskserver::{Control, TaskServer};
+use anyhow::{Context, Result, bail, ensure};
+use log::info;
+use std::sync::Arc;
+
+/// サブコマンド一覧。ヘルプ表示用。
+pub const COMMAND_HELP: &str = "\
+One-shot commands:
+    tweet <text>            Tweet
+    discord-say <text>      Post to the Discord notification channel
+    line-push <to> <text>   Push a LINE message to a user or group ID
+    take-pic [--archive]    Take a picture (and copy it to the archive)
+    chat <prompt>           Chat with OpenAI using the basic functions
+    weather <area>          Print the weather report of the area
+";
+
+/// ワンショットのサブコマンド。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Tweet { text: String },
+    DiscordSay { text: String },
+    LinePush { to: String, text: String },
+    TakePic { archive: bool },
+    Chat { prompt: String },
+    Weather { area: String },
+}
+
+/// コマンドライン引数 (サブコマンド名以降) を [Command] に変換する。
+///
+/// テキストを取るコマンドは、残りの引数を空白でつなげたものをテキストとする。
+pub fn parse_command(args: &[String]) -> Result<Command> {
+    let (cmd, rest) = args.split_first().context("command is required")?;
+    let text = || -> Result<String> {
+        let text = rest.join(" ");
+        ensure!(!text.trim().is_empty(), "{cmd}: text is required");
+        Ok(text)
+    };
+
+    let cmd = match (cmd.as_str(), rest) {
+        ("tweet", _) => Command::Tweet { text: text()? },
+        ("discord-say", _) => Command::DiscordSay { text: text()? },
+        ("line-push", [to, text @ ..]) if !text.is_empty() => Command::LinePush {
+            to: to.clone(),
+            text: text.join(" "),
+        },
+        ("take-pic", []) => Command::TakePic { archive: false },
+        ("take-pic", [opt]) if opt == "--archive" => Command::TakePic { archive: true },
+        ("chat", _) => Command::Chat { prompt: text()? },
+        ("weather", [area]) => Command::Weather { area: area.clone() },
+        _ => bail!("invalid command: {}", args.join(" ")),
+    };
+
+    Ok(cmd)
+}
+
+/// `cmd` を実行し、結果を stdout に出力する。
+///
+/// 設定データはロード済みであること。
+pub fn run(cmd: Command) -> Result<()> {
+    info!("[oneshot] {cmd:?}");
+
+    // function からは他のシステムモジュールが参照されるため、chat のみ全モジュールを作成する
+    if let Command::Chat { prompt } = cmd {
+        let ts = TaskServer::new(SystemModules::new()?);
+        let reply = ts.run_oneshot(move |ctrl| async move { chat(ctrl, &prompt).await })?;
+        println!("{reply}");
+        return Ok(());
+    }
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(run_async(cmd))
+}
+
+async fn run_async(cmd: Command) -> Result<()> {
+    match cmd {
+        Command::Tweet { text } => {
+            Twitter::new()?.tweet(&text).await?;
+            println!("tweeted");
+        }
+        Command::DiscordSay { text } => {
+            Discord::new()?.say_oneshot(&text).await?;
+            println!("posted");
+        }
+        Command::LinePush { to, text } => {
+            Line::new()?.push_message(&to, &text).await?;
+            println!("pushed");
+        }
+        Command::TakePic { archive } => {
+            let name = take_pic(archive).await?;
+            println!("{name}");
+        }
+        Command::Weather { area } => {
+            println!("{}", basicfuncs::weather_report(&area).await?);
+        }
+        Command::Chat { .. } => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// 撮影してヒストリに保存し、エントリ名を返す。
+async fn take_pic(archive: bool) -> Result<String> {
+    let mut camera = Camera::new()?;
+
+    let pic = camera::take_a_pic(TakePicOption::new()).await?;
+    let thumb = camera::create_thumbnail(&pic)?;
+    let name = camera.push_pic_history(&pic, &thumb).await?;
+    if archive {
+        camera.push_pic_archive(&name).await?;
+    }
+
+    Ok(name)
+}
+
+/// `prompt` を1回だけ送り、AI の返答を返す。
+///
+/// 返答が得られるまで function 呼び出しを繰り返す。
+async fn chat(ctrl: Control, prompt: &str) -> Result<String> {
+    let mut func_table = FunctionTable::new(Arc::clone(&ctrl), None);
+    func_table.register_basic_functions();
+    let tools: Vec<_> = func_table
+        .function_list()
+        .iter()
+        .map(|f| Tool::Function(f.clone()))
+        .collect();
+
+    let model = ctrl.sysmods().openai.lock().await.model_name().to_string();
+    let mut history = ChatHistory::new(&model);
+    history.push_input_message(Role::User, prompt)?;
+
+    loop {
+        let input = Vec::from_iter(history.iter().cloned());
+        let resp = {
+            let mut ai = ctrl.sysmods().openai.lock().await;
+            ai.chat_with_tools(None, input, &tools).await?
+        };
+        for fc in resp.func_call_iter() {
+            let func_out = func_table.call((), &fc.name, &fc.arguments).await;
+            info!(
+                "[oneshot] function call: {}({}) -> {func_out}",
+                fc.name, fc.arguments
+            );
+            history.push_function(&fc.call_id, &fc.name, &fc.arguments, &func_out)?;
+        }
+        let text = resp.output_text();
+        let text = if text.is_empty() { None } else { Some(text) };
+        history.push_output_and_tools(text.as_deref(), resp.web_search_iter().cloned())?;
+
+        if let Some(text) = text {
+            return Ok(text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            parse_command(&args("tweet Hello world")).unwrap(),
+            Command::Tweet {
+                text: "Hello world".to_string()
+            }
+        );
+        assert_eq!(
+            parse_command(&args("line-push U0123 Hello world")).unwrap(),
+            Command::LinePush {
+                to: "U0123".to_string(),
+                text: "Hello world".to_string()
+            }
+        );
+        assert_eq!(
+            parse_command(&args("take-pic")).unwrap(),
+            Command::TakePic { archive: false }
+        );
+        assert_eq!(
+            parse_command(&args("take-pic --archive")).unwrap(),
+            Command::TakePic { archive: true }
+        );
+        assert_eq!(
+            parse_command(&args("weather 広島県")).unwrap(),
+            Command::Weather {
+                area: "広島県".to_string()
+            }
+        );
+
+        assert!(parse_command(&args("")).is_err());
+        assert!(parse_command(&args("tweet")).is_err());
+        assert!(parse_command(&args("line-push U0123")).is_err());
+        assert!(parse_command(&args("take-pic --all")).is_err());
+        assert!(parse_command(&args("weather")).is_err());
+        assert!(parse_command(&args("unknown")).is_err());
+    }
+}
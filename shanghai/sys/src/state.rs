@@ -0,0 +1,181 @@
+//! システムモジュールの状態の永続化。
+//!
+//! 状態は [utils::dir::state_dir] 以下に `<name>.json` として保存される。
+//! 各システムモジュールは [crate::sysmod::SystemModule::on_stop] で [save] し、
+//! コンストラクタで [load] して復元する。
+//!
+//! 書き込みは一時ファイルに書いてから rename することでアトミックに行う。
+//! 途中で電源断等が起きても、古い状態か新しい状態のどちらかが残る。
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use serde::{Serialize, de::DeserializeOwned};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// `name` の状態をロードする。
+///
+/// 存在しない場合や壊れている場合は None を返す。
+/// 状態の復元に失敗しても起動は続けるため、エラーはログにのみ出力する。
+pub fn load<T: DeserializeOwned>(name: &str) -> Option<T> {
+    match utils::dir::state_dir() {
+        Ok(dir) => load_from(&dir, name),
+        Err(e) => {
+            warn!("[state] {e:#}");
+            None
+        }
+    }
+}
+
+/// `name` の状態をアトミックに保存する。
+pub fn save<T: Serialize>(name: &str, value: &T) -> Result<()> {
+    let dir = utils::dir::state_dir()?;
+    save_to(&dir, name, value)
+}
+
+fn state_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{name}.json"))
+}
+
+fn load_from<T: DeserializeOwned>(dir: &Path, name: &str) -> Option<T> {
+    let path = state_path(dir, name);
+    let bin = match std::fs::read(&path) {
+        Ok(bin) => bin,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("[state] no saved state: {name}");
+            return None;
+        }
+        Err(e) => {
+            warn!("[state] cannot read {}: {e}", path.display());
+            return None;
+        }
+    };
+    match serde_json::from_slice(&bin) {
+        Ok(value) => {
+            info!("[state] loaded: {}", path.display());
+            Some(value)
+        }
+        Err(e) => {
+            warn!("[state] ignore broken state {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+fn save_to<T: Serialize>(dir: &Path, name: &str, value: &T) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let path = state_path(dir, name);
+    let tmp_path = dir.join(format!("{name}.json.tmp"));
+
+    let json = serde_json::to_vec(value)?;
+    {
+        // permission=600 で一時ファイルに書いて fsync する
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)
+            .with_context(|| format!("Failed to open {}", tmp_path.display()))?;
+        f.write_all(&json)?;
+        f.sync_all()?;
+        // close
+    }
+    std::fs::rename(&tmp_path, &path)
+        .with_context(|| format!("Failed to rename to {}", path.display()))?;
+    // rename 自体も永続化する
+    File::open(dir)?.sync_all()?;
+    info!("[state] saved: {}", path.display());
+
+    Ok(())
+}
+
+/// 保存のため、[Instant] を現在時刻基準で時刻に変換する。
+pub fn instant_to_datetime(instant: Instant) -> DateTime<Local> {
+    let now = Instant::now();
+    let local_now = Local::now();
+    if instant >= now {
+        local_now + (instant - now)
+    } else {
+        local_now - (now - instant)
+    }
+}
+
+/// [instant_to_datetime] の逆変換。
+///
+/// 過去の時刻は現在の [Instant] になる。
+pub fn datetime_to_instant(dt: DateTime<Local>) -> Instant {
+    let now = Instant::now();
+    match (dt - Local::now()).to_std() {
+        Ok(duration) => now + duration,
+        Err(_) => now,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::time::Duration;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct State {
+        id: Option<String>,
+        count: u32,
+    }
+
+    #[test]
+    fn save_load() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("state");
+
+        assert_eq!(load_from::<State>(&dir, "test"), None);
+
+        let state = State {
+            id: Some("123".to_string()),
+            count: 1,
+        };
+        save_to(&dir, "test", &state).unwrap();
+        assert_eq!(load_from(&dir, "test"), Some(state));
+
+        // 上書き
+        let state = State { id: None, count: 2 };
+        save_to(&dir, "test", &state).unwrap();
+        assert_eq!(load_from(&dir, "test"), Some(state));
+
+        let mode = std::fs::metadata(state_path(&dir, "test"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(!dir.join("test.json.tmp").exists());
+    }
+
+    #[test]
+    fn broken() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::write(state_path(tmp.path(), "test"), "{ broken").unwrap();
+
+        assert_eq!(load_from::<State>(tmp.path(), "test"), None);
+    }
+
+    #[test]
+    fn instant_conversion() {
+        let later = Instant::now() + Duration::from_secs(600);
+        let restored = datetime_to_instant(instant_to_datetime(later));
+        let diff = if restored > later {
+            restored - later
+        } else {
+            later - restored
+        };
+        assert!(diff < Duration::from_secs(1));
+
+        // 過去は現在になる
+        let earlier = Local::now() - chrono::Duration::minutes(10);
+        assert!(datetime_to_instant(earlier) <= Instant::now());
+    }
+}
@@ -6,30 +6,67 @@ pub mod health;
 pub mod http;
 pub mod line;
 pub mod openai;
+pub mod status;
 pub mod sysinfo;
 pub mod twitter;
+pub mod uptime;
+pub mod watchdog;
 
 use self::{
     camera::Camera, discord::Discord, health::Health, http::HttpServer, openai::OpenAi,
-    sysinfo::SystemInfo, twitter::Twitter,
+    sysinfo::SystemInfo, twitter::Twitter, uptime::Uptime, watchdog::Watchdog,
 };
+use crate::config::{self, ConfigChanges};
+use crate::taskserver::lock::RankedMutex;
 use crate::{rpienv, sysmod::line::Line, taskserver::Control};
 use anyhow::Result;
-use chrono::NaiveTime;
-use log::info;
+use log::{error, info, warn};
 use std::sync::Arc;
-use tokio::sync::Mutex as TokioMutex;
+use std::time::Duration;
 
 /// システムモジュールが実装するトレイト。
 pub trait SystemModule: Sync + Send {
     /// [SystemModule] の初期化時には [crate::sys::taskserver::TaskServer] がまだ存在しないので
     /// タスクの登録はこのタイミングまで遅延させる。
     fn on_start(&mut self, _ctrl: &Control) {}
+
+    /// シャットダウンシーケンスにおいて、全タスクの完了後に呼ばれる。
+    /// 設定の再ロードによる再起動の前にも呼ばれる。
+    ///
+    /// 次回起動時に引き継ぐ状態は、ここで [crate::state::save] しておき、
+    /// コンストラクタで [crate::state::load] して復元する。
+    fn on_stop(&mut self, _ctrl: &Control) {}
+
+    /// 設定の再ロードにより、自身の設定セクションが変更された時に呼ばれる。
+    ///
+    /// その場で反映できた場合は [ConfigAction::Applied] を返す。
+    /// 反映できない場合は [ConfigAction::Restart] を返す。
+    /// その場合、このモジュールだけが新しい設定で作り直され、
+    /// [Self::on_start] で生成したタスクにはキャンセルが通知され
+    /// ([crate::taskserver::Controller::wait_cancel_rx])、
+    /// それらの完了後に [Self::on_stop] が呼ばれる。
+    /// その後、新しいモジュールの [Self::on_start] が呼ばれる。
+    /// 作り直しに失敗した場合は、同じインスタンスの [Self::on_start] が再度呼ばれる。
+    ///
+    /// デフォルトでは再起動する。
+    fn on_config_changed(&mut self, _ctrl: &Control, _changes: &ConfigChanges) -> ConfigAction {
+        ConfigAction::Restart
+    }
+}
+
+/// [SystemModule::on_config_changed] の結果。
+pub enum ConfigAction {
+    /// 新しい設定を反映した。
+    Applied,
+    /// モジュールの再起動を要求する。
+    Restart,
 }
 
 /// [SystemModules] 内の [SystemModule] はマルチスレッドにアクセスされるため、
 /// ロックが必要かつ await 可能。
-type SysModArc<T> = Arc<TokioMutex<T>>;
+///
+/// ロック順序は [crate::taskserver::lock] により検査される。
+type SysModArc<T> = Arc<RankedMutex<T>>;
 
 /// タスクのエントリポイントに渡される引数からアクセス可能な [SystemModule] のリスト。
 /// デッドロックに注意。
@@ -38,9 +75,19 @@ type SysModArc<T> = Arc<TokioMutex<T>>;
 /// それぞれの [SystemModule] はアクセスする前にロックを取得する必要があるが、
 /// 複数同時にロックする場合、その順番に気を付けないと
 /// デッドロックを引き起こす可能性がある。
+///
+/// 複数同時にロックする場合は、下記フィールドの宣言順にロックしなければならない。
+/// 他のモジュールを呼び出す側 (twitter, discord, line) を先に、
+/// 呼び出される側 (openai, http) を後に置いている。
+/// 違反した場合、デバッグビルドでは panic する。
+///
+/// 他のモジュールへの通知は直接ロックせず、
+/// [crate::taskserver::event] のイベントとして発行すること。
 pub struct SystemModules {
     pub sysinfo: SysModArc<sysinfo::SystemInfo>,
     pub health: SysModArc<health::Health>,
+    pub uptime: SysModArc<uptime::Uptime>,
+    pub watchdog: SysModArc<watchdog::Watchdog>,
     pub camera: SysModArc<camera::Camera>,
     pub twitter: SysModArc<twitter::Twitter>,
     pub discord: SysModArc<discord::Discord>,
@@ -60,59 +107,24 @@ impl SystemModules {
         let rpienv = rpienv::raspi_env();
         info!("{}", rpienv);
 
-        let wakeup_health_ck: Vec<_> = (0..24)
-            .flat_map(|hour| (0..60).map(move |min| NaiveTime::from_hms_opt(hour, min, 0).unwrap()))
-            .collect();
-        let wakeup_health_tw = vec![
-            NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
-        ];
-
-        let wakeup_camera = vec![
-            NaiveTime::from_hms_opt(0, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(3, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(15, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
-            NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
-        ];
-
-        let wakeup_twiter: Vec<_> = (0..24)
-            .flat_map(|hour| {
-                (0..60)
-                    .step_by(5)
-                    .map(move |min| NaiveTime::from_hms_opt(hour, min, 0).unwrap())
-            })
-            .collect();
-
-        let wakeup_discord: Vec<_> = (0..24)
-            .flat_map(|hour| {
-                (0..60)
-                    .step_by(10)
-                    .map(move |min| NaiveTime::from_hms_opt(hour, min, 0).unwrap())
-            })
-            .collect();
-
         let mut event_target_list: Vec<SysModArc<dyn SystemModule>> = vec![];
 
-        let sysinfo = Arc::new(TokioMutex::new(SystemInfo::new()));
-        let health = Arc::new(TokioMutex::new(Health::new(
-            wakeup_health_ck,
-            wakeup_health_tw,
-        )?));
-        let camera = Arc::new(TokioMutex::new(Camera::new(wakeup_camera)?));
-        let twitter = Arc::new(TokioMutex::new(Twitter::new(wakeup_twiter)?));
-        let discord = Arc::new(TokioMutex::new(Discord::new(wakeup_discord)?));
-        let line = Arc::new(TokioMutex::new(Line::new()?));
-        let openai = Arc::new(TokioMutex::new(OpenAi::new()?));
-        let http = Arc::new(TokioMutex::new(HttpServer::new()?));
+        // ロック順位はフィールドの宣言順
+        let sysinfo = Arc::new(RankedMutex::new("sysinfo", 0, SystemInfo::new()));
+        let health = Arc::new(RankedMutex::new("health", 1, Health::new()?));
+        let uptime = Arc::new(RankedMutex::new("uptime", 2, Uptime::new()?));
+        let watchdog = Arc::new(RankedMutex::new("watchdog", 3, Watchdog::new()?));
+        let camera = Arc::new(RankedMutex::new("camera", 4, Camera::new()?));
+        let twitter = Arc::new(RankedMutex::new("twitter", 5, Twitter::new()?));
+        let discord = Arc::new(RankedMutex::new("discord", 6, Discord::new()?));
+        let line = Arc::new(RankedMutex::new("line", 7, Line::new()?));
+        let openai = Arc::new(RankedMutex::new("openai", 8, OpenAi::new()?));
+        let http = Arc::new(RankedMutex::new("http", 9, HttpServer::new()?));
 
         event_target_list.push(sysinfo.clone());
         event_target_list.push(health.clone());
+        event_target_list.push(uptime.clone());
+        event_target_list.push(watchdog.clone());
         event_target_list.push(camera.clone());
         event_target_list.push(twitter.clone());
         event_target_list.push(discord.clone());
@@ -124,6 +136,8 @@ impl SystemModules {
         Ok(Self {
             sysinfo,
             health,
+            uptime,
+            watchdog,
             camera,
             twitter,
             discord,
@@ -137,8 +151,97 @@ impl SystemModules {
     pub async fn on_start(&self, ctrl: &Control) {
         info!("invoke on_start for system modules...");
         for sysmod in self.event_target_list.iter() {
-            sysmod.lock().await.on_start(ctrl);
+            let mut m = sysmod.lock().await;
+            ctrl.with_task_owner(sysmod.name(), || m.on_start(ctrl));
         }
         info!("OK: invoke on_start for system modules");
     }
+
+    /// [SystemModule::on_stop] を [Self::on_start] と逆順に配送する。
+    pub async fn on_stop(&self, ctrl: &Control) {
+        info!("invoke on_stop for system modules...");
+        for sysmod in self.event_target_list.iter().rev() {
+            sysmod.lock().await.on_stop(ctrl);
+        }
+        info!("OK: invoke on_stop for system modules");
+    }
+
+    /// `section` を設定セクションとするシステムモジュールがあれば true を返す。
+    ///
+    /// セクション名はロック用の名前と同じ。
+    pub fn has_section(&self, section: &str) -> bool {
+        self.event_target_list
+            .iter()
+            .any(|sysmod| sysmod.name() == section)
+    }
+
+    /// 設定セクションが変更されたシステムモジュールに
+    /// [SystemModule::on_config_changed] を配送する。
+    ///
+    /// セクション名はロック用の名前と同じ。
+    pub async fn on_config_changed(&self, ctrl: &Control, changes: &ConfigChanges) {
+        info!("invoke on_config_changed for system modules...");
+        reload_module(ctrl, changes, &self.health, Health::new).await;
+        reload_module(ctrl, changes, &self.uptime, Uptime::new).await;
+        reload_module(ctrl, changes, &self.watchdog, Watchdog::new).await;
+        reload_module(ctrl, changes, &self.camera, Camera::new).await;
+        reload_module(ctrl, changes, &self.twitter, Twitter::new).await;
+        reload_module(ctrl, changes, &self.discord, Discord::new).await;
+        reload_module(ctrl, changes, &self.line, Line::new).await;
+        reload_module(ctrl, changes, &self.openai, OpenAi::new).await;
+        reload_module(ctrl, changes, &self.http, HttpServer::new).await;
+        info!("OK: invoke on_config_changed for system modules");
+    }
+}
+
+/// 設定セクションが変更されていれば [SystemModule::on_config_changed] を呼び、
+/// 必要ならば `new` でモジュールを作り直す。
+///
+/// 作り直しに失敗した場合は古いモジュールを再度開始する。
+async fn reload_module<T: SystemModule>(
+    ctrl: &Control,
+    changes: &ConfigChanges,
+    module: &SysModArc<T>,
+    new: fn() -> Result<T>,
+) {
+    let name = module.name();
+    if !changes.is_changed(name) {
+        return;
+    }
+
+    {
+        let mut m = module.lock().await;
+        match ctrl.with_task_owner(name, || m.on_config_changed(ctrl, changes)) {
+            ConfigAction::Applied => {
+                info!("[{name}] config applied");
+                return;
+            }
+            ConfigAction::Restart => {}
+        }
+        // 停止処理中のタスクがロックできるよう、ここで解放する
+    }
+
+    info!("[{name}] restart...");
+    let timeout = config::get(|cfg| cfg.taskserver.shutdown_timeout_sec);
+    let aborted = ctrl
+        .stop_owned_tasks(name, Duration::from_secs(timeout))
+        .await;
+    for task in aborted {
+        warn!("[{name}] abort task: {task}");
+    }
+
+    let mut m = module.lock().await;
+    // 状態を保存してから作り直す
+    m.on_stop(ctrl);
+    match new() {
+        Ok(new_module) => {
+            *m = new_module;
+            info!("[{name}] OK: restart");
+        }
+        Err(e) => {
+            error!("[{name}] restart failed, keep running the old one");
+            error!("{e:#}");
+        }
+    }
+    ctrl.with_task_owner(name, || m.on_start(ctrl));
 }
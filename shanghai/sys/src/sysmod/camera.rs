@@ -2,25 +2,34 @@
 //!
 //! 専用カメラを搭載した Raspberry Pi 以外の環境では撮影できない。
 //! [CameraConfig::fake_camera] 設定でフェイクできる。
+//!
+//! 定期撮影 ([Camera::auto_task]) の他に、[motion] による動体検知モードを持つ。
+
+pub mod motion;
 
 use super::SystemModule;
-use crate::taskserver::Control;
-use crate::{config, rpienv, taskserver};
-use anyhow::{Result, anyhow, bail, ensure};
-use chrono::{Local, NaiveTime};
+use crate::config::{self, ValidationReport};
+use crate::taskserver::event::{MotionDetected, PictureTaken};
+use crate::taskserver::{Control, schedule::Schedule};
+use crate::{rpienv, taskserver};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use chrono::Local;
 use image::{ImageFormat, imageops::FilterType};
 use log::{error, info, warn};
+use motion::{MotionConfig, MotionDetector};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeMap,
     io::Cursor,
     os::linux::fs::MetadataExt,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 use tokio::{
     fs::{self, File},
     io::AsyncWriteExt,
     process::Command,
+    select,
 };
 
 /// サムネイルファイル名のポストフィクス。
@@ -36,6 +45,10 @@ pub struct CameraConfig {
     /// raspistill によるリアル撮影ではなく、ダミー黒画像が撮れたことにする。
     /// Raspberry Pi 以外の環境でのデバッグ用。
     fake_camera: bool,
+    /// 自動撮影の起動スケジュール。
+    /// 書式は [crate::taskserver::schedule] を参照。
+    #[serde(default = "default_schedule")]
+    schedule: String,
     /// 撮影した画像を保存するディレクトリ。
     /// [Self::total_size_limit_mb] により自動で削除される。
     pic_history_dir: String,
@@ -45,6 +58,13 @@ pub struct CameraConfig {
     total_size_limit_mb: u32,
     /// 画像一覧ページの1ページ当たりの画像数。
     pub page_by: u32,
+    /// 動体検知モード。
+    #[serde(default)]
+    pub motion: MotionConfig,
+}
+
+fn default_schedule() -> String {
+    "0 */3 * * *".to_string()
 }
 
 impl Default for CameraConfig {
@@ -53,10 +73,38 @@ impl Default for CameraConfig {
             enabled: false,
             debug_exec_once: false,
             fake_camera: true,
+            schedule: default_schedule(),
             pic_history_dir: "./camera/history".to_string(),
             pic_archive_dir: "./camera/archive".to_string(),
             total_size_limit_mb: 1024,
             page_by: 100,
+            motion: Default::default(),
+        }
+    }
+}
+
+impl CameraConfig {
+    /// 撮影した画像の保存ディレクトリ。
+    pub(crate) fn pic_history_dir(&self) -> &str {
+        &self.pic_history_dir
+    }
+
+    /// 永久保存ディレクトリ。
+    pub(crate) fn pic_archive_dir(&self) -> &str {
+        &self.pic_archive_dir
+    }
+
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        report.check_schedule("camera.schedule", &self.schedule);
+        // 有効無効に関わらず起動時に作成される
+        report.check_dir_writable("camera.pic_history_dir", &self.pic_history_dir);
+        report.check_dir_writable("camera.pic_archive_dir", &self.pic_archive_dir);
+        if self.page_by == 0 {
+            report.add("camera.page_by", "must be greater than 0");
+        }
+        self.motion.validate(report);
+        if self.motion.enabled && !self.enabled {
+            report.add("camera.motion.enabled", "camera.enabled is required");
         }
     }
 }
@@ -91,8 +139,8 @@ pub struct Camera {
     ///
     /// web からも参照される。
     pub config: CameraConfig,
-    /// 自動撮影の時刻リスト。
-    wakeup_list: Vec<NaiveTime>,
+    /// 自動撮影の起動スケジュール。
+    schedule: Schedule,
     /// ストレージ上の画像リストデータ。
     storage: Storage,
 }
@@ -101,18 +149,19 @@ impl Camera {
     /// コンストラクタ。
     ///
     /// 設定データの読み込みと、ストレージの状態取得を行い画像リストを初期化する。
-    pub fn new(wakeup_list: Vec<NaiveTime>) -> Result<Self> {
+    pub fn new() -> Result<Self> {
         info!("[camera] initialize");
 
         let config = config::get(|cfg| cfg.camera.clone());
         ensure!(config.page_by > 0);
+        let schedule = config.schedule.parse().context("camera.schedule")?;
 
         let pic_history_list = init_pics(&config.pic_history_dir)?;
         let pic_archive_list = init_pics(&config.pic_archive_dir)?;
 
         Ok(Camera {
             config,
-            wakeup_list,
+            schedule,
             storage: Storage {
                 pic_history_list,
                 pic_archive_list,
@@ -139,7 +188,9 @@ impl Camera {
     ///
     /// * `img` - jpg ファイルのバイナリデータ。
     /// * `thumb` - サムネイル jpg ファイルのバイナリデータ。
-    pub async fn push_pic_history(&mut self, img: &[u8], thumb: &[u8]) -> Result<()> {
+    ///
+    /// 追加したエントリ名を返す。
+    pub async fn push_pic_history(&mut self, img: &[u8], thumb: &[u8]) -> Result<String> {
         // 現在時刻からキーを生成する
         // 重複するなら少し待ってからリトライする
         let mut now;
@@ -183,9 +234,14 @@ impl Camera {
             path_th,
             total_size,
         };
-        assert!(self.storage.pic_history_list.insert(dtstr, entry).is_none());
+        assert!(
+            self.storage
+                .pic_history_list
+                .insert(dtstr.clone(), entry)
+                .is_none()
+        );
 
-        Ok(())
+        Ok(dtstr)
     }
 
     /// ヒストリ内の `key` で指定したエントリを永続領域にコピーする。
@@ -289,16 +345,96 @@ impl Camera {
         Ok(())
     }
 
+    /// 撮影した画像をサムネイルと共に履歴に追加し、[PictureTaken] を発行する。
+    ///
+    /// 追加したエントリ名とサムネイルを返す。
+    async fn save_pic(ctrl: &Control, pic: &[u8]) -> Result<(String, Vec<u8>)> {
+        let thumb = create_thumbnail(pic)?;
+
+        let mut camera = ctrl.sysmods().camera.lock().await;
+        let name = camera.push_pic_history(pic, &thumb).await?;
+        camera.clean_pic_history().await?;
+        drop(camera);
+
+        ctrl.publish(PictureTaken { name: name.clone() });
+
+        Ok((name, thumb))
+    }
+
     /// 自動撮影タスク。
     async fn auto_task(ctrl: Control) -> Result<()> {
         let pic = take_a_pic(TakePicOption::new()).await?;
+        Self::save_pic(&ctrl, &pic).await?;
 
-        let thumb = create_thumbnail(&pic)?;
+        Ok(())
+    }
 
-        let mut camera = ctrl.sysmods().camera.lock().await;
-        camera.push_pic_history(&pic, &thumb).await?;
-        camera.clean_pic_history().await?;
-        drop(camera);
+    /// 動体検知タスク。
+    ///
+    /// [MotionConfig::interval_sec] ごとに低解像度のフレームを撮影して [MotionDetector] に渡す。
+    /// 動きを検出したらフル解像度で撮影して履歴に追加し、[MotionDetected] を発行する。
+    /// 撮影や保存のエラーはログを出して続行する。
+    async fn motion_task(ctrl: Control) -> Result<()> {
+        let config = ctrl.sysmods().camera.lock().await.config.motion.clone();
+        // 設定の検証は起動を妨げないので、ここでも 0 秒を避ける
+        let interval = Duration::from_secs(config.interval_sec.max(1));
+        let cooldown = Duration::from_secs(config.cooldown_sec);
+        let mut detector = MotionDetector::new(&config);
+        let mut last_saved: Option<Instant> = None;
+
+        loop {
+            match Self::motion_check(&config, &mut detector).await {
+                Ok(Some(percent)) => {
+                    if last_saved.is_some_and(|t| t.elapsed() < cooldown) {
+                        info!("[camera-motion] motion detected ({percent:.1}%), cooldown");
+                    } else {
+                        last_saved = Some(Instant::now());
+                        info!("[camera-motion] motion detected ({percent:.1}%)");
+                        if let Err(e) = Self::motion_save(&ctrl, &config, percent).await {
+                            error!("[camera-motion] save failed: {e:#}");
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => error!("[camera-motion] capture failed: {e:#}"),
+            }
+
+            select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = ctrl.wait_cancel_rx() => {
+                    info!("[camera-motion] task cancel");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// 低解像度のフレームを1枚撮影して `detector` に渡す。
+    async fn motion_check(
+        config: &MotionConfig,
+        detector: &mut MotionDetector,
+    ) -> Result<Option<f64>> {
+        let opt = TakePicOption::new()
+            .width(config.width)
+            .height(config.height)
+            .timeout_ms(MOTION_FRAME_TO_MS);
+        let frame = take_a_pic(opt).await?;
+        let frame = image::load_from_memory_with_format(&frame, ImageFormat::Jpeg)?;
+
+        Ok(detector.feed(&frame))
+    }
+
+    /// フル解像度で撮影して保存し、[MotionDetected] を発行する。
+    async fn motion_save(ctrl: &Control, config: &MotionConfig, percent: f64) -> Result<()> {
+        let pic = take_a_pic(TakePicOption::new()).await?;
+        let (name, thumb) = Self::save_pic(ctrl, &pic).await?;
+
+        ctrl.publish(MotionDetected {
+            text: format!("[Camera] Motion detected: {name} ({percent:.1}%)"),
+            name,
+            thumb,
+            discord_ch: config.discord_ch,
+        });
 
         Ok(())
     }
@@ -308,8 +444,12 @@ impl SystemModule for Camera {
     /// async 使用可能になってからの初期化。
     ///
     /// 設定有効ならば [Self::auto_task] を spawn する。
+    /// さらに動体検知モードも有効ならば [Self::motion_task] を spawn する。
     fn on_start(&mut self, ctrl: &Control) {
         info!("[camera] on_start");
+        if self.config.enabled && self.config.motion.enabled {
+            taskserver::spawn_oneshot_task(ctrl, "camera-motion", Camera::motion_task);
+        }
         if self.config.enabled {
             if self.config.debug_exec_once {
                 taskserver::spawn_oneshot_task(ctrl, "camera-auto", Camera::auto_task);
@@ -317,7 +457,7 @@ impl SystemModule for Camera {
                 taskserver::spawn_periodic_task(
                     ctrl,
                     "camera-auto",
-                    &self.wakeup_list,
+                    &self.schedule,
                     Camera::auto_task,
                 );
             }
@@ -404,6 +544,8 @@ const PIC_DEF_TO_MS: u32 = 1000;
 const THUMB_W: u32 = 128;
 /// サムネイルの縦サイズ。
 const THUMB_H: u32 = 96;
+/// 動体検知用フレームの撮影時間(ms)。
+const MOTION_FRAME_TO_MS: u32 = 100;
 
 /// 写真撮影オプション。
 pub struct TakePicOption {
@@ -432,12 +574,10 @@ impl TakePicOption {
             timeout_ms: PIC_DEF_TO_MS,
         }
     }
-    #[allow(dead_code)]
     pub fn width(mut self, w: u32) -> Self {
         self.w = Some(w);
         self
     }
-    #[allow(dead_code)]
     pub fn height(mut self, h: u32) -> Self {
         self.h = Some(h);
         self
@@ -448,7 +588,6 @@ impl TakePicOption {
         self.q = q;
         self
     }
-    #[allow(dead_code)]
     pub fn timeout_ms(mut self, timeout_ms: u32) -> Self {
         self.timeout_ms = timeout_ms;
         self
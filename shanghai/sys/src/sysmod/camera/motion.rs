@@ -0,0 +1,453 @@
+//! 動体検知。
+//!
+//! 低解像度のフレームをグレースケール化してぼかし、
+//! 移動平均で更新する背景との差分が [MotionConfig::threshold] を超える画素の割合が
+//! [MotionConfig::min_area_percent] 以上ならば動きありとする。
+//! [MotionConfig::masks] の領域は判定に使わない。
+//! `camera.enabled` も有効にする必要がある。
+//!
+//! ```toml
+//! [camera.motion]
+//! enabled = true
+//! interval_sec = 5
+//! threshold = 25
+//! min_area_percent = 1.0
+//! discord_ch = 123456789
+//!
+//! # 右上 1/4 (時計等) を無視する
+//! [[camera.motion.masks]]
+//! x = 0.75
+//! y = 0.0
+//! w = 0.25
+//! h = 0.25
+//! ```
+
+use crate::config::ValidationReport;
+use image::{DynamicImage, GrayImage, imageops};
+use serde::{Deserialize, Serialize};
+
+/// 動体検知設定データ。toml 設定に対応する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MotionConfig {
+    /// 動体検知モードを有効化する。`camera.enabled` も有効にすること。
+    pub enabled: bool,
+    /// フレームを撮影する間隔 (秒)。
+    #[serde(default = "default_interval_sec")]
+    pub interval_sec: u64,
+    /// フレームの横サイズ。
+    #[serde(default = "default_width")]
+    pub width: u32,
+    /// フレームの縦サイズ。
+    #[serde(default = "default_height")]
+    pub height: u32,
+    /// 背景との輝度差がこれを超える画素を変化ありとする。
+    #[serde(default = "default_threshold")]
+    pub threshold: u8,
+    /// 変化ありの画素の割合 (%) がこれ以上ならば動きありとする。
+    #[serde(default = "default_min_area_percent")]
+    pub min_area_percent: f64,
+    /// ノイズ除去のためのガウスぼかしの標準偏差。0 ならばぼかさない。
+    #[serde(default = "default_blur_sigma")]
+    pub blur_sigma: f32,
+    /// 背景の更新率。1 フレームごとに背景をこの割合だけフレームに近づける。
+    #[serde(default = "default_background_alpha")]
+    pub background_alpha: f32,
+    /// 保存後、次に保存するまでの最小間隔 (秒)。
+    #[serde(default = "default_cooldown_sec")]
+    pub cooldown_sec: u64,
+    /// 通知先 Discord チャネル ID。0 ならば通知しない。
+    #[serde(default)]
+    pub discord_ch: u64,
+    /// 判定に使わない領域のリスト。
+    #[serde(default)]
+    pub masks: Vec<MaskRect>,
+}
+
+fn default_interval_sec() -> u64 {
+    5
+}
+
+fn default_width() -> u32 {
+    320
+}
+
+fn default_height() -> u32 {
+    240
+}
+
+fn default_threshold() -> u8 {
+    25
+}
+
+fn default_min_area_percent() -> f64 {
+    1.0
+}
+
+fn default_blur_sigma() -> f32 {
+    2.0
+}
+
+fn default_background_alpha() -> f32 {
+    0.05
+}
+
+fn default_cooldown_sec() -> u64 {
+    60
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_sec: default_interval_sec(),
+            width: default_width(),
+            height: default_height(),
+            threshold: default_threshold(),
+            min_area_percent: default_min_area_percent(),
+            blur_sigma: default_blur_sigma(),
+            background_alpha: default_background_alpha(),
+            cooldown_sec: default_cooldown_sec(),
+            discord_ch: 0,
+            masks: Vec::new(),
+        }
+    }
+}
+
+impl MotionConfig {
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        if self.interval_sec == 0 {
+            report.add("camera.motion.interval_sec", "must be 1 or more");
+        }
+        if self.width == 0 {
+            report.add("camera.motion.width", "must be 1 or more");
+        }
+        if self.height == 0 {
+            report.add("camera.motion.height", "must be 1 or more");
+        }
+        if !(self.min_area_percent > 0.0 && self.min_area_percent <= 100.0) {
+            report.add("camera.motion.min_area_percent", "must be in (0, 100]");
+        }
+        if !(0.0..).contains(&self.blur_sigma) {
+            report.add("camera.motion.blur_sigma", "must be 0 or more");
+        }
+        if !(self.background_alpha > 0.0 && self.background_alpha <= 1.0) {
+            report.add("camera.motion.background_alpha", "must be in (0, 1]");
+        }
+        for (i, mask) in self.masks.iter().enumerate() {
+            if !mask.is_valid() {
+                report.add(
+                    &format!("camera.motion.masks[{i}]"),
+                    "must be a non-empty rectangle within [0, 1]",
+                );
+            }
+        }
+    }
+}
+
+/// 判定に使わない矩形領域。toml 設定に対応する。
+///
+/// フレームの解像度によらないよう、座標とサイズは幅と高さに対する割合 (0.0 - 1.0) で指定する。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MaskRect {
+    /// 左端。
+    pub x: f64,
+    /// 上端。
+    pub y: f64,
+    /// 幅。
+    pub w: f64,
+    /// 高さ。
+    pub h: f64,
+}
+
+impl MaskRect {
+    fn is_valid(&self) -> bool {
+        let range = 0.0..=1.0;
+        range.contains(&self.x)
+            && range.contains(&self.y)
+            && self.w > 0.0
+            && self.h > 0.0
+            && self.x + self.w <= 1.0
+            && self.y + self.h <= 1.0
+    }
+
+    /// 画素の中心が矩形内にあれば true を返す。
+    fn contains(&self, px: u32, py: u32, width: u32, height: u32) -> bool {
+        let fx = (px as f64 + 0.5) / width as f64;
+        let fy = (py as f64 + 0.5) / height as f64;
+
+        (self.x..self.x + self.w).contains(&fx) && (self.y..self.y + self.h).contains(&fy)
+    }
+}
+
+/// 背景モデル。
+struct Background {
+    width: u32,
+    height: u32,
+    /// 画素ごとの輝度の移動平均。
+    pixels: Vec<f32>,
+    /// 画素ごとの判定に使うかどうか。
+    active: Vec<bool>,
+    /// [Self::active] が true の画素数。
+    active_count: usize,
+}
+
+/// 動体検知器。
+///
+/// フレームを順に [Self::feed] する。撮影とは独立しているため、合成画像でテストできる。
+pub struct MotionDetector {
+    threshold: f32,
+    min_area_percent: f64,
+    blur_sigma: f32,
+    background_alpha: f32,
+    masks: Vec<MaskRect>,
+    /// 最初のフレームまたはサイズが変わるまでは None。
+    background: Option<Background>,
+}
+
+impl MotionDetector {
+    pub fn new(config: &MotionConfig) -> Self {
+        Self {
+            threshold: config.threshold as f32,
+            min_area_percent: config.min_area_percent,
+            blur_sigma: config.blur_sigma,
+            background_alpha: config.background_alpha,
+            masks: config.masks.clone(),
+            background: None,
+        }
+    }
+
+    /// フレームを背景と比較し、背景を更新する。
+    ///
+    /// 動きありならば変化ありの画素の割合 (%) を返す。
+    /// 最初のフレームとサイズが変わったフレームは背景の初期化にのみ使う。
+    pub fn feed(&mut self, frame: &DynamicImage) -> Option<f64> {
+        let gray = self.preprocess(frame);
+        let (width, height) = gray.dimensions();
+
+        let Some(bg) = self
+            .background
+            .as_mut()
+            .filter(|bg| bg.width == width && bg.height == height)
+        else {
+            self.background = Some(self.init_background(&gray));
+            return None;
+        };
+
+        let alpha = self.background_alpha;
+        let mut changed = 0;
+        for ((bg_pix, &active), pix) in bg.pixels.iter_mut().zip(&bg.active).zip(gray.pixels()) {
+            let value = pix.0[0] as f32;
+            if active && (value - *bg_pix).abs() > self.threshold {
+                changed += 1;
+            }
+            *bg_pix += (value - *bg_pix) * alpha;
+        }
+        if bg.active_count == 0 {
+            return None;
+        }
+
+        let percent = 100.0 * changed as f64 / bg.active_count as f64;
+        (percent >= self.min_area_percent).then_some(percent)
+    }
+
+    /// グレースケール化してぼかす。
+    fn preprocess(&self, frame: &DynamicImage) -> GrayImage {
+        let gray = frame.to_luma8();
+        if self.blur_sigma > 0.0 {
+            imageops::blur(&gray, self.blur_sigma)
+        } else {
+            gray
+        }
+    }
+
+    fn init_background(&self, gray: &GrayImage) -> Background {
+        let (width, height) = gray.dimensions();
+        let pixels = gray.pixels().map(|pix| pix.0[0] as f32).collect();
+        let active: Vec<bool> = (0..height)
+            .flat_map(|py| (0..width).map(move |px| (px, py)))
+            .map(|(px, py)| {
+                !self
+                    .masks
+                    .iter()
+                    .any(|mask| mask.contains(px, py, width, height))
+            })
+            .collect();
+        let active_count = active.iter().filter(|&&a| a).count();
+
+        Background {
+            width,
+            height,
+            pixels,
+            active,
+            active_count,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    const W: u32 = 64;
+    const H: u32 = 48;
+
+    /// 一様な輝度 `base` に、`rect` (x, y, w, h) の範囲だけ輝度 `value` の物体を置いたフレーム。
+    fn frame(base: u8, rect: Option<(u32, u32, u32, u32, u8)>) -> DynamicImage {
+        let img = GrayImage::from_fn(W, H, |x, y| match rect {
+            Some((rx, ry, rw, rh, value))
+                if (rx..rx + rw).contains(&x) && (ry..ry + rh).contains(&y) =>
+            {
+                Luma([value])
+            }
+            _ => Luma([base]),
+        });
+        DynamicImage::ImageLuma8(img)
+    }
+
+    /// 画素ごとに決まった小さな揺らぎを持つフレーム。
+    fn noisy_frame(base: u8, seed: u32) -> DynamicImage {
+        let img = GrayImage::from_fn(W, H, |x, y| {
+            let noise = (x * 7 + y * 13 + seed * 31) % 9;
+            Luma([base + noise as u8])
+        });
+        DynamicImage::ImageLuma8(img)
+    }
+
+    fn detector(config: MotionConfig) -> MotionDetector {
+        let mut detector = MotionDetector::new(&config);
+        assert_eq!(detector.feed(&frame(50, None)), None);
+        detector
+    }
+
+    #[test]
+    fn still() {
+        let mut detector = detector(MotionConfig::default());
+        for _ in 0..10 {
+            assert_eq!(detector.feed(&frame(50, None)), None);
+        }
+    }
+
+    #[test]
+    fn object() {
+        let mut detector = detector(MotionConfig::default());
+        let percent = detector.feed(&frame(50, Some((10, 10, 16, 16, 200))));
+        // ぼかしの分だけ面積は前後する
+        let percent = percent.unwrap();
+        assert!((5.0..15.0).contains(&percent), "{percent}");
+    }
+
+    #[test]
+    fn small_object() {
+        let config = MotionConfig {
+            min_area_percent: 5.0,
+            ..Default::default()
+        };
+        let mut detector = detector(config);
+        assert_eq!(detector.feed(&frame(50, Some((10, 10, 4, 4, 200)))), None);
+    }
+
+    #[test]
+    fn noise() {
+        let config = MotionConfig {
+            blur_sigma: 0.0,
+            ..Default::default()
+        };
+        let mut detector = MotionDetector::new(&config);
+        assert_eq!(detector.feed(&noisy_frame(50, 0)), None);
+        for seed in 1..10 {
+            assert_eq!(detector.feed(&noisy_frame(50, seed)), None);
+        }
+    }
+
+    #[test]
+    fn mask() {
+        let config = MotionConfig {
+            // 左上 1/2 x 1/2
+            masks: vec![MaskRect {
+                x: 0.0,
+                y: 0.0,
+                w: 0.5,
+                h: 0.5,
+            }],
+            blur_sigma: 0.0,
+            ..Default::default()
+        };
+        let mut detector = detector(config);
+        assert_eq!(detector.feed(&frame(50, Some((4, 4, 16, 16, 200)))), None);
+
+        // マスク外は検出する。割合はマスク外の画素数に対して計算する
+        let percent = detector.feed(&frame(50, Some((40, 30, 8, 6, 200))));
+        assert_eq!(percent, Some(100.0 * 48.0 / (W * H * 3 / 4) as f64));
+    }
+
+    #[test]
+    fn background_follows() {
+        let config = MotionConfig {
+            background_alpha: 0.5,
+            ..Default::default()
+        };
+        let mut detector = detector(config);
+
+        // 緩やかな明るさの変化は検出しない
+        for base in 51..=70 {
+            assert_eq!(detector.feed(&frame(base, None)), None, "{base}");
+        }
+
+        // 置かれたままの物体は背景に取り込まれる
+        let moved = frame(70, Some((10, 10, 16, 16, 200)));
+        assert!(detector.feed(&moved).is_some());
+        for _ in 0..10 {
+            detector.feed(&moved);
+        }
+        assert_eq!(detector.feed(&moved), None);
+    }
+
+    #[test]
+    fn resize() {
+        let mut detector = detector(MotionConfig::default());
+        let other = DynamicImage::ImageLuma8(GrayImage::from_pixel(32, 24, Luma([200])));
+        // サイズが変わったら背景を作り直す
+        assert_eq!(detector.feed(&other), None);
+        assert_eq!(detector.feed(&other), None);
+    }
+
+    #[test]
+    fn validate() {
+        let mut report = ValidationReport::default();
+        MotionConfig::default().validate(&mut report);
+        assert!(report.is_empty());
+
+        let config = MotionConfig {
+            interval_sec: 0,
+            min_area_percent: 0.0,
+            background_alpha: 1.5,
+            masks: vec![
+                MaskRect {
+                    x: 0.5,
+                    y: 0.5,
+                    w: 0.5,
+                    h: 0.5,
+                },
+                MaskRect {
+                    x: 0.5,
+                    y: 0.0,
+                    w: 0.6,
+                    h: 0.5,
+                },
+            ],
+            ..Default::default()
+        };
+        let mut report = ValidationReport::default();
+        config.validate(&mut report);
+        assert_eq!(
+            report.paths(),
+            [
+                "camera.motion.interval_sec",
+                "camera.motion.min_area_percent",
+                "camera.motion.background_alpha",
+                "camera.motion.masks[1]",
+            ]
+        );
+    }
+}
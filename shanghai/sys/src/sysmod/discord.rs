@@ -2,24 +2,31 @@
 
 use super::SystemModule;
 
+use crate::config::{self, ValidationReport};
+use crate::dryrun;
 use crate::sysmod::camera::{self, TakePicOption};
-use crate::sysmod::openai::chat_history::ChatHistory;
+use crate::sysmod::health::Health;
+use crate::sysmod::health::chart::ChartRange;
+use crate::sysmod::openai::chat_history::{ChatHistory, ChatHistorySnapshot};
 use crate::sysmod::openai::function::FUNCTION_TOKEN;
 use crate::sysmod::openai::{self, OpenAi, OpenAiErrorKind, SearchContextSize, Tool, UserLocation};
 use crate::sysmod::openai::{Role, function::FunctionTable};
 use crate::taskserver;
-use crate::{config, taskserver::Control};
+use crate::taskserver::event::{BootCompleted, GitPushed, HealthReported, MotionDetected, Notify};
+use crate::taskserver::registry::TaskResult;
+use crate::taskserver::schedule::Schedule;
+use crate::{state, taskserver::Control};
 use utils::netutil;
 use utils::playtools::dice::{self};
 
 use anyhow::Context as _;
 use anyhow::{Result, anyhow, bail, ensure};
-use chrono::{NaiveTime, Utc};
+use chrono::Utc;
 use log::{error, info, warn};
 use poise::{CreateReply, FrameworkContext, serenity_prelude as serenity};
 use serde::{Deserialize, Serialize};
 use serenity::Client;
-use serenity::all::{CreateAttachment, FullEvent};
+use serenity::all::{CreateAttachment, CreateMessage, FullEvent};
 use serenity::http::MessagePagination;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
@@ -44,12 +51,19 @@ const MSG_MAX_LEN: usize = 2000;
 pub struct DiscordConfig {
     /// 機能を有効化するなら true。
     enabled: bool,
+    /// 自動削除タスクの起動スケジュール。
+    /// 書式は [crate::taskserver::schedule] を参照。
+    #[serde(default = "default_autodel_schedule")]
+    autodel_schedule: String,
     /// アクセストークン。Developer Portal で入手できる。
     token: String,
     /// メッセージの発言先チャネル。
     /// Discord の詳細設定で開発者モードを有効にして、チャネルを右クリックで
     /// ID をコピーできる。
     notif_channel: u64,
+    /// 定期ヘルスレポートを通知チャネルに投稿する。
+    #[serde(default)]
+    health_report: bool,
     /// 自動削除機能の対象とするチャネル ID のリスト。
     auto_del_chs: Vec<u64>,
     /// オーナーのユーザ ID。
@@ -63,12 +77,18 @@ pub struct DiscordConfig {
     prompt: DiscordPrompt,
 }
 
+fn default_autodel_schedule() -> String {
+    "*/10 * * * *".to_string()
+}
+
 impl Default for DiscordConfig {
     fn default() -> Self {
         Self {
             enabled: false,
+            autodel_schedule: default_autodel_schedule(),
             token: "".to_string(),
             notif_channel: 0,
+            health_report: false,
             auto_del_chs: Default::default(),
             owner_ids: Default::default(),
             perm_err_msg: "バカジャネーノ".to_string(),
@@ -77,6 +97,23 @@ impl Default for DiscordConfig {
     }
 }
 
+impl DiscordConfig {
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        report.check_schedule("discord.autodel_schedule", &self.autodel_schedule);
+        if self.enabled {
+            report.require("discord.token", &self.token, "discord.enabled = true");
+        }
+        for (i, &ch) in self.auto_del_chs.iter().enumerate() {
+            if ch == 0 {
+                report.add(
+                    &format!("discord.auto_del_chs[{i}]"),
+                    "channel ID must not be 0",
+                );
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DiscordPrompt {
     /// 最初に一度だけ与えられるシステムメッセージ。
@@ -104,16 +141,16 @@ impl Default for DiscordPrompt {
 pub struct Discord {
     /// 設定データ。
     config: DiscordConfig,
-    /// 定期実行の時刻リスト。
-    wakeup_list: Vec<NaiveTime>,
+    /// 自動削除タスクの起動スケジュール。
+    autodel_schedule: Schedule,
     /// 現在有効な Discord Client コンテキスト。
     ///
     /// 起動直後は None で、[event_handler] イベントの度に置き換わる。
     ctx: Option<Context>,
-    /// [Self::ctx] が None の間に発言しようとしたメッセージのキュー。
+    /// [Self::ctx] が None の間に発言しようとした (チャネル ID, メッセージ) のキュー。
     ///
     /// Some になるタイミングで全て送信する。
-    postponed_msgs: Vec<String>,
+    postponed_msgs: Vec<(u64, String)>,
 
     /// 自動削除機能の設定データ。
     auto_del_config: BTreeMap<ChannelId, AutoDeleteConfig>,
@@ -122,12 +159,25 @@ pub struct Discord {
     chat_history: Option<ChatHistory>,
     /// [Self::chat_history] の有効期限。
     chat_timeout: Option<Instant>,
+    /// 前回終了時に保存された会話履歴。
+    ///
+    /// [Self::chat_history] の初期化時に復元される。
+    saved_history: Option<ChatHistorySnapshot>,
     /// OpenAI function 機能テーブル
     func_table: Option<FunctionTable<()>>,
 }
 
+/// 再起動をまたいで保持する状態。[state] に保存する。
+#[derive(Default, Serialize, Deserialize)]
+struct DiscordState {
+    /// チャネル ID をキーとする自動削除設定。
+    auto_del_config: BTreeMap<u64, AutoDeleteConfig>,
+    chat_history: Option<ChatHistorySnapshot>,
+    chat_timeout: Option<chrono::DateTime<chrono::Local>>,
+}
+
 /// 自動削除設定。チャネルごとに保持される。
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct AutoDeleteConfig {
     /// 残す数。0 は無効。
     keep_count: u32,
@@ -159,31 +209,41 @@ impl Discord {
     ///
     /// 設定データの読み込みのみ行い、実際の初期化は async が有効になる
     /// [discord_main] で行う。
-    pub fn new(wakeup_list: Vec<NaiveTime>) -> Result<Self> {
+    pub fn new() -> Result<Self> {
         info!("[discord] initialize");
 
         let config = config::get(|cfg| cfg.discord.clone());
+        let autodel_schedule = config
+            .autodel_schedule
+            .parse()
+            .context("discord.autodel_schedule")?;
+
+        let state: DiscordState = state::load("discord").unwrap_or_default();
 
+        // 保存されていた設定は、現在も対象のチャネルのものだけ復元する
         let mut auto_del_config = BTreeMap::new();
         for &ch in &config.auto_del_chs {
             ensure!(ch != 0);
-            auto_del_config.insert(
-                ChannelId::new(ch),
-                AutoDeleteConfig {
+            let ch_config = state
+                .auto_del_config
+                .get(&ch)
+                .copied()
+                .unwrap_or(AutoDeleteConfig {
                     keep_count: 0,
                     keep_dur_min: 0,
-                },
-            );
+                });
+            auto_del_config.insert(ChannelId::new(ch), ch_config);
         }
 
         Ok(Self {
             config,
-            wakeup_list,
+            autodel_schedule,
             ctx: None,
             postponed_msgs: Default::default(),
             auto_del_config,
             chat_history: None,
-            chat_timeout: None,
+            chat_timeout: state.chat_timeout.map(state::datetime_to_instant),
+            saved_history: state.chat_history,
             func_table: None,
         })
     }
@@ -216,6 +276,11 @@ impl Discord {
         info!("[discord] {reserved:6} reserved");
         info!("[discord] {:6} chat history", chat_history.usage().1);
 
+        if let Some(saved) = self.saved_history.take() {
+            chat_history.restore(saved);
+            info!("[discord] {} chat history restored", chat_history.len());
+        }
+
         let mut func_table = FunctionTable::new(Arc::clone(ctrl), Some("discord"));
         func_table.register_basic_functions();
 
@@ -241,32 +306,102 @@ impl Discord {
        }
     */
 
-    /// 発言を投稿する。
+    /// 通知チャネルに発言を投稿する。
     ///
     /// 接続前の場合、接続後まで遅延する。
     pub async fn say(&mut self, msg: &str) -> Result<()> {
+        self.say_to(self.config.notif_channel, msg).await
+    }
+
+    /// チャネル `ch` に発言を投稿する。0 ならば投稿しない。
+    ///
+    /// 接続前の場合、接続後まで遅延する。
+    pub async fn say_to(&mut self, ch: u64, msg: &str) -> Result<()> {
         if !self.config.enabled {
             info!("[discord] disabled - msg: {msg}");
             return Ok(());
         }
-        if self.config.notif_channel == 0 {
+        if ch == 0 {
             info!("[discord] notification disabled - msg: {msg}");
             return Ok(());
         }
+        if dryrun::is_enabled() {
+            dryrun::write("discord", "POST", &channel_msg_url(ch), &msg);
+            return Ok(());
+        }
         if self.ctx.is_none() {
             info!("[discord] not ready, postponed - msg: {msg}");
-            self.postponed_msgs.push(msg.to_string());
+            self.postponed_msgs.push((ch, msg.to_string()));
             return Ok(());
         }
 
         info!("[discord] say msg: {msg}");
-        let ch = ChannelId::new(self.config.notif_channel);
+        let ch = ChannelId::new(ch);
         let ctx = self.ctx.as_ref().unwrap();
         ch.say(ctx, msg).await?;
 
         Ok(())
     }
 
+    /// チャネル `ch` にファイルを添付して発言を投稿する。0 ならば投稿しない。
+    ///
+    /// 接続前の場合、添付ファイルは捨てて発言のみ接続後まで遅延する。
+    pub async fn say_with_file(
+        &mut self,
+        ch: u64,
+        msg: &str,
+        file_name: &str,
+        bin: Vec<u8>,
+    ) -> Result<()> {
+        if self.config.enabled && ch != 0 && dryrun::is_enabled() {
+            let payload = serde_json::json!({
+                "content": msg,
+                "attachment": { "filename": file_name, "size": bin.len() },
+            });
+            dryrun::write("discord", "POST", &channel_msg_url(ch), &payload);
+            return Ok(());
+        }
+        if !self.config.enabled || ch == 0 || self.ctx.is_none() {
+            return self.say_to(ch, msg).await;
+        }
+
+        info!("[discord] say msg: {msg} (attachment: {file_name})");
+        let ch = ChannelId::new(ch);
+        let ctx = self.ctx.as_ref().unwrap();
+        let attach = CreateAttachment::bytes(bin, file_name);
+        ch.send_message(ctx, CreateMessage::new().content(msg).add_file(attach))
+            .await?;
+
+        Ok(())
+    }
+
+    /// Gateway に接続せず、REST API のみで通知チャネルに発言する。
+    ///
+    /// `shanghai discord-say` サブコマンド用。
+    pub async fn say_oneshot(&self, msg: &str) -> Result<()> {
+        ensure!(self.config.enabled, "discord is disabled");
+        ensure!(
+            self.config.notif_channel != 0,
+            "discord.notif_channel is not set"
+        );
+        if dryrun::is_enabled() {
+            dryrun::write(
+                "discord",
+                "POST",
+                &channel_msg_url(self.config.notif_channel),
+                &msg,
+            );
+            return Ok(());
+        }
+
+        info!("[discord] say msg: {msg}");
+        let http = serenity::http::Http::new(&self.config.token);
+        let ch = ChannelId::new(self.config.notif_channel);
+        ch.say(&http, msg).await?;
+
+        Ok(())
+    }
+
     /// [Self::chat_history] にタイムアウトを適用する。
     fn check_history_timeout(&mut self) {
         let now = Instant::now();
@@ -280,15 +415,78 @@ impl Discord {
     }
 }
 
+/// [BootCompleted] イベントを受けてブートメッセージを通知する。
+async fn on_boot_completed(ctrl: Control, _event: BootCompleted) -> Result<()> {
+    let build_info = verinfo::version_info();
+    let now = chrono::Local::now();
+    let now = now.format("%F %T %:z");
+    let msg = format!("[{now}] Boot...\n{build_info}");
+
+    ctrl.sysmods().discord.lock().await.say(&msg).await
+}
+
+/// チャネルへの投稿 API の URL。ドライランのログ用。
+fn channel_msg_url(ch: u64) -> String {
+    format!("https://discord.com/api/v10/channels/{ch}/messages")
+}
+
+/// [Notify] イベントを受けて通知する。
+async fn on_notify(ctrl: Control, event: Notify) -> Result<()> {
+    ctrl.sysmods()
+        .discord
+        .lock()
+        .await
+        .say_to(event.discord_ch, &event.text)
+        .await
+}
+
+/// [MotionDetected] イベントを受けてサムネイル付きで通知する。
+async fn on_motion_detected(ctrl: Control, event: MotionDetected) -> Result<()> {
+    let file_name = format!("{}_thumb.jpg", event.name);
+    ctrl.sysmods()
+        .discord
+        .lock()
+        .await
+        .say_with_file(event.discord_ch, &event.text, &file_name, event.thumb)
+        .await
+}
+
+/// [HealthReported] イベントを受けて、有効ならば通知チャネルに投稿する。
+async fn on_health_reported(ctrl: Control, event: HealthReported) -> Result<()> {
+    let mut discord = ctrl.sysmods().discord.lock().await;
+    if !discord.config.health_report {
+        return Ok(());
+    }
+    let ch = discord.config.notif_channel;
+    match event.chart {
+        Some(chart) => {
+            discord
+                .say_with_file(ch, &event.text, "health.png", chart)
+                .await
+        }
+        None => discord.say_to(ch, &event.text).await,
+    }
+}
+
+/// [GitPushed] イベントを受けて通知する。
+async fn on_git_pushed(ctrl: Control, event: GitPushed) -> Result<()> {
+    ctrl.sysmods()
+        .discord
+        .lock()
+        .await
+        .say(&event.message())
+        .await
+}
+
 /// システムを初期化し開始する。
 ///
 /// [Discord::on_start] から spawn される。
 async fn discord_main(ctrl: Control) -> Result<()> {
-    let (config, wakeup_list) = {
+    let (config, schedule) = {
         let mut discord = ctrl.sysmods().discord.lock().await;
         discord.init_openai(&ctrl).await;
 
-        (discord.config.clone(), discord.wakeup_list.clone())
+        (discord.config.clone(), discord.autodel_schedule.clone())
     };
 
     // owner_ids を HashSet に変換 (0 は panic するので禁止)
@@ -334,6 +532,7 @@ async fn discord_main(ctrl: Control) -> Result<()> {
                 info!("[discord] register commands...");
                 poise::builtins::register_globally(ctx, &framework.options().commands).await?;
                 info!("[discord] register commands OK");
+                ctrl_for_setup.report_ready("discord");
 
                 // construct user data here (invoked when bot connects to Discord)
                 Ok(PoiseData {
@@ -370,7 +569,7 @@ async fn discord_main(ctrl: Control) -> Result<()> {
     });
 
     // 定期チェックタスクを立ち上げる
-    taskserver::spawn_periodic_task(&ctrl, "discord-periodic", &wakeup_list, periodic_main);
+    taskserver::spawn_periodic_task(&ctrl, "discord-periodic", &schedule, periodic_main);
 
     // システムスタート
     client.start().await?;
@@ -590,6 +789,8 @@ fn command_list() -> Vec<poise::Command<PoiseData, PoiseError>> {
     vec![
         help(),
         sysinfo(),
+        health(),
+        tasks(),
         autodel(),
         coin(),
         dice(),
@@ -641,6 +842,91 @@ async fn sysinfo(ctx: PoiseContext<'_>) -> Result<(), PoiseError> {
     Ok(())
 }
 
+#[poise::command(
+    slash_command,
+    prefix_command,
+    category = "General",
+    subcommands("health_chart")
+)]
+async fn health(_ctx: PoiseContext<'_>) -> Result<(), PoiseError> {
+    // 親コマンドはスラッシュコマンドでは使用不可
+    Ok(())
+}
+
+#[derive(Default, poise::ChoiceParameter)]
+enum HealthChartRange {
+    #[default]
+    #[name = "24h"]
+    Day,
+    #[name = "7d"]
+    Week,
+}
+
+/// Show charts of CPU, memory, disk and temperature history.
+#[poise::command(slash_command, prefix_command, category = "General", rename = "chart")]
+async fn health_chart(
+    ctx: PoiseContext<'_>,
+    #[description = "Time range (default=24h)"] range: Option<HealthChartRange>,
+) -> Result<(), PoiseError> {
+    let range = match range.unwrap_or_default() {
+        HealthChartRange::Day => ChartRange::Day,
+        HealthChartRange::Week => ChartRange::Week,
+    };
+    ctx.defer().await?;
+
+    match Health::render_chart(&ctx.data().ctrl, range).await {
+        Ok(png) => {
+            let name = format!("health_{}.png", range.name());
+            let attach = CreateAttachment::bytes(png, name.clone());
+            ctx.send(CreateReply::default().content(name).attachment(attach))
+                .await?;
+        }
+        Err(e) => {
+            warn!("[discord] health chart: {e:#}");
+            ctx.reply(format!("{e:#}")).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Show the status of all tasks.
+#[poise::command(slash_command, prefix_command, category = "General", owners_only)]
+async fn tasks(ctx: PoiseContext<'_>) -> Result<(), PoiseError> {
+    let list = ctx.data().ctrl.task_list();
+
+    let mut text = String::new();
+    for entry in list {
+        let result = match &entry.last_result {
+            None => "-".to_string(),
+            Some(TaskResult::Success) => "OK".to_string(),
+            Some(TaskResult::Error(msg)) => format!("Error: {msg}"),
+        };
+        text.push_str(&format!(
+            "{} ({}) runs={} fails={} running={}\n",
+            entry.name, entry.kind, entry.run_count, entry.failure_count, entry.running
+        ));
+        if let Some(next) = entry.next_wakeup {
+            text.push_str(&format!("  next: {}\n", next.format("%F %T")));
+        }
+        if let Some(start) = entry.last_start {
+            let duration = entry
+                .last_duration
+                .map_or("-".to_string(), |d| format!("{:.3}s", d.as_secs_f64()));
+            text.push_str(&format!(
+                "  last: {} ({duration}) {result}\n",
+                start.format("%F %T")
+            ));
+        }
+    }
+    if text.is_empty() {
+        text.push_str("No tasks");
+    }
+    reply_long_mdquote(&ctx, &text).await?;
+
+    Ok(())
+}
+
 const AUTODEL_INVALID_CH_MSG: &str = "Auto delete feature is not enabled for this channel.
 Please contact my owner.";
 
@@ -1214,12 +1500,42 @@ impl SystemModule for Discord {
     /// async 使用可能になってからの初期化。
     ///
     /// 設定有効ならば [discord_main] を spawn する。
+    /// ドライランモードでは Gateway に接続しない。
     fn on_start(&mut self, ctrl: &Control) {
         info!("[discord] on_start");
-        if self.config.enabled {
+        taskserver::spawn_event_task(ctrl, "discord-boot-msg", on_boot_completed);
+        taskserver::spawn_event_task(ctrl, "discord-git-pushed", on_git_pushed);
+        taskserver::spawn_event_task(ctrl, "discord-notify", on_notify);
+        taskserver::spawn_event_task(ctrl, "discord-health-report", on_health_reported);
+        taskserver::spawn_event_task(ctrl, "discord-motion", on_motion_detected);
+        if self.config.enabled && dryrun::is_enabled() {
+            info!("[discord] dry-run: gateway connection skipped");
+        } else if self.config.enabled {
+            ctrl.expect_ready("discord");
             taskserver::spawn_oneshot_task(ctrl, "discord", discord_main);
         }
     }
+
+    fn on_stop(&mut self, _ctrl: &Control) {
+        info!("[discord] on_stop");
+        // 未初期化ならば保存されていたものをそのまま引き継ぐ
+        let chat_history = match &self.chat_history {
+            Some(chat_history) => Some(chat_history.snapshot()),
+            None => self.saved_history.clone(),
+        };
+        let state = DiscordState {
+            auto_del_config: self
+                .auto_del_config
+                .iter()
+                .map(|(ch, config)| (ch.get(), *config))
+                .collect(),
+            chat_history,
+            chat_timeout: self.chat_timeout.map(state::instant_to_datetime),
+        };
+        if let Err(e) = state::save("discord", &state) {
+            error!("[discord] save state failed: {e:#}");
+        }
+    }
 }
 
 /// Poise イベントハンドラ。
@@ -1323,13 +1639,12 @@ async fn event_handler(
                 "[discord] send postponed msgs ({})",
                 discord.postponed_msgs.len()
             );
-            for msg in &discord.postponed_msgs {
-                let ch = discord.config.notif_channel;
-                // notif_channel が有効の場合しかキューされない
-                assert_ne!(0, ch);
+            for (ch, msg) in &discord.postponed_msgs {
+                // 0 の場合はキューされない
+                assert_ne!(0, *ch);
 
                 info!("[discord] say msg: {msg}");
-                let ch = ChannelId::new(ch);
+                let ch = ChannelId::new(*ch);
                 if let Err(why) = ch.say(&ctx, msg).await {
                     error!("{why:#?}");
                 }
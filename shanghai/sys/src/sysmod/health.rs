@@ -1,15 +1,43 @@
 //! 定期ヘルスチェック機能。
+//!
+//! 測定データは [history] によりファイルに保存され、起動時に復元される。
+//! 測定のたびに [alert] のルールを評価し、発生と回復を [Notify] として発行する。
+//! [HealthConfig::font_file] を設定すると、履歴の [chart] を描画できる。
+//!
+//! ディスクは [HealthConfig::mounts] の各マウントポイントに加え、
+//! アプリが書き込むディレクトリ (カメラ、アップロード、OpenAI、ログ) の使用量を [disk] で測定する。
+//! ディレクトリの走査は重いので [HealthConfig::dir_usage_schedule] の間隔で別に行い、
+//! 測定タスクでは最後の結果を記録する。
+//! ネットワーク転送速度、ロードアベレージ、稼働時間、自プロセスの情報は [procfs] から取得する。
+
+pub mod alert;
+pub mod chart;
+pub mod disk;
+pub mod history;
+pub mod procfs;
 
 use super::SystemModule;
-use crate::taskserver::Control;
-use crate::{config, taskserver};
-use anyhow::{Result, anyhow, ensure};
+use crate::config::{self, ValidationReport};
+use crate::taskserver;
+use crate::taskserver::event::{HealthReported, HealthSampled, Notify};
+use crate::taskserver::{Control, schedule::Schedule};
+use alert::{AlertRule, AlertState};
+use anyhow::{Context, Result, anyhow, bail, ensure};
 use bitflags::bitflags;
-use chrono::{DateTime, Local, NaiveTime};
-use log::info;
+use chart::ChartRange;
+use chrono::{DateTime, Duration, Local};
+use disk::{DirUsage, MountInfo};
+use history::{HistoryReader, HistoryStore};
+use log::{info, warn};
+use procfs::{LoadAvg, NetDevCounter, NetRate, ProcessInfo};
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::{process::Command, select};
+use utils::graphics::FontRenderer;
 
 /// [Health::history] の最大サイズ。
 ///
@@ -17,52 +45,301 @@ use tokio::{process::Command, select};
 const HISTORY_QUEUE_SIZE: usize = 60 * 1024 * 2;
 
 /// ヘルスチェック設定データ。toml 設定に対応する。
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HealthConfig {
     /// ヘルスチェック機能を有効化する。
     enabled: bool,
     /// 起動時に1回だけタイムライン確認タスクを起動する。デバッグ用。
     debug_exec_once: bool,
+    /// 測定タスクの起動スケジュール。
+    /// 書式は [crate::taskserver::schedule] を参照。
+    #[serde(default = "default_check_schedule")]
+    check_schedule: String,
+    /// ツイートタスクの起動スケジュール。
+    /// 書式は [crate::taskserver::schedule] を参照。
+    #[serde(default = "default_tweet_schedule")]
+    tweet_schedule: String,
+    /// 測定データの保存ディレクトリ。
+    /// 空文字列にすると保存しない。
+    #[serde(default = "default_history_dir")]
+    history_dir: String,
+    /// 測定データの保存日数。
+    #[serde(default = "default_history_days")]
+    history_days: u32,
+    /// アラートの通知先 Discord チャネル ID。0 ならば通知しない。
+    #[serde(default)]
+    alert_discord_ch: u64,
+    /// アラートの通知先 LINE ユーザまたはグループ ID。空文字列ならば通知しない。
+    #[serde(default)]
+    alert_line_to: String,
+    /// アラートルールのリスト。
+    #[serde(default)]
+    alerts: Vec<AlertRule>,
+    /// グラフ描画に使う ttf ファイルへのパス。
+    /// 空文字列にするとグラフを無効化する。
+    #[serde(default)]
+    font_file: String,
+    /// 定期レポートに直近 24 時間のグラフを添付する。
+    #[serde(default)]
+    report_chart: bool,
+    /// 容量と inode 数を測定するマウントポイントのリスト。
+    #[serde(default = "default_mounts")]
+    mounts: Vec<String>,
+    /// ディレクトリ使用量の測定タスクの起動スケジュール。
+    /// ディレクトリの走査は重いので、測定タスクとは別に間隔を空けて行う。
+    /// 書式は [crate::taskserver::schedule] を参照。
+    #[serde(default = "default_dir_usage_schedule")]
+    dir_usage_schedule: String,
+}
+
+fn default_mounts() -> Vec<String> {
+    vec!["/".to_string()]
+}
+
+fn default_dir_usage_schedule() -> String {
+    "*/30 * * * *".to_string()
+}
+
+fn default_history_dir() -> String {
+    "./health".to_string()
+}
+
+fn default_history_days() -> u32 {
+    90
+}
+
+fn default_check_schedule() -> String {
+    "* * * * *".to_string()
+}
+
+fn default_tweet_schedule() -> String {
+    "0 0,6,12,18 * * *".to_string()
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            debug_exec_once: false,
+            check_schedule: default_check_schedule(),
+            tweet_schedule: default_tweet_schedule(),
+            history_dir: default_history_dir(),
+            history_days: default_history_days(),
+            alert_discord_ch: 0,
+            alert_line_to: "".to_string(),
+            alerts: Vec::new(),
+            font_file: "".to_string(),
+            report_chart: false,
+            mounts: default_mounts(),
+            dir_usage_schedule: default_dir_usage_schedule(),
+        }
+    }
+}
+
+impl HealthConfig {
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        report.check_schedule("health.check_schedule", &self.check_schedule);
+        report.check_schedule("health.tweet_schedule", &self.tweet_schedule);
+        report.check_schedule("health.dir_usage_schedule", &self.dir_usage_schedule);
+        if self.enabled && !self.history_dir.is_empty() {
+            report.check_dir_writable("health.history_dir", &self.history_dir);
+        }
+        if self.history_days == 0 {
+            report.add("health.history_days", "must be 1 or more");
+        }
+        for (i, rule) in self.alerts.iter().enumerate() {
+            rule.validate(&format!("health.alerts[{i}]"), report);
+        }
+        if !self.font_file.is_empty() {
+            report.check_file("health.font_file", &self.font_file);
+        }
+        if self.report_chart && self.font_file.is_empty() {
+            report.add("health.report_chart", "health.font_file is required");
+        }
+        for (i, mount) in self.mounts.iter().enumerate() {
+            if !mount.starts_with('/') {
+                report.add(&format!("health.mounts[{i}]"), "must be an absolute path");
+            }
+        }
+    }
 }
 
 /// ヘルスチェックシステムモジュール。
 pub struct Health {
     /// 設定データ。
     config: HealthConfig,
-    /// 定期実行の時刻リスト。
-    wakeup_list_check: Vec<NaiveTime>,
-    /// 定期実行の時刻リスト。
-    wakeup_list_tweet: Vec<NaiveTime>,
+    /// 測定タスクの起動スケジュール。
+    schedule_check: Schedule,
+    /// ツイートタスクの起動スケジュール。
+    schedule_tweet: Schedule,
+    /// ディレクトリ使用量の測定タスクの起動スケジュール。
+    schedule_dirs: Schedule,
     /// 測定データの履歴。最大サイズは [HISTORY_QUEUE_SIZE]。
     history: VecDeque<HistoryEntry>,
+    /// 測定データの保存先。保存しない場合は None。
+    store: Option<HistoryStore>,
+    /// [HealthConfig::alerts] の各ルールの評価状態。
+    alert_states: Vec<AlertState>,
+    /// グラフ描画用フォント。グラフが無効の場合は None。
+    font: Option<Arc<FontRenderer>>,
+    /// 使用量を測定するディレクトリ (表示名, パス)。
+    dirs: Vec<(String, PathBuf)>,
+    /// 前回のディスク測定のエラー。変化した時のみログに出す。
+    disk_errors: Vec<String>,
+    /// 最後に測定したディレクトリ使用量。[Self::dirs_task] で更新する。
+    dir_usage: Vec<DirUsage>,
+    /// 前回のディレクトリ使用量測定のエラー。変化した時のみログに出す。
+    dir_errors: Vec<String>,
+    /// 前回のネットワーク転送量とその取得時刻。転送速度の計算に使う。
+    net_prev: Option<(Instant, Vec<NetDevCounter>)>,
 }
 
 impl Health {
     /// コンストラクタ。
     ///
     /// 設定の読み込みのみ行い、async task の初期化は [Self::on_start] で行う。
-    pub fn new(
-        wakeup_list_check: Vec<NaiveTime>,
-        wakeup_list_tweet: Vec<NaiveTime>,
-    ) -> Result<Self> {
+    pub fn new() -> Result<Self> {
         info!("[health] initialize");
 
         let config: HealthConfig = config::get(|cfg| cfg.health.clone());
+        let schedule_check = config
+            .check_schedule
+            .parse()
+            .context("health.check_schedule")?;
+        let schedule_tweet = config
+            .tweet_schedule
+            .parse()
+            .context("health.tweet_schedule")?;
+        let schedule_dirs = config
+            .dir_usage_schedule
+            .parse()
+            .context("health.dir_usage_schedule")?;
+
+        let store = if config.enabled && !config.history_dir.is_empty() {
+            Some(HistoryStore::new(&config.history_dir, config.history_days))
+        } else {
+            None
+        };
+        let mut history = VecDeque::with_capacity(HISTORY_QUEUE_SIZE);
+        if let Some(store) = &store {
+            // 復元に失敗しても起動は続ける
+            match store.load_latest(HISTORY_QUEUE_SIZE) {
+                Ok(entries) => {
+                    info!("[health] history loaded: {}", entries.len());
+                    history.extend(entries);
+                }
+                Err(e) => warn!("[health] cannot load history: {e:#}"),
+            }
+        }
+
+        let alert_states = vec![AlertState::default(); config.alerts.len()];
+
+        let font = if !config.font_file.is_empty() {
+            let ttf_bin = fs::read(&config.font_file).context("health.font_file")?;
+            Some(Arc::new(FontRenderer::new(ttf_bin)?))
+        } else {
+            None
+        };
+
+        let dirs = watched_dirs();
+        for (name, path) in &dirs {
+            info!("[health] watch dir: {name} = {}", path.display());
+        }
 
         Ok(Health {
             config,
-            wakeup_list_check,
-            wakeup_list_tweet,
-            history: VecDeque::with_capacity(HISTORY_QUEUE_SIZE),
+            schedule_check,
+            schedule_tweet,
+            schedule_dirs,
+            history,
+            store,
+            alert_states,
+            font,
+            dirs,
+            disk_errors: Vec::new(),
+            dir_usage: Vec::new(),
+            dir_errors: Vec::new(),
+            net_prev: None,
+        })
+    }
+
+    /// 最新の測定データ。
+    pub fn latest(&self) -> Option<&HistoryEntry> {
+        self.history.back()
+    }
+
+    /// `from` 以上 `to` 未満の測定データを読み出すための [HistoryReader] を返す。
+    ///
+    /// 保存している場合はファイルから、していない場合はメモリ上の履歴から読む。
+    /// 読み出しはロックを解放してから行うこと。
+    pub fn history_reader(&self, from: DateTime<Local>, to: DateTime<Local>) -> HistoryReader {
+        match &self.store {
+            Some(store) => HistoryReader::Store(store.clone()),
+            None => HistoryReader::Memory(
+                self.history
+                    .iter()
+                    .filter(|entry| from <= entry.timestamp && entry.timestamp < to)
+                    .cloned()
+                    .collect(),
+            ),
+        }
+    }
+
+    /// 現在までの `range` の期間のグラフを PNG で描画する。
+    ///
+    /// ロック中に必要なものだけを取り出し、ロックを解放してから別スレッドで
+    /// 履歴の読み込みと描画を行う。
+    /// [HealthConfig::font_file] が設定されていない場合はエラー。
+    pub async fn render_chart(ctrl: &Control, range: ChartRange) -> Result<Vec<u8>> {
+        let to = Local::now();
+        let from = to - range.duration();
+        let (font, reader) = {
+            let health = ctrl.sysmods().health.lock().await;
+            let Some(font) = &health.font else {
+                bail!("health chart is disabled (health.font_file is not set)");
+            };
+            (Arc::clone(font), health.history_reader(from, to))
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let summaries = reader.query_summary(from, to, range.step())?;
+            chart::render(&font, range, to, &summaries)
         })
+        .await?
     }
 
     /// 測定タスク。
-    /// [Self::history] に最新データを追加する。
-    async fn check_task(&mut self, _ctrl: &Control) -> Result<()> {
+    /// [Self::history] に最新データを追加し、[HealthSampled] を発行する。
+    /// 保存が有効ならばファイルにも追記する。
+    async fn check_task(&mut self, ctrl: &Control) -> Result<()> {
         let cpu_info = get_cpu_info().await?;
         let mem_info = get_mem_info().await?;
         let disk_info = get_disk_info().await?;
+        // 取得できなくても他の測定値は記録する
+        let throttle = get_throttle_status().await.unwrap_or_else(|e| {
+            warn!("[health] throttle status: {e:#}");
+            None
+        });
+        let mounts = self.check_mounts().await?;
+        let dirs = self.dir_usage.clone();
+        // procfs の読み取りに失敗した項目は記録せず、他の測定値は記録する
+        let net = self
+            .check_net()
+            .await
+            .inspect_err(|e| warn!("[health] net: {e:#}"))
+            .unwrap_or_default();
+        let load = procfs::get_loadavg()
+            .await
+            .inspect_err(|e| warn!("[health] loadavg: {e:#}"))
+            .ok();
+        let uptime_secs = procfs::get_uptime()
+            .await
+            .inspect_err(|e| warn!("[health] uptime: {e:#}"))
+            .ok();
+        let process = procfs::get_process_info()
+            .await
+            .inspect_err(|e| warn!("[health] process: {e:#}"))
+            .ok();
 
         let timestamp = Local::now();
         let enrty = HistoryEntry {
@@ -70,6 +347,13 @@ impl Health {
             cpu_info,
             mem_info,
             disk_info,
+            throttle,
+            mounts,
+            dirs,
+            net,
+            load,
+            uptime_secs,
+            process,
         };
 
         debug_assert!(self.history.len() <= HISTORY_QUEUE_SIZE);
@@ -78,19 +362,100 @@ impl Health {
             self.history.pop_front();
         }
         // 今回の分を追加
-        self.history.push_back(enrty);
+        self.history.push_back(enrty.clone());
+        ctrl.publish(HealthSampled(enrty.clone()));
+        self.check_alerts(ctrl, &enrty);
+        if let Some(store) = &mut self.store {
+            store.append(&enrty).context("health history")?;
+        }
 
         Ok(())
     }
 
-    /// ツイートタスク。
-    /// [Self::history] の最新データが存在すればツイートする。
-    async fn tweet_task(&self, ctrl: &Control) -> Result<()> {
-        if let Some(entry) = self.history.back() {
+    /// マウントポイントの使用量を測定する。
+    ///
+    /// 測定できなかったものは結果から除き、エラー内容が変わった時のみ警告する。
+    async fn check_mounts(&mut self) -> Result<Vec<MountInfo>> {
+        let mounts = self.config.mounts.clone();
+        let (mounts, errors) =
+            tokio::task::spawn_blocking(move || disk::get_mounts(&mounts)).await?;
+
+        if errors != self.disk_errors {
+            for e in &errors {
+                warn!("[health] disk: {e}");
+            }
+            self.disk_errors = errors;
+        }
+
+        Ok(mounts)
+    }
+
+    /// ディレクトリ使用量の測定タスク。
+    ///
+    /// 走査には時間がかかるので、ロックを解放して別スレッドで測定し、
+    /// 結果を [Self::dir_usage] に保存する。
+    /// 測定できなかったものは結果から除き、エラー内容が変わった時のみ警告する。
+    async fn dirs_task(ctrl: &Control) -> Result<()> {
+        let dirs = ctrl.sysmods().health.lock().await.dirs.clone();
+        let (dirs, errors) =
+            tokio::task::spawn_blocking(move || disk::get_dir_usage(&dirs)).await?;
+
+        let mut health = ctrl.sysmods().health.lock().await;
+        if errors != health.dir_errors {
+            for e in &errors {
+                warn!("[health] dir: {e}");
+            }
+            health.dir_errors = errors;
+        }
+        health.dir_usage = dirs;
+
+        Ok(())
+    }
+
+    /// 前回の測定からのネットワーク転送速度を計算する。
+    ///
+    /// 起動後の初回は前回の値がないため空になる。
+    async fn check_net(&mut self) -> Result<Vec<NetRate>> {
+        let now = Instant::now();
+        let cur = procfs::get_net_dev().await?;
+        let rates = match &self.net_prev {
+            Some((prev_time, prev)) => {
+                procfs::net_rates(prev, &cur, (now - *prev_time).as_secs_f64())
+            }
+            None => Vec::new(),
+        };
+        self.net_prev = Some((now, cur));
+
+        Ok(rates)
+    }
+
+    /// アラートルールを評価し、通知すべきものを [Notify] として発行する。
+    fn check_alerts(&mut self, ctrl: &Control, entry: &HistoryEntry) {
+        for (rule, state) in self.config.alerts.iter().zip(self.alert_states.iter_mut()) {
+            if let Some(text) = state.update(rule, entry) {
+                info!("[health] {text}");
+                ctrl.publish(Notify {
+                    text,
+                    discord_ch: self.config.alert_discord_ch,
+                    line_to: self.config.alert_line_to.clone(),
+                });
+            }
+        }
+    }
+
+    /// 定期レポートの本文を作る。
+    /// [Self::history] の最新データが存在しなければ None。
+    fn report_text(&self) -> Option<String> {
+        self.latest().map(|entry| {
             let HistoryEntry {
                 cpu_info,
                 mem_info,
                 disk_info,
+                mounts,
+                dirs,
+                load,
+                uptime_secs,
+                process,
                 ..
             } = entry;
 
@@ -109,16 +474,83 @@ impl Health {
                 100.0 * mem_info.avail_mib / mem_info.total_mib,
             ));
 
-            text.push_str(&format!(
-                "\nDisk: {:.1}/{:.1} GB Avail ({:.1}%)",
-                disk_info.avail_gib,
-                disk_info.total_gib,
-                100.0 * disk_info.avail_gib / disk_info.total_gib,
-            ));
+            if mounts.is_empty() {
+                text.push_str(&format!(
+                    "\nDisk: {:.1}/{:.1} GB Avail ({:.1}%)",
+                    disk_info.avail_gib,
+                    disk_info.total_gib,
+                    100.0 * disk_info.avail_gib / disk_info.total_gib,
+                ));
+            }
+            for info in mounts {
+                text.push_str(&format!(
+                    "\nDisk {}: {:.1}/{:.1} GB Avail ({:.1}%)",
+                    info.mount,
+                    info.avail_gib,
+                    info.total_gib,
+                    info.avail_percent().unwrap_or(0.0),
+                ));
+                if let Some(inode) = info.inode_avail_percent() {
+                    text.push_str(&format!(", Inode {inode:.1}%"));
+                }
+            }
 
-            let mut twitter = ctrl.sysmods().twitter.lock().await;
-            twitter.tweet(&text).await?;
-        }
+            if !dirs.is_empty() {
+                let list: Vec<_> = dirs
+                    .iter()
+                    .map(|dir| format!("{} {:.1} MB", dir.name, dir.size_mib))
+                    .collect();
+                text.push_str(&format!("\nDir: {}", list.join(", ")));
+            }
+
+            if let Some(load) = load {
+                text.push_str(&format!(
+                    "\nLoad: {:.2} {:.2} {:.2}",
+                    load.load1, load.load5, load.load15
+                ));
+            }
+            if let Some(uptime_secs) = uptime_secs {
+                let uptime = Duration::seconds(*uptime_secs as i64);
+                text.push_str(&format!(
+                    "\nUptime: {}d {}h",
+                    uptime.num_days(),
+                    uptime.num_hours() % 24
+                ));
+            }
+            if let Some(process) = process {
+                text.push_str(&format!(
+                    "\nProcess: {:.1} MB, {} threads, {} fds",
+                    process.rss_mib, process.threads, process.fds
+                ));
+            }
+
+            text
+        })
+    }
+
+    /// ツイートタスク。
+    /// [Self::history] の最新データが存在すれば [HealthReported] を発行する。
+    /// [HealthConfig::report_chart] が有効ならばグラフを添付する。
+    async fn tweet_task(ctrl: &Control) -> Result<()> {
+        let (text, report_chart) = {
+            let health = ctrl.sysmods().health.lock().await;
+            let Some(text) = health.report_text() else {
+                return Ok(());
+            };
+            (text, health.config.report_chart)
+        };
+
+        // グラフの描画に失敗してもテキストだけでレポートする
+        let chart = if report_chart {
+            Self::render_chart(ctrl, ChartRange::Day)
+                .await
+                .inspect_err(|e| warn!("[health] chart: {e:#}"))
+                .ok()
+        } else {
+            None
+        };
+
+        ctrl.publish(HealthReported { text, chart });
 
         Ok(())
     }
@@ -132,8 +564,14 @@ impl Health {
         // unlock
     }
 
+    /// [Self::dirs_task] のエントリ関数。
+    /// ロックは [Self::dirs_task] の中で行う。
+    async fn dirs_task_entry(ctrl: Control) -> Result<()> {
+        Self::dirs_task(&ctrl).await
+    }
+
     /// [Self::tweet_task] のエントリ関数。
-    /// モジュールをロックしてメソッド呼び出しを行う。
+    /// グラフの描画中はロックしないよう、ロックは [Self::tweet_task] の中で行う。
     async fn tweet_task_entry(ctrl: Control) -> Result<()> {
         // check_task を先に実行する (可能性を高める) ために遅延させる
         select! {
@@ -144,10 +582,7 @@ impl Health {
             }
         }
 
-        // rlock
-        let health = ctrl.sysmods().health.lock().await;
-        health.tweet_task(&ctrl).await
-        // unlock
+        Self::tweet_task(&ctrl).await
     }
 }
 
@@ -155,6 +590,8 @@ impl SystemModule for Health {
     fn on_start(&mut self, ctrl: &Control) {
         info!("[health] on_start");
         if self.config.enabled {
+            // 起動直後の測定にも使えるよう、一度すぐに測定する
+            taskserver::spawn_oneshot_task(ctrl, "health-dirs", Health::dirs_task_entry);
             if self.config.debug_exec_once {
                 taskserver::spawn_oneshot_task(ctrl, "health-check", Health::check_task_entry);
                 taskserver::spawn_oneshot_task(ctrl, "health-tweet", Health::tweet_task_entry);
@@ -162,36 +599,87 @@ impl SystemModule for Health {
                 taskserver::spawn_periodic_task(
                     ctrl,
                     "health-check",
-                    &self.wakeup_list_check,
+                    &self.schedule_check,
                     Health::check_task_entry,
                 );
                 taskserver::spawn_periodic_task(
                     ctrl,
                     "health-tweet",
-                    &self.wakeup_list_tweet,
+                    &self.schedule_tweet,
                     Health::tweet_task_entry,
                 );
+                taskserver::spawn_periodic_task(
+                    ctrl,
+                    "health-dirs",
+                    &self.schedule_dirs,
+                    Health::dirs_task_entry,
+                );
             }
         }
     }
 }
 
+/// 使用量を測定するディレクトリ (表示名, パス) を各モジュールの設定から集める。
+///
+/// 無効なものや未設定のものは含まない。
+fn watched_dirs() -> Vec<(String, PathBuf)> {
+    let mut dirs: Vec<(String, PathBuf)> = config::get(|cfg| {
+        let mut dirs = vec![
+            ("camera_history", cfg.camera.pic_history_dir().to_string()),
+            ("camera_archive", cfg.camera.pic_archive_dir().to_string()),
+        ];
+        if let Some(dir) = cfg.http.upload_dir() {
+            dirs.push(("upload", dir.to_string()));
+        }
+        if !cfg.openai.storage_dir.is_empty() {
+            dirs.push(("openai", cfg.openai.storage_dir.clone()));
+        }
+        dirs.into_iter()
+            .map(|(name, path)| (name.to_string(), PathBuf::from(path)))
+            .collect()
+    });
+    match utils::dir::cache_dir() {
+        Ok(dir) => dirs.push(("log".to_string(), dir)),
+        Err(e) => warn!("[health] log dir: {e:#}"),
+    }
+
+    dirs
+}
+
 /// 履歴データのエントリ。
 #[derive(Debug, Clone)]
-struct HistoryEntry {
+pub struct HistoryEntry {
     /// タイムスタンプ。
-    #[allow(dead_code)]
-    timestamp: DateTime<Local>,
+    pub timestamp: DateTime<Local>,
     /// CPU 使用率。
-    cpu_info: CpuInfo,
+    pub cpu_info: CpuInfo,
     /// メモリ使用率。
-    mem_info: MemInfo,
+    pub mem_info: MemInfo,
     /// ディスク使用率。
-    disk_info: DiskInfo,
+    pub disk_info: DiskInfo,
+    /// CPU スロットリング状態。
+    /// 取得できない環境では [None]。
+    pub throttle: Option<ThrottleFlags>,
+    /// [HealthConfig::mounts] の使用量。測定できたもののみ。
+    pub mounts: Vec<MountInfo>,
+    /// アプリが書き込むディレクトリの使用量。測定できたもののみ。
+    pub dirs: Vec<DirUsage>,
+    /// ループバック以外のネットワークインタフェースの転送速度。
+    /// 起動後の初回は空。
+    pub net: Vec<NetRate>,
+    /// ロードアベレージ。
+    /// 古い履歴には存在しないため [None] になりうる。
+    pub load: Option<LoadAvg>,
+    /// OS 起動からの秒数。
+    /// 古い履歴には存在しないため [None] になりうる。
+    pub uptime_secs: Option<f64>,
+    /// このプロセス自身のリソース使用量。
+    /// 古い履歴には存在しないため [None] になりうる。
+    pub process: Option<ProcessInfo>,
 }
 
 /// CPU 情報。
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct CpuInfo {
     /// 全コア合計の使用率。
     pub cpu_percent_total: f64,
@@ -201,7 +689,7 @@ pub struct CpuInfo {
 }
 
 /// メモリ使用率。
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct MemInfo {
     /// メモリ総量 (MiB)。
     pub total_mib: f64,
@@ -210,7 +698,7 @@ pub struct MemInfo {
 }
 
 /// ディスク使用率。
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct DiskInfo {
     /// ディスク総量 (GiB)。
     pub total_gib: f64,
@@ -332,52 +820,13 @@ pub async fn get_mem_info() -> Result<MemInfo> {
 
 /// [DiskInfo] を計測する。
 ///
-/// `df` コマンドによる。
+/// ルートファイルシステムの statvfs(3) による。
 pub async fn get_disk_info() -> Result<DiskInfo> {
-    let mut cmd = Command::new("df");
-    let output = cmd.output().await?;
-    ensure!(output.status.success(), "df command failed");
-
-    // sample
-    // ファイルシス   1K-ブロック     使用    使用可 使用% マウント位置
-    // /dev/root        122621412 12964620 104641120   12% /
-    // devtmpfs           1800568        0   1800568    0% /dev
-    // tmpfs              1965432        0   1965432    0% /dev/shm
-    // tmpfs              1965432    17116   1948316    1% /run
-    // tmpfs                 5120        4      5116    1% /run/lock
-    // tmpfs              1965432        0   1965432    0% /sys/fs/cgroup
-    // /dev/mmcblk0p1      258095    49324    208772   20% /boot
-    // /dev/sda1         59280316 57109344         0  100% /media/usbbkup
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut total = None;
-    let mut avail = None;
-    for line in stdout.lines().skip(1) {
-        let mut total_tmp = None;
-        let mut avail_tmp = None;
-        let mut mp_tmp = None;
-        for (col_no, elem) in line.split_ascii_whitespace().enumerate() {
-            match col_no {
-                1 => total_tmp = Some(elem),
-                3 => avail_tmp = Some(elem),
-                5 => mp_tmp = Some(elem),
-                _ => (),
-            }
-        }
-        if let Some(mp) = mp_tmp
-            && mp == "/"
-        {
-            total = total_tmp;
-            avail = avail_tmp;
-        }
-    }
-    let total = total.ok_or_else(|| anyhow!("parse error"))?;
-    let avail = avail.ok_or_else(|| anyhow!("parse error"))?;
-    let total_gib = total.parse::<u64>()? as f64 / 1024.0 / 1024.0;
-    let avail_gib = avail.parse::<u64>()? as f64 / 1024.0 / 1024.0;
+    let info = disk::get_mount_info("/")?;
 
     Ok(DiskInfo {
-        total_gib,
-        avail_gib,
+        total_gib: info.total_gib,
+        avail_gib: info.avail_gib,
     })
 }
 
@@ -494,7 +943,7 @@ pub async fn get_freq_conf() -> Result<Option<u64>> {
 
 bitflags! {
     /// vcgencmd get_throttled bit flags
-    #[derive(Default)]
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
     pub struct ThrottleFlags: u32 {
         /// 0: Under-voltage detected
         const UNDER_VOLTAGE = 0x1;
@@ -519,6 +968,7 @@ bitflags! {
 ///
 /// Raspberry Pi vcgencmd コマンドを使用する。
 /// 存在しない環境ではエラーではなく None を返す。
+/// 未定義のビットは無視する。
 pub async fn get_throttle_status() -> Result<Option<ThrottleFlags>> {
     let result = Command::new("vcgencmd").arg("get_throttled").output().await;
     let output = match result {
@@ -533,7 +983,12 @@ pub async fn get_throttle_status() -> Result<Option<ThrottleFlags>> {
             }
         }
     };
-    ensure!(output.status.success(), "vcgencmd get_throttled failed");
+    ensure!(
+        output.status.success(),
+        "vcgencmd get_throttled failed: {}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     let bits = if let Some((_le, ri)) = stdout.trim().split_once("=0x") {
@@ -541,7 +996,8 @@ pub async fn get_throttle_status() -> Result<Option<ThrottleFlags>> {
     } else {
         return Err(anyhow!("Parse error"));
     };
-    let status = ThrottleFlags::from_bits(bits).ok_or_else(|| anyhow!("Invalid bitflags"))?;
+    // 未知のビットは無視する
+    let status = ThrottleFlags::from_bits_truncate(bits);
 
     Ok(Some(status))
 }
@@ -0,0 +1,377 @@
+//! ヘルスチェックのアラート。
+//!
+//! 測定のたびに各 [AlertRule] を評価し、発生と回復を通知する。
+//!
+//! * 閾値を超えた状態が [AlertRule::duration_min] 続いたら発生とする。
+//! * 発生中は [AlertRule::recover] まで戻ったら回復とする (ヒステリシス)。
+//! * 発生を通知してから [AlertRule::cooldown_min] の間は、次の発生を通知しない。
+//!   クールダウンが明けてもまだ発生中ならば、その時に通知する。
+//!   通知しなかった発生の回復も通知しない。
+//!
+//! ```toml
+//! [[health.alerts]]
+//! name = "CPU temperature"
+//! metric = "cpu_temp"
+//! above = 75.0
+//! recover = 70.0
+//! duration_min = 5
+//!
+//! [[health.alerts]]
+//! name = "Under-voltage"
+//! metric = "under_voltage"
+//! above = 0.0
+//!
+//! [[health.alerts]]
+//! name = "Backup disk"
+//! metric = "mount_avail_percent"
+//! target = "/media/usbbkup"
+//! below = 5.0
+//! recover = 10.0
+//! ```
+
+use super::HistoryEntry;
+use super::history::Metric;
+use crate::config::ValidationReport;
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+
+/// アラートルール。toml 設定に対応する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertRule {
+    /// ルール名。通知に使う。
+    pub name: String,
+    /// 監視する値。
+    pub metric: Metric,
+    /// 監視対象のマウントポイント、ディレクトリ名またはインタフェース名。
+    /// [Metric::has_target] の場合のみ指定する。
+    #[serde(default)]
+    pub target: Option<String>,
+    /// この値を上回ったら発生とする。[Self::below] とどちらか一方を指定する。
+    pub above: Option<f64>,
+    /// この値を下回ったら発生とする。[Self::above] とどちらか一方を指定する。
+    pub below: Option<f64>,
+    /// 回復とする値。省略時は発生の閾値と同じ。
+    ///
+    /// [Self::above] の場合はこの値を下回ったら、
+    /// [Self::below] の場合はこの値を上回ったら回復とする。
+    pub recover: Option<f64>,
+    /// 閾値を超えた状態がこの時間 (分) 続いたら発生とする。0 ならば即時。
+    #[serde(default)]
+    pub duration_min: u32,
+    /// 発生を通知してから次の発生を通知しない時間 (分)。
+    #[serde(default = "default_cooldown_min")]
+    pub cooldown_min: u32,
+}
+
+fn default_cooldown_min() -> u32 {
+    60
+}
+
+impl AlertRule {
+    pub(crate) fn validate(&self, path: &str, report: &mut ValidationReport) {
+        if self.name.is_empty() {
+            report.add(&format!("{path}.name"), "must not be empty");
+        }
+        match (self.metric.has_target(), &self.target) {
+            (true, None) => report.add(
+                &format!("{path}.target"),
+                format!("required for {}", self.metric.name()),
+            ),
+            (false, Some(_)) => report.add(
+                &format!("{path}.target"),
+                format!("not allowed for {}", self.metric.name()),
+            ),
+            _ => {}
+        }
+        match (self.above, self.below, self.recover) {
+            (Some(_), Some(_), _) | (None, None, _) => {
+                report.add(path, "either above or below is required");
+            }
+            (Some(above), None, Some(recover)) if recover > above => {
+                report.add(&format!("{path}.recover"), "must be <= above");
+            }
+            (None, Some(below), Some(recover)) if recover < below => {
+                report.add(&format!("{path}.recover"), "must be >= below");
+            }
+            _ => {}
+        }
+    }
+
+    /// `value` が発生条件を満たすか。
+    fn is_alert(&self, value: f64) -> bool {
+        match (self.above, self.below) {
+            (Some(above), _) => value > above,
+            (None, Some(below)) => value < below,
+            (None, None) => false,
+        }
+    }
+
+    /// `value` が回復条件を満たすか。
+    fn is_recovered(&self, value: f64) -> bool {
+        match (self.above, self.below) {
+            (Some(above), _) => value < self.recover.unwrap_or(above),
+            (None, Some(below)) => value > self.recover.unwrap_or(below),
+            (None, None) => true,
+        }
+    }
+
+    /// 監視する値の表示用文字列。
+    fn metric_label(&self) -> String {
+        match &self.target {
+            Some(target) => format!("{}[{target}]", self.metric.name()),
+            None => self.metric.name().to_string(),
+        }
+    }
+
+    /// 条件の表示用文字列。
+    fn condition(&self) -> String {
+        let op = match (self.above, self.below) {
+            (Some(above), _) => format!("> {above}"),
+            (None, Some(below)) => format!("< {below}"),
+            (None, None) => "-".to_string(),
+        };
+        if self.duration_min > 0 {
+            format!("{op} for {} min", self.duration_min)
+        } else {
+            op
+        }
+    }
+}
+
+/// [AlertRule] ごとの評価状態。
+#[derive(Debug, Default, Clone)]
+pub struct AlertState {
+    /// 閾値を超え始めた時刻。発生前のみ。
+    since: Option<DateTime<Local>>,
+    /// 発生中。
+    firing: bool,
+    /// 今回の発生を通知したか。
+    notified: bool,
+    /// 最後に発生を通知した時刻。
+    last_notified: Option<DateTime<Local>>,
+}
+
+impl AlertState {
+    /// `entry` で状態を更新し、通知すべきメッセージがあれば返す。
+    ///
+    /// 値が取得できなかった場合は何もしない。
+    pub fn update(&mut self, rule: &AlertRule, entry: &HistoryEntry) -> Option<String> {
+        let value = rule.metric.value_of(entry, rule.target.as_deref())?;
+        let now = entry.timestamp;
+        let cooldown_over = self
+            .last_notified
+            .is_none_or(|last| now - last >= Duration::minutes(rule.cooldown_min as i64));
+
+        if self.firing {
+            if rule.is_recovered(value) {
+                self.firing = false;
+                self.since = None;
+                return self.notified.then(|| {
+                    format!(
+                        "[RECOVERED] {}: {} = {value:.1}",
+                        rule.name,
+                        rule.metric_label()
+                    )
+                });
+            }
+            if self.notified || !cooldown_over {
+                return None;
+            }
+        } else {
+            if !rule.is_alert(value) {
+                self.since = None;
+                return None;
+            }
+            let since = *self.since.get_or_insert(now);
+            if now - since < Duration::minutes(rule.duration_min as i64) {
+                return None;
+            }
+
+            self.firing = true;
+            self.notified = false;
+            if !cooldown_over {
+                return None;
+            }
+        }
+        self.notified = true;
+        self.last_notified = Some(now);
+
+        Some(format!(
+            "[ALERT] {}: {} = {value:.1} ({})",
+            rule.name,
+            rule.metric_label(),
+            rule.condition()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysmod::health::{CpuInfo, DiskInfo, MemInfo};
+    use chrono::TimeZone;
+
+    fn entry(min: i64, temp: f64) -> HistoryEntry {
+        HistoryEntry {
+            timestamp: Local.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+                + Duration::minutes(min),
+            cpu_info: CpuInfo {
+                cpu_percent_total: 0.0,
+                temp: Some(temp),
+            },
+            mem_info: MemInfo {
+                total_mib: 0.0,
+                avail_mib: 0.0,
+            },
+            disk_info: DiskInfo {
+                total_gib: 0.0,
+                avail_gib: 0.0,
+            },
+            throttle: None,
+            mounts: Vec::new(),
+            dirs: Vec::new(),
+            net: Vec::new(),
+            load: None,
+            uptime_secs: None,
+            process: None,
+        }
+    }
+
+    fn rule() -> AlertRule {
+        AlertRule {
+            name: "temp".to_string(),
+            metric: Metric::CpuTemp,
+            target: None,
+            above: Some(75.0),
+            below: None,
+            recover: Some(70.0),
+            duration_min: 2,
+            cooldown_min: 30,
+        }
+    }
+
+    /// (分, 温度) の列を流し、通知が出た分とメッセージの先頭を返す。
+    fn run(rule: &AlertRule, samples: &[(i64, f64)]) -> Vec<(i64, String)> {
+        let mut state = AlertState::default();
+        samples
+            .iter()
+            .filter_map(|&(min, temp)| {
+                let msg = state.update(rule, &entry(min, temp))?;
+                let head = msg.split(':').next().unwrap().to_string();
+                Some((min, head))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn duration_and_hysteresis() {
+        let result = run(
+            &rule(),
+            &[
+                // 2 分続かなければ発生しない
+                (0, 80.0),
+                (1, 80.0),
+                (2, 74.0),
+                (3, 80.0),
+                (4, 80.0),
+                (5, 80.0),
+                // 回復の閾値までは発生のまま
+                (6, 72.0),
+                (7, 80.0),
+                (8, 69.0),
+            ],
+        );
+        assert_eq!(
+            result,
+            [
+                (5, "[ALERT] temp".to_string()),
+                (8, "[RECOVERED] temp".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn cooldown() {
+        let mut rule = rule();
+        rule.duration_min = 0;
+        let result = run(
+            &rule,
+            &[
+                (0, 80.0),
+                (1, 60.0),
+                // クールダウン中の発生と回復は通知しない
+                (10, 80.0),
+                (11, 60.0),
+                (30, 80.0),
+                (31, 60.0),
+            ],
+        );
+        assert_eq!(
+            result,
+            [
+                (0, "[ALERT] temp".to_string()),
+                (1, "[RECOVERED] temp".to_string()),
+                (30, "[ALERT] temp".to_string()),
+                (31, "[RECOVERED] temp".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn cooldown_still_firing() {
+        let mut rule = rule();
+        rule.duration_min = 0;
+        let result = run(
+            &rule,
+            &[
+                (0, 80.0),
+                (1, 60.0),
+                // クールダウン中に発生し、明けても発生中ならば通知する
+                (10, 80.0),
+                (20, 80.0),
+                (30, 80.0),
+                (31, 80.0),
+                (40, 60.0),
+            ],
+        );
+        assert_eq!(
+            result,
+            [
+                (0, "[ALERT] temp".to_string()),
+                (1, "[RECOVERED] temp".to_string()),
+                (30, "[ALERT] temp".to_string()),
+                (40, "[RECOVERED] temp".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let mut report = ValidationReport::default();
+        rule().validate("health.alerts[0]", &mut report);
+        assert!(report.is_empty());
+
+        let mut bad = rule();
+        bad.recover = Some(80.0);
+        bad.validate("health.alerts[0]", &mut report);
+        let mut both = rule();
+        both.below = Some(10.0);
+        both.validate("health.alerts[1]", &mut report);
+        let mut no_target = rule();
+        no_target.metric = Metric::DirSizeMib;
+        no_target.validate("health.alerts[2]", &mut report);
+        let mut extra_target = rule();
+        extra_target.target = Some("/".to_string());
+        extra_target.validate("health.alerts[3]", &mut report);
+
+        assert_eq!(
+            report.paths(),
+            [
+                "health.alerts[0].recover",
+                "health.alerts[1]",
+                "health.alerts[2].target",
+                "health.alerts[3].target"
+            ]
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! 測定データ履歴のグラフ。
+//!
+//! [downsample](super::history::downsample) の集計結果から、
+//! CPU・メモリ・ディスクの使用率と CPU 温度の折れ線グラフを縦に並べた PNG 画像を作る。
+
+use super::history::{Metric, Summary};
+use anyhow::{Result, bail};
+use chrono::{DateTime, Duration, Local};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use utils::graphics::FontRenderer;
+use utils::graphics::chart::{self, LineChart, Series};
+
+/// 画像の横幅。
+const WIDTH: u32 = 800;
+/// グラフ1つ分の高さ。
+const CHART_HEIGHT: u32 = 280;
+
+const COLOR_CPU: (u8, u8, u8) = (0xe0, 0x40, 0x40);
+const COLOR_MEM: (u8, u8, u8) = (0x30, 0x70, 0xd0);
+const COLOR_DISK: (u8, u8, u8) = (0x30, 0xa0, 0x50);
+const COLOR_TEMP_AVG: (u8, u8, u8) = (0xe0, 0x80, 0x20);
+const COLOR_TEMP_MAX: (u8, u8, u8) = (0xa0, 0x20, 0x20);
+
+/// グラフの期間。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChartRange {
+    /// 直近 24 時間。10 分ごとに集計する。
+    #[default]
+    Day,
+    /// 直近 7 日間。1 時間ごとに集計する。
+    Week,
+}
+
+impl ChartRange {
+    /// 名前。コマンドやクエリの値と同じ。
+    pub fn name(self) -> &'static str {
+        match self {
+            ChartRange::Day => "24h",
+            ChartRange::Week => "7d",
+        }
+    }
+
+    /// 期間の長さ。
+    pub fn duration(self) -> Duration {
+        match self {
+            ChartRange::Day => Duration::hours(24),
+            ChartRange::Week => Duration::days(7),
+        }
+    }
+
+    /// 集計間隔。
+    pub fn step(self) -> Duration {
+        match self {
+            ChartRange::Day => Duration::minutes(10),
+            ChartRange::Week => Duration::hours(1),
+        }
+    }
+
+    /// x 軸の目盛り間隔 (時間) と時刻の書式。
+    fn x_label(self) -> (i64, &'static str) {
+        match self {
+            ChartRange::Day => (6, "%H:%M"),
+            ChartRange::Week => (24, "%m/%d"),
+        }
+    }
+}
+
+impl FromStr for ChartRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "24h" => Ok(ChartRange::Day),
+            "7d" => Ok(ChartRange::Week),
+            _ => bail!("invalid range: {s} (24h or 7d)"),
+        }
+    }
+}
+
+/// `to` までの `range` の期間のグラフを PNG で描画する。
+///
+/// `summaries` は `to - range.duration()` から `range.step()` ごとに集計したもの。
+pub fn render(
+    font: &FontRenderer,
+    range: ChartRange,
+    to: DateTime<Local>,
+    summaries: &[Summary],
+) -> Result<Vec<u8>> {
+    let from = to - range.duration();
+    let points = |f: &dyn Fn(&Summary) -> Option<f64>| series_points(summaries, from, range, f);
+    let avg = |metric: Metric| move |s: &Summary| s.stats.get(&metric).map(|stat| stat.avg);
+    let used =
+        |metric: Metric| move |s: &Summary| s.stats.get(&metric).map(|stat| 100.0 - stat.avg);
+
+    let x_range = (-range.duration().num_minutes() as f64 / 60.0, 0.0);
+    let (label_step, label_fmt) = range.x_label();
+    let x_labels: Vec<_> = (0..=range.duration().num_hours() / label_step)
+        .map(|i| {
+            let hours = -i * label_step;
+            let time = to + Duration::hours(hours);
+            (hours as f64, time.format(label_fmt).to_string())
+        })
+        .collect();
+
+    let usage = LineChart {
+        title: format!("Usage (%) - last {}", range.name()),
+        width: WIDTH,
+        height: CHART_HEIGHT,
+        x_range,
+        y_range: Some((0.0, 100.0)),
+        x_labels: x_labels.clone(),
+        series: vec![
+            Series {
+                label: "CPU".to_string(),
+                color: COLOR_CPU,
+                points: points(&avg(Metric::CpuPercent)),
+            },
+            Series {
+                label: "Memory".to_string(),
+                color: COLOR_MEM,
+                points: points(&used(Metric::MemAvailPercent)),
+            },
+            Series {
+                label: "Disk".to_string(),
+                color: COLOR_DISK,
+                points: points(&used(Metric::DiskAvailPercent)),
+            },
+        ],
+    };
+    let temp = LineChart {
+        title: format!("CPU Temp ('C) - last {}", range.name()),
+        width: WIDTH,
+        height: CHART_HEIGHT,
+        x_range,
+        y_range: None,
+        x_labels,
+        series: vec![
+            Series {
+                label: "Avg".to_string(),
+                color: COLOR_TEMP_AVG,
+                points: points(&avg(Metric::CpuTemp)),
+            },
+            Series {
+                label: "Max".to_string(),
+                color: COLOR_TEMP_MAX,
+                points: points(&|s| s.stats.get(&Metric::CpuTemp).map(|stat| stat.max)),
+            },
+        ],
+    };
+
+    chart::render_column_png(&[usage, temp], font)
+}
+
+/// 全区間について (終了時刻からの時間, 値) を返す。
+///
+/// x は区間の中央とする。データのない区間は None となり、グラフの線が切れる。
+fn series_points(
+    summaries: &[Summary],
+    from: DateTime<Local>,
+    range: ChartRange,
+    f: &dyn Fn(&Summary) -> Option<f64>,
+) -> Vec<(f64, Option<f64>)> {
+    let step = range.step();
+    let count = range.duration().num_seconds() / step.num_seconds();
+    let to = from + range.duration();
+    let by_start: BTreeMap<_, _> = summaries.iter().map(|s| (s.start, s)).collect();
+
+    (0..count)
+        .map(|i| {
+            let start = from + step * i as i32;
+            let mid = start + step / 2;
+            let x = (mid - to).num_seconds() as f64 / 3600.0;
+            (x, by_start.get(&start).and_then(|s| f(s)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysmod::health::history::Stat;
+    use chrono::TimeZone;
+
+    fn summary(start: DateTime<Local>, temp: f64) -> Summary {
+        let stat = Stat {
+            min: temp,
+            avg: temp,
+            max: temp,
+        };
+        Summary {
+            start,
+            count: 1,
+            stats: [(Metric::CpuTemp, stat)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!("24h".parse::<ChartRange>().unwrap(), ChartRange::Day);
+        assert_eq!("7d".parse::<ChartRange>().unwrap(), ChartRange::Week);
+        assert!("1y".parse::<ChartRange>().is_err());
+        for range in [ChartRange::Day, ChartRange::Week] {
+            assert_eq!(range.name().parse::<ChartRange>().unwrap(), range);
+        }
+    }
+
+    #[test]
+    fn points() {
+        let to = Local.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+        let from = to - Duration::hours(24);
+        let summaries = [
+            summary(from, 40.0),
+            summary(from + Duration::minutes(20), 50.0),
+        ];
+        let temp = |s: &Summary| s.stats.get(&Metric::CpuTemp).map(|stat| stat.avg);
+        let points = series_points(&summaries, from, ChartRange::Day, &temp);
+
+        let minutes = |x: f64| (x * 60.0).round() as i64;
+        assert_eq!(points.len(), 144);
+        // 区間の中央
+        assert_eq!(minutes(points[0].0), -24 * 60 + 5);
+        assert_eq!(points[0].1, Some(40.0));
+        // データのない区間
+        assert_eq!(points[1].1, None);
+        assert_eq!(points[2].1, Some(50.0));
+        assert_eq!(minutes(points[143].0), -5);
+    }
+}
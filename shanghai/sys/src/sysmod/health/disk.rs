@@ -0,0 +1,219 @@
+//! ディスク使用量の測定。
+//!
+//! マウントポイントごとの容量と inode 数を statvfs(3) で、
+//! アプリが書き込むディレクトリごとの使用量をディレクトリの走査で測定する。
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::mem::MaybeUninit;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// マウントポイントの使用量。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MountInfo {
+    /// マウントポイント (設定値)。
+    pub mount: String,
+    /// 容量 (GiB)。
+    pub total_gib: f64,
+    /// 一般ユーザが利用可能なサイズ (GiB)。
+    pub avail_gib: f64,
+    /// inode 総数。
+    pub total_inodes: u64,
+    /// 一般ユーザが利用可能な inode 数。
+    pub avail_inodes: u64,
+}
+
+impl MountInfo {
+    /// 利用可能サイズの割合 (%)。
+    pub fn avail_percent(&self) -> Option<f64> {
+        (self.total_gib > 0.0).then(|| 100.0 * self.avail_gib / self.total_gib)
+    }
+
+    /// 利用可能 inode の割合 (%)。
+    ///
+    /// inode 数の概念がないファイルシステムでは None。
+    pub fn inode_avail_percent(&self) -> Option<f64> {
+        (self.total_inodes > 0).then(|| 100.0 * self.avail_inodes as f64 / self.total_inodes as f64)
+    }
+}
+
+/// ディレクトリの使用量。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirUsage {
+    /// 表示名。アラートルールの対象指定にも使う。
+    pub name: String,
+    /// ディスク上で使用しているサイズ (MiB)。
+    pub size_mib: f64,
+}
+
+/// `path` を含むファイルシステムの [MountInfo] を statvfs(3) で取得する。
+pub fn get_mount_info(path: &str) -> Result<MountInfo> {
+    let cpath = CString::new(path)?;
+    let mut buf = MaybeUninit::<libc::statvfs>::zeroed();
+    // SAFETY: cpath は NUL 終端文字列、buf は statvfs 構造体の領域
+    let ret = unsafe { libc::statvfs(cpath.as_ptr(), buf.as_mut_ptr()) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error()).with_context(|| format!("statvfs: {path}"));
+    }
+    // SAFETY: 成功時は書き込まれている
+    let st = unsafe { buf.assume_init() };
+
+    // 32 bit 環境では型が異なる
+    #[allow(clippy::unnecessary_cast)]
+    let (frsize, blocks, bavail, files, favail) = (
+        st.f_frsize as u64,
+        st.f_blocks as u64,
+        st.f_bavail as u64,
+        st.f_files as u64,
+        st.f_favail as u64,
+    );
+
+    Ok(MountInfo {
+        mount: path.to_string(),
+        total_gib: (blocks * frsize) as f64 / GIB,
+        avail_gib: (bavail * frsize) as f64 / GIB,
+        total_inodes: files,
+        avail_inodes: favail,
+    })
+}
+
+/// `path` がマウントポイントならば true を返す。
+///
+/// 親ディレクトリとデバイスが異なるかで判定する。
+/// アンマウントされたマウントポイントの statvfs は親のファイルシステムの値になってしまうため。
+pub fn is_mount_point(path: &Path) -> Result<bool> {
+    let meta = std::fs::metadata(path).with_context(|| format!("{}", path.display()))?;
+    let parent = path.join("..");
+    let parent_meta =
+        std::fs::metadata(&parent).with_context(|| format!("{}", parent.display()))?;
+
+    // ルートは親も自分自身
+    Ok(meta.dev() != parent_meta.dev() || meta.ino() == parent_meta.ino())
+}
+
+/// マウントポイントのリストについて [MountInfo] を測定する。
+///
+/// 測定できなかったものやマウントされていないものはエラーメッセージと共に返す。
+pub fn get_mounts(mounts: &[String]) -> (Vec<MountInfo>, Vec<String>) {
+    let mut result = Vec::new();
+    let mut errors = Vec::new();
+    for mount in mounts {
+        let info = is_mount_point(Path::new(mount)).and_then(|is_mp| {
+            anyhow::ensure!(is_mp, "not mounted");
+            get_mount_info(mount)
+        });
+        match info {
+            Ok(info) => result.push(info),
+            Err(e) => errors.push(format!("{mount}: {e:#}")),
+        }
+    }
+
+    (result, errors)
+}
+
+/// `path` 以下のファイルがディスク上で使用しているサイズ (バイト) を `du` と同様に数える。
+///
+/// シンボリックリンクはたどらない。
+/// 走査中に削除されたファイルは無視する。
+pub fn dir_size(path: &Path) -> Result<u64> {
+    let mut total = 0;
+    let mut stack = vec![PathBuf::from(path)];
+    while let Some(dir) = stack.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && dir != path => continue,
+            Err(e) => return Err(e).with_context(|| format!("read_dir: {}", dir.display())),
+        };
+        for entry in entries {
+            let entry = entry?;
+            // シンボリックリンクをたどらない
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if meta.is_dir() {
+                stack.push(entry.path());
+            }
+            // st_blocks は 512 バイト単位
+            total += meta.blocks() * 512;
+        }
+    }
+
+    Ok(total)
+}
+
+/// (表示名, パス) のリストについて [DirUsage] を測定する。
+///
+/// ブロッキング処理なので非同期タスクからは spawn_blocking で呼ぶこと。
+/// 測定できなかったディレクトリはエラーメッセージと共に返す。
+pub fn get_dir_usage(dirs: &[(String, PathBuf)]) -> (Vec<DirUsage>, Vec<String>) {
+    let mut result = Vec::new();
+    let mut errors = Vec::new();
+    for (name, path) in dirs {
+        match dir_size(path) {
+            Ok(size) => result.push(DirUsage {
+                name: name.clone(),
+                size_mib: size as f64 / MIB,
+            }),
+            Err(e) => errors.push(format!("{name}: {e:#}")),
+        }
+    }
+
+    (result, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount() {
+        let info = get_mount_info("/").unwrap();
+        assert_eq!(info.mount, "/");
+        assert!(info.total_gib > 0.0);
+        assert!(info.avail_gib <= info.total_gib);
+        assert!(info.avail_inodes <= info.total_inodes);
+
+        assert!(get_mount_info("/not/exist/dir").is_err());
+        assert!(get_mount_info("a\0b").is_err());
+
+        assert!(is_mount_point(Path::new("/")).unwrap());
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(!is_mount_point(tmp.path()).unwrap());
+
+        let mounts = vec!["/".to_string(), tmp.path().to_str().unwrap().to_string()];
+        let (result, errors) = get_mounts(&mounts);
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].mount, "/");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].ends_with("not mounted"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        std::fs::create_dir(tmp.path().join("sub")).unwrap();
+        std::fs::write(tmp.path().join("a.bin"), vec![1u8; 10000]).unwrap();
+        std::fs::write(tmp.path().join("sub/b.bin"), vec![1u8; 20000]).unwrap();
+
+        let size = dir_size(tmp.path()).unwrap();
+        // ブロック単位で確保されるので少なくともファイルサイズ以上
+        assert!(size >= 30000, "{size}");
+
+        let dirs = vec![
+            ("tmp".to_string(), tmp.path().to_path_buf()),
+            ("none".to_string(), tmp.path().join("none")),
+        ];
+        let (usage, errors) = get_dir_usage(&dirs);
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].name, "tmp");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("none: "));
+    }
+}
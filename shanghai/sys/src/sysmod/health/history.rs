@@ -0,0 +1,708 @@
+//! ヘルスチェック履歴の永続化。
+//!
+//! 測定データを1日1ファイル `<dir>/YYYY-MM-DD.jsonl` に1行1エントリの JSON で追記する。
+//! ファイル名の日付はローカル時刻による。
+//! 保存日数を過ぎたファイルは、日付が変わった後の最初の書き込み時にファイル単位で削除する。
+//!
+//! 任意の時間範囲の読み出し ([HistoryStore::query]) と、
+//! 一定間隔ごとの最小/平均/最大値への集計 ([downsample]) を提供する。
+
+use super::disk::{DirUsage, MountInfo};
+use super::procfs::{LoadAvg, NetRate, ProcessInfo};
+use super::{CpuInfo, DiskInfo, HistoryEntry, MemInfo, ThrottleFlags};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, TimeZone};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// 履歴ファイルの拡張子。
+const FILE_EXT: &str = "jsonl";
+
+/// 履歴から取り出せる値の種類。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// CPU 使用率 (%)。
+    CpuPercent,
+    /// CPU 温度 (℃)。
+    CpuTemp,
+    /// 利用可能メモリ量 (MiB)。
+    MemAvailMib,
+    /// 利用可能メモリの割合 (%)。
+    MemAvailPercent,
+    /// 利用可能ディスクサイズ (GiB)。
+    DiskAvailGib,
+    /// 利用可能ディスクの割合 (%)。
+    DiskAvailPercent,
+    /// 低電圧状態ならば 1、そうでなければ 0。
+    UnderVoltage,
+    /// スロットリング中ならば 1、そうでなければ 0。
+    Throttled,
+    /// 対象マウントポイントの利用可能サイズ (GiB)。
+    MountAvailGib,
+    /// 対象マウントポイントの利用可能サイズの割合 (%)。
+    MountAvailPercent,
+    /// 対象マウントポイントの利用可能 inode の割合 (%)。
+    InodeAvailPercent,
+    /// 対象ディレクトリの使用量 (MiB)。
+    DirSizeMib,
+    /// 1 分間のロードアベレージ。
+    Load1,
+    /// 5 分間のロードアベレージ。
+    Load5,
+    /// 15 分間のロードアベレージ。
+    Load15,
+    /// 自プロセスの物理メモリ使用量 (MiB)。
+    ProcessRssMib,
+    /// 自プロセスのスレッド数。
+    ProcessThreads,
+    /// 自プロセスのファイルディスクリプタ数。
+    ProcessFds,
+    /// 対象インタフェースの受信速度 (bytes/s)。
+    NetRxBytesPerSec,
+    /// 対象インタフェースの送信速度 (bytes/s)。
+    NetTxBytesPerSec,
+}
+
+impl Metric {
+    /// 対象の指定が不要なもの。
+    pub const ALL: [Metric; 14] = [
+        Metric::CpuPercent,
+        Metric::CpuTemp,
+        Metric::MemAvailMib,
+        Metric::MemAvailPercent,
+        Metric::DiskAvailGib,
+        Metric::DiskAvailPercent,
+        Metric::UnderVoltage,
+        Metric::Throttled,
+        Metric::Load1,
+        Metric::Load5,
+        Metric::Load15,
+        Metric::ProcessRssMib,
+        Metric::ProcessThreads,
+        Metric::ProcessFds,
+    ];
+
+    /// 名前。設定や JSON のキーと同じ。
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::CpuPercent => "cpu_percent",
+            Metric::CpuTemp => "cpu_temp",
+            Metric::MemAvailMib => "mem_avail_mib",
+            Metric::MemAvailPercent => "mem_avail_percent",
+            Metric::DiskAvailGib => "disk_avail_gib",
+            Metric::DiskAvailPercent => "disk_avail_percent",
+            Metric::UnderVoltage => "under_voltage",
+            Metric::Throttled => "throttled",
+            Metric::MountAvailGib => "mount_avail_gib",
+            Metric::MountAvailPercent => "mount_avail_percent",
+            Metric::InodeAvailPercent => "inode_avail_percent",
+            Metric::DirSizeMib => "dir_size_mib",
+            Metric::Load1 => "load1",
+            Metric::Load5 => "load5",
+            Metric::Load15 => "load15",
+            Metric::ProcessRssMib => "process_rss_mib",
+            Metric::ProcessThreads => "process_threads",
+            Metric::ProcessFds => "process_fds",
+            Metric::NetRxBytesPerSec => "net_rx_bytes_per_sec",
+            Metric::NetTxBytesPerSec => "net_tx_bytes_per_sec",
+        }
+    }
+
+    /// 対象 (マウントポイント、ディレクトリ名またはインタフェース名) の指定が必要ならば true。
+    pub fn has_target(self) -> bool {
+        matches!(
+            self,
+            Metric::MountAvailGib
+                | Metric::MountAvailPercent
+                | Metric::InodeAvailPercent
+                | Metric::DirSizeMib
+                | Metric::NetRxBytesPerSec
+                | Metric::NetTxBytesPerSec
+        )
+    }
+
+    /// `entry` から値を取り出す。取得できなかった値は None。
+    ///
+    /// 対象の指定が必要なものは常に None。
+    pub fn value(self, entry: &HistoryEntry) -> Option<f64> {
+        self.value_of(entry, None)
+    }
+
+    /// `entry` から `target` の値を取り出す。取得できなかった値は None。
+    ///
+    /// `target` は [Self::has_target] の場合のみ使う。
+    pub fn value_of(self, entry: &HistoryEntry, target: Option<&str>) -> Option<f64> {
+        let mount = || {
+            entry
+                .mounts
+                .iter()
+                .find(|info| Some(info.mount.as_str()) == target)
+        };
+        let net = || {
+            entry
+                .net
+                .iter()
+                .find(|rate| Some(rate.name.as_str()) == target)
+        };
+        let percent = |value: f64, total: f64| (total > 0.0).then(|| 100.0 * value / total);
+        let flag = |flag: ThrottleFlags| {
+            entry
+                .throttle
+                .map(|throttle| if throttle.contains(flag) { 1.0 } else { 0.0 })
+        };
+        match self {
+            // 0.0..=1.0 の比率で記録されている
+            Metric::CpuPercent => Some(100.0 * entry.cpu_info.cpu_percent_total),
+            Metric::CpuTemp => entry.cpu_info.temp,
+            Metric::MemAvailMib => Some(entry.mem_info.avail_mib),
+            Metric::MemAvailPercent => percent(entry.mem_info.avail_mib, entry.mem_info.total_mib),
+            Metric::DiskAvailGib => Some(entry.disk_info.avail_gib),
+            Metric::DiskAvailPercent => {
+                percent(entry.disk_info.avail_gib, entry.disk_info.total_gib)
+            }
+            Metric::UnderVoltage => flag(ThrottleFlags::UNDER_VOLTAGE),
+            Metric::Throttled => flag(ThrottleFlags::THROTTLED),
+            Metric::MountAvailGib => mount().map(|info| info.avail_gib),
+            Metric::MountAvailPercent => mount().and_then(|info| info.avail_percent()),
+            Metric::InodeAvailPercent => mount().and_then(|info| info.inode_avail_percent()),
+            Metric::DirSizeMib => entry
+                .dirs
+                .iter()
+                .find(|dir| Some(dir.name.as_str()) == target)
+                .map(|dir| dir.size_mib),
+            Metric::Load1 => entry.load.map(|load| load.load1),
+            Metric::Load5 => entry.load.map(|load| load.load5),
+            Metric::Load15 => entry.load.map(|load| load.load15),
+            Metric::ProcessRssMib => entry.process.map(|process| process.rss_mib),
+            Metric::ProcessThreads => entry.process.map(|process| process.threads as f64),
+            Metric::ProcessFds => entry.process.map(|process| process.fds as f64),
+            Metric::NetRxBytesPerSec => net().map(|rate| rate.rx_bytes_per_sec),
+            Metric::NetTxBytesPerSec => net().map(|rate| rate.tx_bytes_per_sec),
+        }
+    }
+}
+
+/// 区間内の集計値。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stat {
+    pub min: f64,
+    pub avg: f64,
+    pub max: f64,
+}
+
+/// [downsample] の1区間。
+#[derive(Debug, Clone)]
+pub struct Summary {
+    /// 区間の開始時刻。
+    pub start: DateTime<Local>,
+    /// 区間内のエントリ数。
+    pub count: usize,
+    /// メトリクスごとの集計値。区間内に値が1つもないものは含まない。
+    pub stats: BTreeMap<Metric, Stat>,
+}
+
+/// 時刻順の `entries` を `from` から `step` ごとの区間に分けて集計する。
+///
+/// `from` より前のエントリと、エントリが1つもない区間は結果に含まない。
+pub fn downsample(entries: &[HistoryEntry], from: DateTime<Local>, step: Duration) -> Vec<Summary> {
+    let step_ms = step.num_milliseconds().max(1);
+
+    let mut result: Vec<Summary> = Vec::new();
+    let mut sums: BTreeMap<Metric, (Stat, usize)> = BTreeMap::new();
+    let flush = |result: &mut Vec<Summary>, sums: &mut BTreeMap<Metric, (Stat, usize)>| {
+        if let Some(last) = result.last_mut() {
+            last.stats = std::mem::take(sums)
+                .into_iter()
+                .map(|(metric, (stat, n))| {
+                    let avg = stat.avg / n as f64;
+                    (metric, Stat { avg, ..stat })
+                })
+                .collect();
+        }
+    };
+
+    for entry in entries {
+        let elapsed = (entry.timestamp - from).num_milliseconds();
+        if elapsed < 0 {
+            continue;
+        }
+        let start = from + Duration::milliseconds(elapsed / step_ms * step_ms);
+        if result.last().is_none_or(|last| last.start != start) {
+            flush(&mut result, &mut sums);
+            result.push(Summary {
+                start,
+                count: 0,
+                stats: BTreeMap::new(),
+            });
+        }
+        result.last_mut().unwrap().count += 1;
+
+        for metric in Metric::ALL {
+            let Some(value) = metric.value(entry) else {
+                continue;
+            };
+            // 集計中は avg に合計を入れておく
+            let (stat, n) = sums.entry(metric).or_insert((
+                Stat {
+                    min: value,
+                    avg: 0.0,
+                    max: value,
+                },
+                0,
+            ));
+            stat.min = stat.min.min(value);
+            stat.max = stat.max.max(value);
+            stat.avg += value;
+            *n += 1;
+        }
+    }
+    flush(&mut result, &mut sums);
+
+    result
+}
+
+/// ファイル上の1行。
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    /// UNIX 時刻 (秒)。
+    t: i64,
+    cpu: CpuInfo,
+    mem: MemInfo,
+    disk: DiskInfo,
+    /// [ThrottleFlags] のビット。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    throttle: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    mounts: Vec<MountInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    dirs: Vec<DirUsage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    net: Vec<NetRate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    load: Option<LoadAvg>,
+    /// 起動からの秒数。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uptime: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    process: Option<ProcessInfo>,
+}
+
+impl From<&HistoryEntry> for Record {
+    fn from(entry: &HistoryEntry) -> Self {
+        Self {
+            t: entry.timestamp.timestamp(),
+            cpu: entry.cpu_info,
+            mem: entry.mem_info,
+            disk: entry.disk_info,
+            throttle: entry.throttle.map(|flags| flags.bits()),
+            mounts: entry.mounts.clone(),
+            dirs: entry.dirs.clone(),
+            net: entry.net.clone(),
+            load: entry.load,
+            uptime: entry.uptime_secs,
+            process: entry.process,
+        }
+    }
+}
+
+impl Record {
+    fn into_entry(self) -> Option<HistoryEntry> {
+        let timestamp = Local.timestamp_opt(self.t, 0).single()?;
+
+        Some(HistoryEntry {
+            timestamp,
+            cpu_info: self.cpu,
+            mem_info: self.mem,
+            disk_info: self.disk,
+            throttle: self.throttle.map(ThrottleFlags::from_bits_truncate),
+            mounts: self.mounts,
+            dirs: self.dirs,
+            net: self.net,
+            load: self.load,
+            uptime_secs: self.uptime,
+            process: self.process,
+        })
+    }
+}
+
+/// 履歴ファイルの保存先。
+#[derive(Clone)]
+pub struct HistoryStore {
+    /// 保存ディレクトリ。
+    dir: PathBuf,
+    /// 保存日数。今日を含む。
+    days: u32,
+    /// 最後に書き込んだ日付。変わったらローテーションする。
+    last_date: Option<NaiveDate>,
+}
+
+impl HistoryStore {
+    pub fn new(dir: impl Into<PathBuf>, days: u32) -> Self {
+        Self {
+            dir: dir.into(),
+            days,
+            last_date: None,
+        }
+    }
+
+    fn file_path(&self, date: NaiveDate) -> PathBuf {
+        self.dir.join(format!("{}.{FILE_EXT}", date.format("%F")))
+    }
+
+    /// 履歴ファイルを日付順に返す。
+    fn files(&self) -> Result<Vec<(NaiveDate, PathBuf)>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("{}", self.dir.display())),
+        };
+        let mut files = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != FILE_EXT) {
+                continue;
+            }
+            let date = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| NaiveDate::parse_from_str(stem, "%F").ok());
+            if let Some(date) = date {
+                files.push((date, path));
+            }
+        }
+        files.sort();
+
+        Ok(files)
+    }
+
+    /// `entry` を追記する。
+    pub fn append(&mut self, entry: &HistoryEntry) -> Result<()> {
+        let date = entry.timestamp.date_naive();
+        if self.last_date != Some(date) {
+            std::fs::create_dir_all(&self.dir)?;
+            self.rotate(date)?;
+            self.last_date = Some(date);
+        }
+
+        let path = self.file_path(date);
+        let mut line = serde_json::to_string(&Record::from(entry))?;
+        line.push('\n');
+        let mut f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        f.write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// `today` を含めて保存日数より古いファイルを削除し、削除した数を返す。
+    pub fn rotate(&self, today: NaiveDate) -> Result<usize> {
+        let oldest = today - Duration::days(self.days.saturating_sub(1) as i64);
+        let mut count = 0;
+        for (date, path) in self.files()? {
+            if date >= oldest {
+                break;
+            }
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+            info!("[health] history removed: {}", path.display());
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// `from` 以上 `to` 未満のエントリを時刻順に返す。
+    pub fn query(&self, from: DateTime<Local>, to: DateTime<Local>) -> Result<Vec<HistoryEntry>> {
+        let (first, last) = (from.date_naive(), to.date_naive());
+        let mut result = Vec::new();
+        for (date, path) in self.files()? {
+            if date < first || date > last {
+                continue;
+            }
+            let entries = read_file(&path)?;
+            result.extend(
+                entries
+                    .into_iter()
+                    .filter(|entry| from <= entry.timestamp && entry.timestamp < to),
+            );
+        }
+        // 時刻が戻された場合に備える
+        result.sort_by_key(|entry| entry.timestamp);
+
+        Ok(result)
+    }
+
+    /// 新しいものから最大 `max` 件を時刻順に返す。起動時の復元用。
+    pub fn load_latest(&self, max: usize) -> Result<Vec<HistoryEntry>> {
+        let mut chunks = Vec::new();
+        let mut total = 0;
+        for (_date, path) in self.files()?.into_iter().rev() {
+            if total >= max {
+                break;
+            }
+            let entries = read_file(&path)?;
+            total += entries.len();
+            chunks.push(entries);
+        }
+        let mut result: Vec<_> = chunks.into_iter().rev().flatten().collect();
+        result.sort_by_key(|entry| entry.timestamp);
+        let skip = result.len().saturating_sub(max);
+
+        Ok(result.split_off(skip))
+    }
+}
+
+/// 履歴ファイルを読む。
+///
+/// 書き込み中の電源断等で壊れた行は警告を出して読み飛ばす。
+fn read_file(path: &Path) -> Result<Vec<HistoryEntry>> {
+    let f = std::fs::File::open(path).with_context(|| format!("{}", path.display()))?;
+
+    let mut result = Vec::new();
+    let mut broken = 0;
+    for line in BufReader::new(f).lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Record>(&line)
+            .ok()
+            .and_then(Record::into_entry)
+        {
+            Some(entry) => result.push(entry),
+            None => broken += 1,
+        }
+    }
+    if broken > 0 {
+        warn!(
+            "[health] ignore {broken} broken line(s): {}",
+            path.display()
+        );
+    }
+
+    Ok(result)
+}
+
+/// ロックの外で履歴を読み出すためのスナップショット。
+///
+/// [super::Health::history_reader] で取得する。
+/// ファイルの読み込みと解析はブロッキング処理なので、
+/// 非同期タスクからは spawn_blocking で呼ぶこと。
+#[derive(Clone)]
+pub enum HistoryReader {
+    /// 保存ファイルから読む。
+    Store(HistoryStore),
+    /// 取得時点のメモリ上の履歴。
+    Memory(Vec<HistoryEntry>),
+}
+
+impl HistoryReader {
+    /// `from` 以上 `to` 未満の測定データを時刻順に返す。
+    pub fn query(&self, from: DateTime<Local>, to: DateTime<Local>) -> Result<Vec<HistoryEntry>> {
+        match self {
+            Self::Store(store) => store.query(from, to),
+            Self::Memory(entries) => Ok(entries
+                .iter()
+                .filter(|entry| from <= entry.timestamp && entry.timestamp < to)
+                .cloned()
+                .collect()),
+        }
+    }
+
+    /// [Self::query] の結果を `from` から `step` ごとに集計して返す。
+    pub fn query_summary(
+        &self,
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+        step: Duration,
+    ) -> Result<Vec<Summary>> {
+        let entries = self.query(from, to)?;
+
+        Ok(downsample(&entries, from, step))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: DateTime<Local>, cpu: f64, temp: Option<f64>) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            cpu_info: CpuInfo {
+                cpu_percent_total: cpu,
+                temp,
+            },
+            mem_info: MemInfo {
+                total_mib: 1000.0,
+                avail_mib: 250.0,
+            },
+            disk_info: DiskInfo {
+                total_gib: 0.0,
+                avail_gib: 0.0,
+            },
+            throttle: None,
+            mounts: Vec::new(),
+            dirs: Vec::new(),
+            net: Vec::new(),
+            load: None,
+            uptime_secs: None,
+            process: None,
+        }
+    }
+
+    fn dt(s: &str) -> DateTime<Local> {
+        let naive = chrono::NaiveDateTime::parse_from_str(s, "%F %T").unwrap();
+        Local.from_local_datetime(&naive).unwrap()
+    }
+
+    #[test]
+    fn store() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("history");
+        let mut store = HistoryStore::new(&dir, 2);
+
+        assert!(store.load_latest(10).unwrap().is_empty());
+
+        for s in [
+            "2024-01-01 23:58:00",
+            "2024-01-01 23:59:00",
+            "2024-01-02 00:00:00",
+            "2024-01-02 00:01:00",
+        ] {
+            store.append(&entry(dt(s), 0.5, Some(40.0))).unwrap();
+        }
+        // 壊れた行は読み飛ばす
+        let mut f = OpenOptions::new()
+            .append(true)
+            .open(dir.join("2024-01-02.jsonl"))
+            .unwrap();
+        f.write_all(b"{\"t\":17").unwrap();
+
+        let result = store
+            .query(dt("2024-01-01 23:59:00"), dt("2024-01-02 00:01:00"))
+            .unwrap();
+        let times: Vec<_> = result.iter().map(|e| e.timestamp).collect();
+        assert_eq!(
+            times,
+            [dt("2024-01-01 23:59:00"), dt("2024-01-02 00:00:00")]
+        );
+        assert_eq!(result[0].cpu_info.temp, Some(40.0));
+
+        let latest = store.load_latest(3).unwrap();
+        assert_eq!(latest.len(), 3);
+        assert_eq!(latest[0].timestamp, dt("2024-01-01 23:59:00"));
+        assert_eq!(latest[2].timestamp, dt("2024-01-02 00:01:00"));
+
+        // 2 日分を超えたものは日付が変わったときに消える
+        let mut throttled = entry(dt("2024-01-03 00:00:00"), 0.5, None);
+        throttled.throttle = Some(ThrottleFlags::UNDER_VOLTAGE | ThrottleFlags::PAST_THROTTLED);
+        throttled.mounts = vec![MountInfo {
+            mount: "/mnt/usb".to_string(),
+            total_gib: 100.0,
+            avail_gib: 10.0,
+            total_inodes: 1000,
+            avail_inodes: 500,
+        }];
+        throttled.dirs = vec![DirUsage {
+            name: "upload".to_string(),
+            size_mib: 12.5,
+        }];
+        throttled.net = vec![NetRate {
+            name: "eth0".to_string(),
+            rx_bytes_per_sec: 2048.0,
+            tx_bytes_per_sec: 512.0,
+        }];
+        throttled.load = Some(LoadAvg {
+            load1: 1.5,
+            load5: 1.0,
+            load15: 0.5,
+        });
+        throttled.uptime_secs = Some(3600.0);
+        throttled.process = Some(ProcessInfo {
+            rss_mib: 50.0,
+            threads: 9,
+            fds: 12,
+        });
+        store.append(&throttled).unwrap();
+        assert!(!dir.join("2024-01-01.jsonl").exists());
+        assert!(dir.join("2024-01-02.jsonl").exists());
+        assert!(dir.join("2024-01-03.jsonl").exists());
+
+        let result = store
+            .query(dt("2024-01-03 00:00:00"), dt("2024-01-04 00:00:00"))
+            .unwrap();
+        assert_eq!(result[0].throttle, throttled.throttle);
+        assert_eq!(Metric::UnderVoltage.value(&result[0]), Some(1.0));
+        assert_eq!(Metric::Throttled.value(&result[0]), Some(0.0));
+        assert_eq!(result[0].mounts, throttled.mounts);
+        assert_eq!(result[0].dirs, throttled.dirs);
+        assert_eq!(result[0].net, throttled.net);
+        assert_eq!(result[0].load, throttled.load);
+        assert_eq!(result[0].uptime_secs, Some(3600.0));
+        assert_eq!(result[0].process, throttled.process);
+        let e = &result[0];
+        assert_eq!(
+            Metric::MountAvailPercent.value_of(e, Some("/mnt/usb")),
+            Some(10.0)
+        );
+        assert_eq!(
+            Metric::InodeAvailPercent.value_of(e, Some("/mnt/usb")),
+            Some(50.0)
+        );
+        assert_eq!(Metric::MountAvailGib.value_of(e, Some("/")), None);
+        assert_eq!(Metric::DirSizeMib.value_of(e, Some("upload")), Some(12.5));
+        assert_eq!(Metric::DirSizeMib.value(e), None);
+        assert_eq!(Metric::Load5.value(e), Some(1.0));
+        assert_eq!(Metric::ProcessFds.value(e), Some(12.0));
+        assert_eq!(
+            Metric::NetRxBytesPerSec.value_of(e, Some("eth0")),
+            Some(2048.0)
+        );
+        assert_eq!(Metric::NetTxBytesPerSec.value_of(e, Some("wlan0")), None);
+        // 記録されていない
+        assert_eq!(Metric::Load1.value(&latest[0]), None);
+    }
+
+    #[test]
+    fn summary() {
+        let from = dt("2024-01-01 00:00:00");
+        let entries = [
+            entry(dt("2023-12-31 23:59:00"), 0.9, None),
+            entry(dt("2024-01-01 00:00:00"), 0.1, Some(40.0)),
+            entry(dt("2024-01-01 00:04:00"), 0.3, None),
+            entry(dt("2024-01-01 00:12:00"), 0.2, Some(50.0)),
+        ];
+        let result = downsample(&entries, from, Duration::minutes(5));
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].start, from);
+        assert_eq!(result[0].count, 2);
+        let cpu = result[0].stats[&Metric::CpuPercent];
+        assert!((cpu.min - 10.0).abs() < 1e-9);
+        assert!((cpu.avg - 20.0).abs() < 1e-9);
+        assert!((cpu.max - 30.0).abs() < 1e-9);
+        assert_eq!(
+            result[0].stats[&Metric::CpuTemp],
+            Stat {
+                min: 40.0,
+                avg: 40.0,
+                max: 40.0
+            }
+        );
+        assert_eq!(result[0].stats[&Metric::MemAvailPercent].avg, 25.0);
+        // total が 0 のものは割合を出せない
+        assert!(!result[0].stats.contains_key(&Metric::DiskAvailPercent));
+
+        assert_eq!(result[1].start, dt("2024-01-01 00:10:00"));
+        assert_eq!(result[1].count, 1);
+    }
+}
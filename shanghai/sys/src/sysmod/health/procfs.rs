@@ -0,0 +1,290 @@
+//! procfs からのネットワーク、ロードアベレージ、稼働時間、プロセス情報の取得。
+//!
+//! 解析部分はファイルの内容を受け取る関数に分けてあり、
+//! テストでは実機から取得したファイル (`res/test/proc/`) を使う。
+
+use anyhow::{Context, Result, anyhow, ensure};
+use serde::{Deserialize, Serialize};
+
+/// ネットワークインタフェースの累積転送量。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetDevCounter {
+    /// インタフェース名。
+    pub name: String,
+    /// 受信バイト数。
+    pub rx_bytes: u64,
+    /// 送信バイト数。
+    pub tx_bytes: u64,
+}
+
+/// ネットワークインタフェースの転送速度。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetRate {
+    /// インタフェース名。
+    pub name: String,
+    /// 受信速度 (bytes/s)。
+    pub rx_bytes_per_sec: f64,
+    /// 送信速度 (bytes/s)。
+    pub tx_bytes_per_sec: f64,
+}
+
+/// ロードアベレージ。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoadAvg {
+    /// 1 分平均。
+    pub load1: f64,
+    /// 5 分平均。
+    pub load5: f64,
+    /// 15 分平均。
+    pub load15: f64,
+}
+
+/// このプロセス自身のリソース使用量。
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    /// 物理メモリ使用量 (MiB)。
+    pub rss_mib: f64,
+    /// スレッド数。
+    pub threads: u32,
+    /// オープンしているファイルディスクリプタ数。
+    pub fds: u32,
+}
+
+/// _/proc/net/dev_ を解析する。
+///
+/// ```text
+/// Inter-|   Receive                                                |  Transmit
+///  face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets ...
+///     lo: 48213576  153211    0    0    0     0          0         0 48213576  153211 ...
+/// ```
+pub fn parse_net_dev(text: &str) -> Result<Vec<NetDevCounter>> {
+    let mut result = Vec::new();
+    // 先頭 2 行はヘッダ
+    for line in text.lines().skip(2) {
+        let (name, values) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("parse error: {line}"))?;
+        let values: Vec<&str> = values.split_ascii_whitespace().collect();
+        ensure!(values.len() >= 16, "parse error: {line}");
+        result.push(NetDevCounter {
+            name: name.trim().to_string(),
+            rx_bytes: values[0].parse()?,
+            tx_bytes: values[8].parse()?,
+        });
+    }
+
+    Ok(result)
+}
+
+/// 前回と今回の [NetDevCounter] から `secs` 秒間の転送速度を計算する。
+///
+/// ループバックと、前回存在しなかったインタフェースは含まない。
+/// カウンタが減少した場合 (インタフェースの再作成等) も含まない。
+pub fn net_rates(prev: &[NetDevCounter], cur: &[NetDevCounter], secs: f64) -> Vec<NetRate> {
+    if secs <= 0.0 {
+        return Vec::new();
+    }
+    cur.iter()
+        .filter(|c| c.name != "lo")
+        .filter_map(|c| {
+            let p = prev.iter().find(|p| p.name == c.name)?;
+            let rx = c.rx_bytes.checked_sub(p.rx_bytes)?;
+            let tx = c.tx_bytes.checked_sub(p.tx_bytes)?;
+            Some(NetRate {
+                name: c.name.clone(),
+                rx_bytes_per_sec: rx as f64 / secs,
+                tx_bytes_per_sec: tx as f64 / secs,
+            })
+        })
+        .collect()
+}
+
+/// _/proc/loadavg_ を解析する。
+///
+/// `0.52 0.38 0.31 2/213 28716`
+pub fn parse_loadavg(text: &str) -> Result<LoadAvg> {
+    let mut iter = text.split_ascii_whitespace();
+    let mut next = || -> Result<f64> {
+        Ok(iter
+            .next()
+            .ok_or_else(|| anyhow!("parse error: {text}"))?
+            .parse()?)
+    };
+
+    Ok(LoadAvg {
+        load1: next()?,
+        load5: next()?,
+        load15: next()?,
+    })
+}
+
+/// _/proc/uptime_ を解析し、起動からの秒数を返す。
+///
+/// `1234567.89 4712345.67` (稼働時間, 全コアのアイドル時間の合計)
+pub fn parse_uptime(text: &str) -> Result<f64> {
+    let uptime = text
+        .split_ascii_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("parse error: {text}"))?;
+
+    Ok(uptime.parse()?)
+}
+
+/// _/proc/self/status_ を解析し、(RSS (MiB), スレッド数) を返す。
+///
+/// `VmRSS:` と `Threads:` の行を使う。
+pub fn parse_status(text: &str) -> Result<(f64, u32)> {
+    let mut rss_kib = None;
+    let mut threads = None;
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key {
+            "VmRSS" => {
+                let value = value.trim().trim_end_matches("kB").trim_end();
+                rss_kib = Some(value.parse::<u64>()?);
+            }
+            "Threads" => threads = Some(value.trim().parse::<u32>()?),
+            _ => (),
+        }
+    }
+    let rss_kib = rss_kib.ok_or_else(|| anyhow!("VmRSS not found"))?;
+    let threads = threads.ok_or_else(|| anyhow!("Threads not found"))?;
+
+    Ok((rss_kib as f64 / 1024.0, threads))
+}
+
+/// [NetDevCounter] を取得する。
+pub async fn get_net_dev() -> Result<Vec<NetDevCounter>> {
+    let text = tokio::fs::read_to_string("/proc/net/dev").await?;
+    parse_net_dev(&text).context("/proc/net/dev")
+}
+
+/// [LoadAvg] を取得する。
+pub async fn get_loadavg() -> Result<LoadAvg> {
+    let text = tokio::fs::read_to_string("/proc/loadavg").await?;
+    parse_loadavg(&text).context("/proc/loadavg")
+}
+
+/// 起動からの秒数を取得する。
+pub async fn get_uptime() -> Result<f64> {
+    let text = tokio::fs::read_to_string("/proc/uptime").await?;
+    parse_uptime(&text).context("/proc/uptime")
+}
+
+/// [ProcessInfo] を取得する。
+pub async fn get_process_info() -> Result<ProcessInfo> {
+    let text = tokio::fs::read_to_string("/proc/self/status").await?;
+    let (rss_mib, threads) = parse_status(&text).context("/proc/self/status")?;
+
+    let mut fds: u32 = 0;
+    let mut dir = tokio::fs::read_dir("/proc/self/fd").await?;
+    while dir.next_entry().await?.is_some() {
+        fds += 1;
+    }
+    // read_dir 自身が使っている分を除く
+    let fds = fds.saturating_sub(1);
+
+    Ok(ProcessInfo {
+        rss_mib,
+        threads,
+        fds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/res/test/proc/",
+                $name
+            ))
+        };
+    }
+
+    #[test]
+    fn net_dev() {
+        let list = parse_net_dev(fixture!("net_dev")).unwrap();
+        let names: Vec<_> = list.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["lo", "eth0", "wlan0"]);
+        assert_eq!(list[1].rx_bytes, 8823649112);
+        assert_eq!(list[1].tx_bytes, 1592238731);
+
+        assert!(parse_net_dev("header\nheader\n  eth0: 1 2 3\n").is_err());
+
+        let mut prev = list.clone();
+        // 再作成されてカウンタが戻った
+        prev[2].rx_bytes = 500;
+        let mut cur = list;
+        cur[0].rx_bytes += 1000;
+        cur[1].rx_bytes += 6000;
+        cur[1].tx_bytes += 3000;
+        // 前回は存在しなかった
+        cur.push(NetDevCounter {
+            name: "usb0".to_string(),
+            rx_bytes: 100,
+            tx_bytes: 100,
+        });
+        let rates = net_rates(&prev, &cur, 60.0);
+        assert_eq!(
+            rates,
+            [NetRate {
+                name: "eth0".to_string(),
+                rx_bytes_per_sec: 100.0,
+                tx_bytes_per_sec: 50.0,
+            }]
+        );
+        assert!(net_rates(&prev, &cur, 0.0).is_empty());
+    }
+
+    #[test]
+    fn loadavg() {
+        let load = parse_loadavg(fixture!("loadavg")).unwrap();
+        assert_eq!(
+            load,
+            LoadAvg {
+                load1: 0.52,
+                load5: 0.38,
+                load15: 0.31,
+            }
+        );
+        assert!(parse_loadavg("0.52 0.38").is_err());
+    }
+
+    #[test]
+    fn uptime() {
+        let uptime = parse_uptime(fixture!("uptime")).unwrap();
+        assert_eq!(uptime, 1234567.89);
+        assert!(parse_uptime("").is_err());
+    }
+
+    #[test]
+    fn status() {
+        let (rss_mib, threads) = parse_status(fixture!("self_status")).unwrap();
+        assert_eq!(rss_mib, 51328.0 / 1024.0);
+        assert_eq!(threads, 9);
+        assert!(parse_status("Name:\tshanghai\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn current() {
+        let list = get_net_dev().await.unwrap();
+        assert!(list.iter().any(|c| c.name == "lo"));
+
+        let load = get_loadavg().await.unwrap();
+        assert!(load.load1 >= 0.0);
+
+        assert!(get_uptime().await.unwrap() > 0.0);
+
+        let info = get_process_info().await.unwrap();
+        assert!(info.rss_mib > 0.0);
+        assert!(info.threads >= 1);
+        // stdin, stdout, stderr
+        assert!(info.fds >= 3, "{info:?}");
+    }
+}
@@ -5,14 +5,19 @@
 mod github;
 mod index;
 mod line_hook;
+mod metrics;
 mod priv_camera;
+mod priv_health;
 mod priv_index;
+mod priv_task;
+mod priv_uptime;
 mod tmp;
 mod upload;
 
 use super::SystemModule;
+use crate::config::{self, ValidationReport};
 use crate::taskserver;
-use crate::{config, taskserver::Control};
+use crate::taskserver::Control;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, Responder, http::header::ContentType};
 use actix_web::{HttpResponseBuilder, web};
@@ -57,6 +62,13 @@ pub struct HttpConfig {
     ghhook_secret: String,
     /// LINE webhook 機能を有効化する。パスは /_rootpath_/line/。
     line_hook_enabled: bool,
+    /// Prometheus 形式のメトリクスを有効化する。パスは /_rootpath_/_privpath_/metrics。
+    ///
+    /// マウントポイントやネットワークインタフェース、タスクの状態を含むので管理者専用ページに置く。
+    /// 認証は他の管理者専用ページと同じくフロントサーバで行うこと。
+    /// [Self::priv_enabled] も有効にする必要がある。
+    #[serde(default)]
+    metrics_enabled: bool,
 }
 
 impl Default for HttpConfig {
@@ -73,6 +85,38 @@ impl Default for HttpConfig {
             ghhook_enabled: false,
             ghhook_secret: "".to_string(),
             line_hook_enabled: false,
+            metrics_enabled: false,
+        }
+    }
+}
+
+impl HttpConfig {
+    /// ベース URL。
+    pub(crate) fn server_url(&self) -> &str {
+        &self.server_url
+    }
+
+    /// アップロードされたファイルの保存場所。アップローダが無効ならば None。
+    pub(crate) fn upload_dir(&self) -> Option<&str> {
+        (self.enabled && self.upload_enabled).then_some(self.upload_dir.as_str())
+    }
+
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        if !self.enabled {
+            return;
+        }
+        if self.upload_enabled {
+            report.check_dir_writable("http.upload_dir", &self.upload_dir);
+        }
+        if self.metrics_enabled && !self.priv_enabled {
+            report.add("http.metrics_enabled", "http.priv_enabled is required");
+        }
+        if self.ghhook_enabled {
+            report.require(
+                "http.ghhook_secret",
+                &self.ghhook_secret,
+                "http.ghhook_enabled = true",
+            );
         }
     }
 }
@@ -167,6 +211,7 @@ async fn http_main_task(ctrl: Control) -> Result<()> {
     .disable_signals()
     .bind(("127.0.0.1", port))?
     .run();
+    ctrl.report_ready("http");
 
     // シャットダウンが来たらハンドルでサーバを停止するタスクを生成
     let ctrl_for_stop = Arc::clone(&ctrl);
@@ -190,6 +235,7 @@ impl SystemModule for HttpServer {
     fn on_start(&mut self, ctrl: &Control) {
         info!("[http] on_start");
         if self.config.enabled {
+            ctrl.expect_ready("http");
             taskserver::spawn_oneshot_task(ctrl, "http", http_main_task);
         }
     }
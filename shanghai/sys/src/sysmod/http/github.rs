@@ -2,10 +2,9 @@
 //!
 //! <https://docs.github.com/ja/developers/webhooks-and-events/webhooks>
 
-use std::sync::Arc;
-
 use super::WebResult;
-use crate::taskserver::{self, Control};
+use crate::taskserver::Control;
+use crate::taskserver::event::GitPushed;
 use actix_web::{HttpRequest, HttpResponse, Responder, http::header::ContentType, web};
 use anyhow::{Result, anyhow};
 use log::{error, info};
@@ -111,32 +110,17 @@ async fn index_post(req: HttpRequest, body: String, ctrl: web::Data<Control>) ->
     }
     info!("verify request body OK");
 
-    process_post(&ctrl, &body).await;
+    process_post(&ctrl, &body);
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::plaintext())
         .body(""))
 }
 
-async fn process_post(ctrl: &Control, json_body: &str) {
-    match create_msg_from_json(json_body) {
-        Ok(msg) => {
-            let ctrl_clone = Arc::clone(ctrl);
-            let msg_clone = msg.clone();
-            taskserver::spawn_oneshot_fn(ctrl, "http-github-tweet", async move {
-                ctrl_clone
-                    .sysmods()
-                    .twitter
-                    .lock()
-                    .await
-                    .tweet(&msg_clone)
-                    .await
-            });
-
-            let ctrl_clone = ctrl.clone();
-            taskserver::spawn_oneshot_fn(ctrl, "http-github-discord", async move {
-                ctrl_clone.sysmods().discord.lock().await.say(&msg).await
-            });
+fn process_post(ctrl: &Control, json_body: &str) {
+    match parse_push_event(json_body) {
+        Ok(event) => {
+            ctrl.publish(event);
         }
         Err(why) => {
             error!("{why:#?}");
@@ -144,7 +128,7 @@ async fn process_post(ctrl: &Control, json_body: &str) {
     }
 }
 
-fn create_msg_from_json(json_body: &str) -> Result<String> {
+fn parse_push_event(json_body: &str) -> Result<GitPushed> {
     let root: Value = serde_json::from_str(json_body)?;
 
     let refstr = root["ref"]
@@ -154,7 +138,10 @@ fn create_msg_from_json(json_body: &str) -> Result<String> {
         .as_str()
         .ok_or_else(|| anyhow!("compare not found"))?;
 
-    Ok(format!("Pushed to Github: {refstr}\n{compare}"))
+    Ok(GitPushed {
+        refname: refstr.to_string(),
+        compare: compare.to_string(),
+    })
 }
 
 #[cfg(test)]
@@ -167,7 +154,7 @@ mod tests {
             env!("CARGO_MANIFEST_DIR"),
             "/res/test/github/simplepush.json"
         ));
-        let msg = create_msg_from_json(jsonstr).unwrap();
+        let msg = parse_push_event(jsonstr).unwrap().message();
 
         assert_eq!(
             msg,
@@ -181,7 +168,7 @@ mod tests {
             env!("CARGO_MANIFEST_DIR"),
             "/res/test/github/emptybranch.json"
         ));
-        let msg = create_msg_from_json(jsonstr).unwrap();
+        let msg = parse_push_event(jsonstr).unwrap().message();
 
         assert_eq!(
             msg,
@@ -195,7 +182,7 @@ mod tests {
             env!("CARGO_MANIFEST_DIR"),
             "/res/test/github/deletebranch.json"
         ));
-        let msg = create_msg_from_json(jsonstr).unwrap();
+        let msg = parse_push_event(jsonstr).unwrap().message();
 
         assert_eq!(
             msg,
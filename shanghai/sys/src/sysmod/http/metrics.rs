@@ -0,0 +1,447 @@
+//! Prometheus 形式のメトリクス。
+//!
+//! [HttpConfig::metrics_enabled] が有効ならば管理者専用ページの GET /metrics で
+//! text exposition format を返す。
+//! 取得に失敗した値はログを出して省略する。
+//!
+//! <https://prometheus.io/docs/instrumenting/exposition_formats/>
+
+use super::{HttpConfig, upload};
+use crate::sysmod::health::{self, ThrottleFlags, procfs};
+use crate::taskserver::Control;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpResponse, web};
+use log::warn;
+use std::fmt::Write;
+
+/// メトリクス名の接頭辞。
+const PREFIX: &str = "shanghai";
+
+/// text exposition format の出力先。
+#[derive(Default)]
+struct MetricsWriter {
+    buf: String,
+}
+
+impl MetricsWriter {
+    /// `# HELP` と `# TYPE` を出力する。
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        writeln!(self.buf, "# HELP {PREFIX}_{name} {help}").unwrap();
+        writeln!(self.buf, "# TYPE {PREFIX}_{name} {kind}").unwrap();
+    }
+
+    /// 値を1つ出力する。
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        write!(self.buf, "{PREFIX}_{name}").unwrap();
+        if !labels.is_empty() {
+            let labels: Vec<_> = labels
+                .iter()
+                .map(|(k, v)| format!("{k}=\"{}\"", escape_label(v)))
+                .collect();
+            write!(self.buf, "{{{}}}", labels.join(",")).unwrap();
+        }
+        writeln!(self.buf, " {value}").unwrap();
+    }
+
+    /// ラベルのない gauge を1つ出力する。
+    fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, "gauge", help);
+        self.sample(name, &[], value);
+    }
+}
+
+/// ラベル値のエスケープ。
+fn escape_label(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[actix_web::get("/metrics")]
+async fn index_get(ctrl: web::Data<Control>, config: web::Data<HttpConfig>) -> HttpResponse {
+    let mut w = MetricsWriter::default();
+    write_system(&mut w).await;
+    write_disks(&mut w, &ctrl).await;
+    write_procfs(&mut w).await;
+    write_openai(&mut w, &ctrl).await;
+    write_storage(&mut w, &ctrl, &config).await;
+    write_tasks(&mut w, &ctrl);
+
+    HttpResponse::Ok()
+        .content_type(ContentType(
+            "text/plain; version=0.0.4; charset=utf-8".parse().unwrap(),
+        ))
+        .body(w.buf)
+}
+
+/// CPU、メモリ、ディスク、温度、周波数、スロットリング。
+async fn write_system(w: &mut MetricsWriter) {
+    match health::get_cpu_info().await {
+        Ok(info) => w.gauge(
+            "cpu_usage_ratio",
+            "CPU usage of all cores since boot (0.0-1.0).",
+            info.cpu_percent_total,
+        ),
+        Err(e) => warn!("[metrics] cpu info: {e:#}"),
+    }
+    match health::get_mem_info().await {
+        Ok(info) => {
+            w.gauge(
+                "memory_total_bytes",
+                "Total memory size.",
+                info.total_mib * 1024.0 * 1024.0,
+            );
+            w.gauge(
+                "memory_available_bytes",
+                "Available memory size.",
+                info.avail_mib * 1024.0 * 1024.0,
+            );
+        }
+        Err(e) => warn!("[metrics] mem info: {e:#}"),
+    }
+    match health::get_disk_info().await {
+        Ok(info) => {
+            let gib = 1024.0 * 1024.0 * 1024.0;
+            w.gauge(
+                "disk_total_bytes",
+                "Total size of the root filesystem.",
+                info.total_gib * gib,
+            );
+            w.gauge(
+                "disk_available_bytes",
+                "Available size of the root filesystem.",
+                info.avail_gib * gib,
+            );
+        }
+        Err(e) => warn!("[metrics] disk info: {e:#}"),
+    }
+    match health::get_cpu_temp().await {
+        Ok(Some(temp)) => w.gauge("cpu_temperature_celsius", "CPU temperature.", temp),
+        Ok(None) => {}
+        Err(e) => warn!("[metrics] cpu temp: {e:#}"),
+    }
+    match health::get_current_freq().await {
+        Ok(Some(freq)) => w.gauge("cpu_frequency_hertz", "Current ARM clock.", freq as f64),
+        Ok(None) => {}
+        Err(e) => warn!("[metrics] cpu freq: {e:#}"),
+    }
+    match health::get_throttle_status().await {
+        Ok(Some(flags)) => write_throttle(w, flags),
+        Ok(None) => {}
+        Err(e) => warn!("[metrics] throttle: {e:#}"),
+    }
+}
+
+/// 直近の health 測定によるマウントポイントとディレクトリの使用量。
+async fn write_disks(w: &mut MetricsWriter, ctrl: &Control) {
+    let (mounts, dirs) = {
+        let health = ctrl.sysmods().health.lock().await;
+        match health.latest() {
+            Some(entry) => (entry.mounts.clone(), entry.dirs.clone()),
+            None => return,
+        }
+    };
+    let gib = 1024.0 * 1024.0 * 1024.0;
+
+    w.family(
+        "mount_total_bytes",
+        "gauge",
+        "Total size of the mount point.",
+    );
+    for info in &mounts {
+        w.sample(
+            "mount_total_bytes",
+            &[("mount", &info.mount)],
+            info.total_gib * gib,
+        );
+    }
+    w.family(
+        "mount_available_bytes",
+        "gauge",
+        "Available size of the mount point.",
+    );
+    for info in &mounts {
+        w.sample(
+            "mount_available_bytes",
+            &[("mount", &info.mount)],
+            info.avail_gib * gib,
+        );
+    }
+    w.family(
+        "mount_inodes_available",
+        "gauge",
+        "Available inodes of the mount point.",
+    );
+    for info in &mounts {
+        w.sample(
+            "mount_inodes_available",
+            &[("mount", &info.mount)],
+            info.avail_inodes as f64,
+        );
+    }
+    w.family("dir_bytes", "gauge", "Disk usage of the directory.");
+    for dir in &dirs {
+        w.sample(
+            "dir_bytes",
+            &[("dir", &dir.name)],
+            dir.size_mib * 1024.0 * 1024.0,
+        );
+    }
+}
+
+/// ネットワーク転送量、ロードアベレージ、稼働時間、自プロセスの情報。
+async fn write_procfs(w: &mut MetricsWriter) {
+    match procfs::get_net_dev().await {
+        Ok(list) => {
+            w.family(
+                "network_receive_bytes_total",
+                "counter",
+                "Received bytes of the interface.",
+            );
+            for c in &list {
+                w.sample(
+                    "network_receive_bytes_total",
+                    &[("interface", &c.name)],
+                    c.rx_bytes as f64,
+                );
+            }
+            w.family(
+                "network_transmit_bytes_total",
+                "counter",
+                "Transmitted bytes of the interface.",
+            );
+            for c in &list {
+                w.sample(
+                    "network_transmit_bytes_total",
+                    &[("interface", &c.name)],
+                    c.tx_bytes as f64,
+                );
+            }
+        }
+        Err(e) => warn!("[metrics] net dev: {e:#}"),
+    }
+    match procfs::get_loadavg().await {
+        Ok(load) => {
+            w.family("load_average", "gauge", "System load average.");
+            for (period, value) in [("1m", load.load1), ("5m", load.load5), ("15m", load.load15)] {
+                w.sample("load_average", &[("period", period)], value);
+            }
+        }
+        Err(e) => warn!("[metrics] loadavg: {e:#}"),
+    }
+    match procfs::get_uptime().await {
+        Ok(uptime) => w.gauge("uptime_seconds", "System uptime.", uptime),
+        Err(e) => warn!("[metrics] uptime: {e:#}"),
+    }
+    match procfs::get_process_info().await {
+        Ok(info) => {
+            w.gauge(
+                "process_resident_memory_bytes",
+                "Resident memory size of this process.",
+                info.rss_mib * 1024.0 * 1024.0,
+            );
+            w.gauge(
+                "process_threads",
+                "Threads of this process.",
+                info.threads as f64,
+            );
+            w.gauge(
+                "process_open_fds",
+                "Open file descriptors of this process.",
+                info.fds as f64,
+            );
+        }
+        Err(e) => warn!("[metrics] process: {e:#}"),
+    }
+}
+
+/// [ThrottleFlags] の各ビットを 0/1 で出力する。
+fn write_throttle(w: &mut MetricsWriter, flags: ThrottleFlags) {
+    w.family(
+        "throttle_flag",
+        "gauge",
+        "vcgencmd get_throttled flags (1 if set).",
+    );
+    for (name, flag) in ThrottleFlags::all().iter_names() {
+        let value = if flags.contains(flag) { 1.0 } else { 0.0 };
+        w.sample(
+            "throttle_flag",
+            &[("flag", &name.to_ascii_lowercase())],
+            value,
+        );
+    }
+}
+
+/// OpenAI API の使用量とレートリミット。
+async fn write_openai(w: &mut MetricsWriter, ctrl: &Control) {
+    let (usage, rate_limit) = {
+        let ai = ctrl.sysmods().openai.lock().await;
+        (ai.token_usage(), ai.get_expected_rate_limit())
+    };
+
+    w.family(
+        "openai_requests_total",
+        "counter",
+        "OpenAI Response API calls since start.",
+    );
+    w.sample("openai_requests_total", &[], usage.requests as f64);
+    w.family(
+        "openai_tokens_total",
+        "counter",
+        "OpenAI tokens used since start.",
+    );
+    for (kind, value) in [
+        ("input", usage.input_tokens),
+        ("cached_input", usage.cached_tokens),
+        ("output", usage.output_tokens),
+        ("reasoning", usage.reasoning_tokens),
+    ] {
+        w.sample("openai_tokens_total", &[("kind", kind)], value as f64);
+    }
+
+    if let Some(exp) = rate_limit {
+        w.family(
+            "openai_ratelimit_limit",
+            "gauge",
+            "OpenAI rate limit per period.",
+        );
+        w.sample(
+            "openai_ratelimit_limit",
+            &[("kind", "requests")],
+            exp.limit_requests as f64,
+        );
+        w.sample(
+            "openai_ratelimit_limit",
+            &[("kind", "tokens")],
+            exp.limit_tokens as f64,
+        );
+        w.family(
+            "openai_ratelimit_remaining",
+            "gauge",
+            "Expected remaining OpenAI rate limit.",
+        );
+        w.sample(
+            "openai_ratelimit_remaining",
+            &[("kind", "requests")],
+            exp.remaining_requests as f64,
+        );
+        w.sample(
+            "openai_ratelimit_remaining",
+            &[("kind", "tokens")],
+            exp.remaining_tokens as f64,
+        );
+    }
+}
+
+/// カメラの保存領域とアップロードディレクトリのサイズ。
+async fn write_storage(w: &mut MetricsWriter, ctrl: &Control, config: &HttpConfig) {
+    let stats: Vec<_> = {
+        let camera = ctrl.sysmods().camera.lock().await;
+        let (history, archive) = camera.pic_list();
+        [("history", history), ("archive", archive)]
+            .into_iter()
+            .map(|(storage, list)| {
+                let size: u64 = list.values().map(|entry| entry.total_size).sum();
+                (storage, list.len(), size)
+            })
+            .collect()
+    };
+    w.family(
+        "camera_storage_bytes",
+        "gauge",
+        "Total size of camera pictures.",
+    );
+    for &(storage, _, size) in &stats {
+        w.sample("camera_storage_bytes", &[("storage", storage)], size as f64);
+    }
+    w.family(
+        "camera_storage_pictures",
+        "gauge",
+        "Number of camera pictures.",
+    );
+    for &(storage, count, _) in &stats {
+        w.sample(
+            "camera_storage_pictures",
+            &[("storage", storage)],
+            count as f64,
+        );
+    }
+
+    if config.upload_enabled {
+        match upload::get_disk_usage(&config.upload_dir).await {
+            Ok(size) => w.gauge(
+                "upload_dir_bytes",
+                "Disk usage of the upload directory.",
+                size as f64,
+            ),
+            Err(e) => warn!("[metrics] upload dir: {e:#}"),
+        }
+    }
+}
+
+/// タスクごとの実行回数。
+fn write_tasks(w: &mut MetricsWriter, ctrl: &Control) {
+    let list = ctrl.task_list();
+
+    w.family("task_runs_total", "counter", "Completed runs of the task.");
+    for entry in &list {
+        w.sample(
+            "task_runs_total",
+            &[("task", &entry.name)],
+            entry.run_count as f64,
+        );
+    }
+    w.family(
+        "task_failures_total",
+        "counter",
+        "Runs of the task which ended with an error.",
+    );
+    for entry in &list {
+        w.sample(
+            "task_failures_total",
+            &[("task", &entry.name)],
+            entry.failure_count as f64,
+        );
+    }
+    w.family("task_running", "gauge", "Running instances of the task.");
+    for entry in &list {
+        w.sample(
+            "task_running",
+            &[("task", &entry.name)],
+            entry.running as f64,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format() {
+        let mut w = MetricsWriter::default();
+        w.gauge("cpu_temperature_celsius", "CPU temperature.", 45.5);
+        w.family("task_runs_total", "counter", "Completed runs.");
+        w.sample("task_runs_total", &[("task", "a\"b\\c\nd")], 3.0);
+        write_throttle(
+            &mut w,
+            ThrottleFlags::UNDER_VOLTAGE | ThrottleFlags::PAST_THROTTLED,
+        );
+
+        let lines: Vec<_> = w.buf.lines().collect();
+        assert_eq!(
+            &lines[..5],
+            [
+                "# HELP shanghai_cpu_temperature_celsius CPU temperature.",
+                "# TYPE shanghai_cpu_temperature_celsius gauge",
+                "shanghai_cpu_temperature_celsius 45.5",
+                "# HELP shanghai_task_runs_total Completed runs.",
+                "# TYPE shanghai_task_runs_total counter",
+            ]
+        );
+        assert_eq!(lines[5], r#"shanghai_task_runs_total{task="a\"b\\c\nd"} 3"#);
+        assert!(lines.contains(&r#"shanghai_throttle_flag{flag="under_voltage"} 1"#));
+        assert!(lines.contains(&r#"shanghai_throttle_flag{flag="throttled"} 0"#));
+        assert!(lines.contains(&r#"shanghai_throttle_flag{flag="past_throttled"} 1"#));
+    }
+}
@@ -3,6 +3,7 @@ use crate::sysmod::camera::{PicEntry, TakePicOption, create_thumbnail, take_a_pi
 use crate::sysmod::twitter::LIMIT_PHOTO_COUNT;
 use crate::sysmod::{camera::resize, http::error_resp_msg, twitter::LIMIT_PHOTO_SIZE};
 use crate::taskserver::Control;
+use crate::taskserver::event::PictureTaken;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, Responder, http::header::ContentType, web};
 use anyhow::{Result, anyhow, bail};
@@ -478,9 +479,11 @@ async fn take_post(ctrl: web::Data<Control>) -> WebResult {
     let thumb = create_thumbnail(&pic)?;
 
     let mut camera = ctrl.sysmods().camera.lock().await;
-    camera.push_pic_history(&pic, &thumb).await?;
+    let name = camera.push_pic_history(&pic, &thumb).await?;
     drop(camera);
 
+    ctrl.publish(PictureTaken { name });
+
     let resp = HttpResponse::Ok()
         .content_type(ContentType::jpeg())
         .body(pic);
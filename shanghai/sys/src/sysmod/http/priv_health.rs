@@ -0,0 +1,199 @@
+//! ヘルスチェック履歴 API。
+
+use super::error_resp_msg;
+use crate::sysmod::health::chart::ChartRange;
+use crate::sysmod::health::history::{Metric, Summary};
+use crate::sysmod::health::{Health, HistoryEntry};
+use crate::taskserver::Control;
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpResponse, web};
+use anyhow::{Result, bail};
+use chrono::{DateTime, Duration, Local};
+use log::error;
+use serde::Deserialize;
+use serde_json::{Map, Value, json};
+
+/// 一度に返す最大の期間。
+const MAX_RANGE_DAYS: i64 = 366;
+/// 一度に返す最大の区間数。
+const MAX_POINTS: i64 = 10000;
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    /// 開始時刻 (RFC 3339)。省略時は `to` の 24 時間前。
+    from: Option<String>,
+    /// 終了時刻 (RFC 3339)。省略時は現在時刻。
+    to: Option<String>,
+    /// 集計間隔 (秒)。省略時や 0 の場合は集計しない。
+    #[serde(default)]
+    step: u32,
+}
+
+/// GET /priv/health/history 測定データの履歴を JSON で返す。
+///
+/// `step` を指定すると、その間隔ごとの最小/平均/最大値を返す。
+#[actix_web::get("/health/history")]
+async fn history_get(ctrl: web::Data<Control>, query: web::Query<HistoryQuery>) -> HttpResponse {
+    let (from, to, step) = match parse_query(&query, Local::now()) {
+        Ok(range) => range,
+        Err(e) => return error_resp_msg(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    // ファイルの読み込みは長くかかりうるので、ロックを解放して別スレッドで行う
+    let reader = ctrl.sysmods().health.lock().await.history_reader(from, to);
+    let result = tokio::task::spawn_blocking(move || match step {
+        Some(step) => reader
+            .query_summary(from, to, step)
+            .map(|list| list.iter().map(summary_to_json).collect::<Vec<_>>()),
+        None => reader
+            .query(from, to)
+            .map(|list| list.iter().map(entry_to_json).collect::<Vec<_>>()),
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.into()));
+
+    match result {
+        Ok(list) => HttpResponse::Ok().json(json!({
+            "from": from.to_rfc3339(),
+            "to": to.to_rfc3339(),
+            "step": query.step,
+            "data": list,
+        })),
+        Err(e) => {
+            error!("{e:#}");
+            error_resp_msg(StatusCode::INTERNAL_SERVER_ERROR, "history read error")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ChartQuery {
+    /// `24h` または `7d`。省略時は `24h`。
+    range: Option<String>,
+}
+
+/// GET /priv/health/chart 測定データのグラフを返す。
+///
+/// image/png を返す。
+#[actix_web::get("/health/chart")]
+async fn chart_get(ctrl: web::Data<Control>, query: web::Query<ChartQuery>) -> HttpResponse {
+    let range = match query
+        .range
+        .as_deref()
+        .map(str::parse::<ChartRange>)
+        .transpose()
+    {
+        Ok(range) => range.unwrap_or_default(),
+        Err(e) => return error_resp_msg(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    match Health::render_chart(&ctrl, range).await {
+        Ok(png) => HttpResponse::Ok()
+            .content_type(ContentType::png())
+            .body(png),
+        Err(e) => {
+            error!("{e:#}");
+            error_resp_msg(StatusCode::INTERNAL_SERVER_ERROR, "chart error")
+        }
+    }
+}
+
+/// 範囲と集計間隔を決める。
+fn parse_query(
+    query: &HistoryQuery,
+    now: DateTime<Local>,
+) -> Result<(DateTime<Local>, DateTime<Local>, Option<Duration>)> {
+    let parse = |s: &str| -> Result<DateTime<Local>> {
+        Ok(DateTime::parse_from_rfc3339(s)?.with_timezone(&Local))
+    };
+    let to = query.to.as_deref().map(parse).transpose()?.unwrap_or(now);
+    let from = match query.from.as_deref() {
+        Some(s) => parse(s)?,
+        None => to - Duration::hours(24),
+    };
+    if from >= to {
+        bail!("from must be before to");
+    }
+    if to - from > Duration::days(MAX_RANGE_DAYS) {
+        bail!("range too long (max {MAX_RANGE_DAYS} days)");
+    }
+    let step = (query.step > 0).then(|| Duration::seconds(query.step as i64));
+    if let Some(step) = step
+        && (to - from).num_seconds() / step.num_seconds() > MAX_POINTS
+    {
+        bail!("step too small (max {MAX_POINTS} points)");
+    }
+
+    Ok((from, to, step))
+}
+
+fn entry_to_json(entry: &HistoryEntry) -> Value {
+    let mut obj = Map::new();
+    obj.insert("timestamp".into(), entry.timestamp.to_rfc3339().into());
+    for metric in Metric::ALL {
+        obj.insert(metric.name().into(), json!(metric.value(entry)));
+    }
+    obj.insert("mounts".into(), json!(entry.mounts));
+    obj.insert("dirs".into(), json!(entry.dirs));
+    obj.insert("net".into(), json!(entry.net));
+    obj.insert("uptime_secs".into(), json!(entry.uptime_secs));
+
+    Value::Object(obj)
+}
+
+fn summary_to_json(summary: &Summary) -> Value {
+    let mut obj = Map::new();
+    obj.insert("start".into(), summary.start.to_rfc3339().into());
+    obj.insert("count".into(), summary.count.into());
+    for (metric, stat) in &summary.stats {
+        obj.insert(
+            metric.name().into(),
+            json!({ "min": stat.min, "avg": stat.avg, "max": stat.max }),
+        );
+    }
+
+    Value::Object(obj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(from: Option<&str>, to: Option<&str>, step: u32) -> HistoryQuery {
+        HistoryQuery {
+            from: from.map(|s| s.to_string()),
+            to: to.map(|s| s.to_string()),
+            step,
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let now = DateTime::parse_from_rfc3339("2024-01-02T00:00:00+09:00")
+            .unwrap()
+            .with_timezone(&Local);
+
+        let (from, to, step) = parse_query(&query(None, None, 0), now).unwrap();
+        assert_eq!(to, now);
+        assert_eq!(to - from, Duration::hours(24));
+        assert_eq!(step, None);
+
+        let (from, to, step) = parse_query(
+            &query(
+                Some("2024-01-01T00:00:00+09:00"),
+                Some("2024-01-01T12:00:00+09:00"),
+                300,
+            ),
+            now,
+        )
+        .unwrap();
+        assert_eq!(to - from, Duration::hours(12));
+        assert_eq!(step, Some(Duration::minutes(5)));
+
+        assert!(parse_query(&query(Some("yesterday"), None, 0), now).is_err());
+        assert!(parse_query(&query(Some("2024-01-03T00:00:00+09:00"), None, 0), now).is_err());
+        assert!(parse_query(&query(Some("2020-01-01T00:00:00+09:00"), None, 0), now).is_err());
+        assert!(parse_query(&query(None, None, 1), now).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::BTreeMap;
 
-use super::{HttpConfig, priv_camera};
+use super::{HttpConfig, metrics, priv_camera, priv_health, priv_task, priv_uptime};
 use actix_web::{HttpRequest, HttpResponse, Responder, http::header::ContentType, web};
 use utils::netutil;
 
@@ -18,6 +18,13 @@ pub(super) fn server_config() -> impl Fn(&mut web::ServiceConfig, &HttpConfig) +
         cfg.service(priv_camera::pic_history_get);
         cfg.service(priv_camera::pic_archive_get);
         cfg.service(priv_camera::index_get);
+        cfg.service(priv_task::index_get);
+        cfg.service(priv_health::history_get);
+        cfg.service(priv_health::chart_get);
+        cfg.service(priv_uptime::index_get);
+        if http_config.metrics_enabled {
+            cfg.service(metrics::index_get);
+        }
     }
 }
 
@@ -55,6 +62,18 @@ async fn index_get(req: HttpRequest) -> impl Responder {
 
     <h2>Camera</h2>
     <p><a href="./camera/">Camera Main Page</a></p>
+
+    <h2>Tasks</h2>
+    <p><a href="./task/">Task List</a></p>
+
+    <h2>Health</h2>
+    <p><a href="./health/history?step=300">History (JSON, last 24 hours)</a></p>
+    <p><a href="./health/chart?range=24h">Chart (last 24 hours)</a></p>
+    <p><a href="./health/chart?range=7d">Chart (last 7 days)</a></p>
+    <p><a href="./metrics">Metrics (Prometheus)</a></p>
+
+    <h2>Uptime</h2>
+    <p><a href="./uptime/">Uptime Monitor</a></p>
   </body>
 </html>
 "#,
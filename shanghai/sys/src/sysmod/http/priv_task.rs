@@ -0,0 +1,73 @@
+//! タスク実行状況ページ。
+
+use crate::taskserver::Control;
+use crate::taskserver::registry::{TaskEntry, TaskResult};
+use actix_web::{HttpResponse, Responder, http::header::ContentType, web};
+use chrono::{DateTime, Local};
+use utils::netutil;
+
+/// GET /priv/task/ タスク一覧。
+#[actix_web::get("/task/")]
+async fn index_get(ctrl: web::Data<Control>) -> impl Responder {
+    let list = ctrl.task_list();
+
+    let rows = list.iter().map(create_row).collect::<Vec<_>>().join("\n");
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <title>(Privileged) Tasks</title>
+  </head>
+  <body>
+    <h1>(Privileged) Tasks</h1>
+    <p>Now: {}</p>
+    <table border="1">
+      <tr>
+        <th>Name</th><th>Kind</th><th>Running</th><th>Next Wakeup</th>
+        <th>Last Start</th><th>Last Duration</th><th>Last Result</th>
+        <th>Runs</th><th>Failures</th>
+      </tr>
+{}
+    </table>
+    <h2>Navigation</h2>
+    <p><a href="../">Main Page</a></p>
+  </body>
+</html>
+"#,
+        Local::now().format("%F %T %:z"),
+        rows
+    );
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body)
+}
+
+fn format_dt(dt: Option<DateTime<Local>>) -> String {
+    dt.map_or("-".to_string(), |dt| dt.format("%F %T").to_string())
+}
+
+fn create_row(entry: &TaskEntry) -> String {
+    let duration = entry
+        .last_duration
+        .map_or("-".to_string(), |d| format!("{:.3} s", d.as_secs_f64()));
+    let result = match &entry.last_result {
+        None => "-".to_string(),
+        Some(TaskResult::Success) => "OK".to_string(),
+        Some(TaskResult::Error(msg)) => format!("Error: {}", netutil::html_escape(msg)),
+    };
+
+    format!(
+        "      <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        netutil::html_escape(&entry.name),
+        entry.kind,
+        entry.running,
+        format_dt(entry.next_wakeup),
+        format_dt(entry.last_start),
+        duration,
+        result,
+        entry.run_count,
+        entry.failure_count,
+    )
+}
@@ -0,0 +1,116 @@
+//! 死活監視ページ。
+
+use crate::sysmod::uptime::{TargetConfig, TargetRecord, UptimeEvent};
+use crate::taskserver::Control;
+use actix_web::{HttpResponse, Responder, http::header::ContentType, web};
+use chrono::{DateTime, Duration, Local};
+use utils::netutil;
+
+/// 稼働率を表示する期間 (日)。
+const PERIODS: [i64; 3] = [1, 7, 30];
+
+/// GET /priv/uptime/ 監視対象ごとの状態と稼働率。
+#[actix_web::get("/uptime/")]
+async fn index_get(ctrl: web::Data<Control>) -> impl Responder {
+    let now = Local::now();
+    let (rows, events) = {
+        let uptime = ctrl.sysmods().uptime.lock().await;
+        let rows = uptime
+            .targets()
+            .map(|(target, record)| create_row(target, record, now))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let events = uptime
+            .events()
+            .iter()
+            .rev()
+            .map(create_event_row)
+            .collect::<Vec<_>>()
+            .join("\n");
+        (rows, events)
+    };
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <title>(Privileged) Uptime</title>
+  </head>
+  <body>
+    <h1>(Privileged) Uptime</h1>
+    <p>Now: {}</p>
+    <table border="1">
+      <tr>
+        <th>Name</th><th>Type</th><th>Address</th><th>Status</th><th>Since</th>
+        <th>Last Check</th><th>Latency</th><th>Last Error</th>
+        <th>24h</th><th>7d</th><th>30d</th><th>Avg Latency (24h)</th>
+      </tr>
+{}
+    </table>
+    <h2>Events</h2>
+    <table border="1">
+      <tr><th>Time</th><th>Name</th><th>Status</th><th>Message</th></tr>
+{}
+    </table>
+    <h2>Navigation</h2>
+    <p><a href="../">Main Page</a></p>
+  </body>
+</html>
+"#,
+        now.format("%F %T %:z"),
+        rows,
+        events
+    );
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body)
+}
+
+fn format_dt(dt: Option<DateTime<Local>>) -> String {
+    dt.map_or("-".to_string(), |dt| dt.format("%F %T").to_string())
+}
+
+fn format_percent(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{v:.2} %"))
+}
+
+fn format_latency(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |v| format!("{v:.0} ms"))
+}
+
+fn create_row(target: &TargetConfig, record: &TargetRecord, now: DateTime<Local>) -> String {
+    let last = record.last.as_ref();
+    let uptime = PERIODS
+        .iter()
+        .map(|&days| {
+            let percent = record.history.uptime_percent(now, Duration::days(days));
+            format!("<td>{}</td>", format_percent(percent))
+        })
+        .collect::<String>();
+    let avg_latency = record.history.avg_latency_ms(now, Duration::days(1));
+
+    format!(
+        "      <tr><td>{}</td><td>{:?}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}<td>{}</td></tr>",
+        netutil::html_escape(&target.name),
+        target.kind,
+        netutil::html_escape(&target.address()),
+        record.tracker.status,
+        format_dt(record.tracker.since),
+        format_dt(last.map(|last| last.time)),
+        format_latency(last.map(|last| last.result.latency_ms)),
+        netutil::html_escape(last.map_or("", |last| last.result.message.as_str())),
+        uptime,
+        format_latency(avg_latency),
+    )
+}
+
+fn create_event_row(event: &UptimeEvent) -> String {
+    format!(
+        "      <tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+        event.time.format("%F %T"),
+        netutil::html_escape(&event.target),
+        event.status,
+        netutil::html_escape(&event.message),
+    )
+}
@@ -185,8 +185,10 @@ fn check_file_name(name: Option<&str>) -> Result<&str, ActixError> {
     }
 }
 
-async fn get_disk_usage(dirpath: &str) -> Result<usize> {
-    let mut cmd = Command::new(format!("du -s -B 1 {dirpath}"));
+/// `du` コマンドでディレクトリのディスク使用量 (バイト) を取得する。
+pub(super) async fn get_disk_usage(dirpath: &str) -> Result<usize> {
+    let mut cmd = Command::new("du");
+    cmd.args(["-s", "-B", "1", dirpath]);
     let output = cmd.output().await?;
     ensure!(output.status.success(), "du command failed");
 
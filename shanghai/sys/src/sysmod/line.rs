@@ -2,16 +2,19 @@
 
 #![allow(clippy::identity_op)]
 
-use super::SystemModule;
 use super::openai::{InputContent, ParameterType};
 use super::openai::{
     ParameterElement,
-    chat_history::ChatHistory,
+    chat_history::{ChatHistory, ChatHistorySnapshot},
     function::{self, BasicContext, FuncArgs, FunctionTable},
 };
-use crate::config;
+use super::{ConfigAction, SystemModule};
+use crate::config::{self, ConfigChanges, ValidationReport};
+use crate::dryrun;
+use crate::state;
 use crate::sysmod::openai::{Function, Parameters, function::FUNCTION_TOKEN};
 use crate::sysmod::openai::{OpenAi, OpenAiErrorKind, Role, SearchContextSize, Tool, UserLocation};
+use crate::taskserver::event::Notify;
 use crate::taskserver::{self, Control};
 
 use actix_web::http::header::ContentType;
@@ -27,6 +30,8 @@ use utils::netutil;
 
 /// LINE API タイムアウト。
 const TIMEOUT: Duration = Duration::from_secs(30);
+/// ドライランでフィクスチャがない場合の返信と push の応答。
+const DRY_RUN_RESPONSE: &str = r#"{"sentMessages":[]}"#;
 /// [Message::Text] の最大文字数。
 /// mention 関連でのずれが少し怖いので余裕を持たせる。
 const MSG_SPLIT_LEN: usize = 5000 - 128;
@@ -49,6 +54,21 @@ pub struct LineConfig {
     pub prompt: LinePrompt,
 }
 
+impl LineConfig {
+    /// 機能が有効ならば true を返す。
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        if self.enabled {
+            let reason = "line.enabled = true";
+            report.require("line.token", &self.token, reason);
+            report.require("line.channel_secret", &self.channel_secret, reason);
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LinePrompt {
     /// 最初に一度だけ与えられるシステムメッセージ。
@@ -105,10 +125,22 @@ pub struct Line {
     pub chat_history: Option<ChatHistory>,
     /// [Self::image_buffer] [Self::chat_history] の有効期限。
     pub history_timeout: Option<Instant>,
+    /// 前回終了時に保存された会話履歴。
+    ///
+    /// [Self::chat_history] の初期化時に復元される。
+    saved_history: Option<ChatHistorySnapshot>,
     /// OpenAI function 機能テーブル
     pub func_table: Option<FunctionTable<FunctionContext>>,
 }
 
+/// 再起動をまたいで保持する状態。[state] に保存する。
+#[derive(Default, Serialize, Deserialize)]
+struct LineState {
+    image_buffer: HashMap<String, Vec<InputContent>>,
+    chat_history: Option<ChatHistorySnapshot>,
+    history_timeout: Option<chrono::DateTime<chrono::Local>>,
+}
+
 impl Line {
     const TASKQUEUE_SIZE: usize = 8;
 
@@ -118,15 +150,17 @@ impl Line {
         let config = config::get(|cfg| cfg.line.clone());
         let client = Client::builder().timeout(TIMEOUT).build()?;
         let (tx, rx) = tokio::sync::mpsc::channel(Self::TASKQUEUE_SIZE);
+        let state: LineState = state::load("line").unwrap_or_default();
 
         Ok(Self {
             config,
             client,
             tx,
             rx: Some(rx),
-            image_buffer: Default::default(),
+            image_buffer: state.image_buffer,
             chat_history: None,
-            history_timeout: None,
+            history_timeout: state.history_timeout.map(state::datetime_to_instant),
+            saved_history: state.chat_history,
             func_table: None,
         })
     }
@@ -163,6 +197,11 @@ impl Line {
         info!("[line] {reserved:6} reserved");
         info!("[line] {:6} chat history", chat_history.usage().1);
 
+        if let Some(saved) = self.saved_history.take() {
+            chat_history.restore(saved);
+            info!("[line] {} chat history restored", chat_history.len());
+        }
+
         let mut func_table = FunctionTable::new(Arc::clone(ctrl), Some("line"));
         func_table.register_basic_functions();
         register_camera(&mut func_table);
@@ -188,6 +227,21 @@ impl Line {
     }
 }
 
+/// [Notify] イベントを受けて通知する。
+async fn on_notify(ctrl: Control, event: Notify) -> Result<()> {
+    if event.line_to.is_empty() {
+        return Ok(());
+    }
+    let line = ctrl.sysmods().line.lock().await;
+    if !line.config.is_enabled() {
+        info!("[line] disabled - msg: {}", event.text);
+        return Ok(());
+    }
+    line.push_message(&event.line_to, &event.text).await?;
+
+    Ok(())
+}
+
 /// WebHook Request 処理タスク。
 async fn line_worker(
     ctrl: Control,
@@ -263,6 +317,36 @@ impl SystemModule for Line {
         taskserver::spawn_oneshot_fn(ctrl, "line-worker", async move {
             line_worker(ctrl_for_worker, rx).await
         });
+        taskserver::spawn_event_task(ctrl, "line-notify", on_notify);
+    }
+
+    /// ワーカータスクと受信画像はそのまま、設定のみ置き換える。
+    /// プロンプトが変わる可能性があるため会話履歴は作り直す (内容は引き継ぐ)。
+    fn on_config_changed(&mut self, _ctrl: &Control, changes: &ConfigChanges) -> ConfigAction {
+        self.config = changes.new.line.clone();
+        if let Some(chat_history) = self.chat_history.take() {
+            self.saved_history = Some(chat_history.snapshot());
+        }
+        self.func_table = None;
+
+        ConfigAction::Applied
+    }
+
+    fn on_stop(&mut self, _ctrl: &Control) {
+        info!("[line] on_stop");
+        // 未初期化ならば保存されていたものをそのまま引き継ぐ
+        let chat_history = match &self.chat_history {
+            Some(chat_history) => Some(chat_history.snapshot()),
+            None => self.saved_history.clone(),
+        };
+        let state = LineState {
+            image_buffer: self.image_buffer.clone(),
+            chat_history,
+            history_timeout: self.history_timeout.map(state::instant_to_datetime),
+        };
+        if let Err(e) = state::save("line", &state) {
+            error!("[line] save state failed: {e:#}");
+        }
     }
 }
 
@@ -972,7 +1056,6 @@ impl Line {
     /// <https://developers.line.biz/ja/reference/messaging-api/#send-push-message>
     ///
     /// <https://developers.line.biz/ja/docs/messaging-api/text-character-count/>
-    #[allow(unused)]
     pub async fn push_message(&self, to: &str, text: &str) -> Result<ReplyResp> {
         ensure!(!text.is_empty(), "text must not be empty");
 
@@ -1051,6 +1134,9 @@ impl Line {
     where
         T: for<'de> Deserialize<'de>,
     {
+        if let Some(bin) = dryrun::read("line", url) {
+            return Ok(serde_json::from_slice(&bin)?);
+        }
         info!("[line] GET {url}");
         let token = &self.config.token;
         let resp = netutil::send_with_retry(|| {
@@ -1068,6 +1154,12 @@ impl Line {
         T: Serialize + Debug,
         R: for<'de> Deserialize<'de>,
     {
+        if dryrun::is_enabled() {
+            // 応答は送信したメッセージのリストのみのため、なければ空とする
+            let bin = dryrun::write("line", "POST", url, body)
+                .unwrap_or_else(|| DRY_RUN_RESPONSE.as_bytes().to_vec());
+            return Ok(serde_json::from_slice(&bin)?);
+        }
         info!("[line] POST {url} {body:?}");
         let token = &self.config.token;
         let resp = netutil::send_with_retry(|| {
@@ -1082,6 +1174,9 @@ impl Line {
     }
 
     async fn get_auth_bin(&self, url: &str) -> Result<(StatusCode, Vec<u8>)> {
+        if let Some(bin) = dryrun::read("line", url) {
+            return Ok((StatusCode::OK, bin));
+        }
         info!("[line] GET {url}");
         let token = &self.config.token;
 
@@ -1,6 +1,6 @@
 //! OpenAI API.
 
-mod basicfuncs;
+pub(crate) mod basicfuncs;
 pub mod chat_history;
 pub mod function;
 
@@ -11,7 +11,8 @@ use std::sync::LazyLock;
 use std::time::{Duration, Instant, SystemTime};
 
 use super::SystemModule;
-use crate::config;
+use crate::config::{self, ValidationReport};
+use crate::dryrun;
 use crate::taskserver::Control;
 use base64::{Engine, engine::general_purpose};
 use utils::netutil::{self, HttpStatusError};
@@ -42,6 +43,26 @@ const URL_RESPONSE: &str = "https://api.openai.com/v1/responses";
 const URL_IMAGE_GEN: &str = "https://api.openai.com/v1/images/generations";
 const URL_AUDIO_SPEECH: &str = "https://api.openai.com/v1/audio/speech";
 
+/// ドライランでフィクスチャがない場合の Response API の応答。
+const DRY_RUN_RESPONSE: &str = r#"{
+    "id": "resp_dry_run",
+    "created_at": 0,
+    "model": "dry-run",
+    "output": [{
+        "type": "message",
+        "id": "msg_dry_run",
+        "role": "assistant",
+        "content": [{ "type": "output_text", "text": "(dry-run)" }]
+    }],
+    "usage": {
+        "input_tokens": 0,
+        "input_tokens_details": { "cached_tokens": 0 },
+        "output_tokens": 0,
+        "output_tokens_details": { "reasoning_tokens": 0 },
+        "total_tokens": 0
+    }
+}"#;
+
 /// [OfflineModelInfo] + [OnlineModelInfo]
 #[derive(Debug, Clone, Serialize)]
 pub struct ModelInfo {
@@ -176,6 +197,31 @@ pub struct ExpectedRateLimit {
     pub remaining_tokens: u32,
 }
 
+/// 起動してからの API 使用量の累計。
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokenUsage {
+    /// Response API の呼び出し回数。
+    pub requests: u64,
+    /// 入力トークン数。
+    pub input_tokens: u64,
+    /// そのうちキャッシュされていたトークン数。
+    pub cached_tokens: u64,
+    /// 出力トークン数。
+    pub output_tokens: u64,
+    /// そのうち推論に使われたトークン数。
+    pub reasoning_tokens: u64,
+}
+
+impl TokenUsage {
+    fn add(&mut self, usage: &Usage) {
+        self.requests += 1;
+        self.input_tokens += usage.input_tokens as u64;
+        self.cached_tokens += usage.input_tokens_details.cached_tokens as u64;
+        self.output_tokens += usage.output_tokens as u64;
+        self.reasoning_tokens += usage.output_tokens_details.reasoning_tokens as u64;
+    }
+}
+
 impl RateLimit {
     fn from(resp: &reqwest::Response) -> Result<Self> {
         let timestamp = Instant::now();
@@ -308,7 +354,7 @@ impl RateLimit {
 /// 入力エレメント。
 ///
 /// <https://platform.openai.com/docs/api-reference/responses/create>
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum InputItem {
     /// Input Message or Output Message.
@@ -339,7 +385,7 @@ pub enum InputItem {
     FunctionCallOutput { call_id: String, output: String },
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum InputContent {
     InputText {
@@ -372,7 +418,7 @@ impl Debug for InputContent {
     }
 }
 
-#[derive(Clone, Default, Debug, Serialize)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum InputImageDetail {
     #[default]
@@ -739,13 +785,11 @@ struct Usage {
     total_tokens: u32,
 }
 
-#[allow(dead_code)]
 #[derive(Default, Clone, Debug, Deserialize)]
 struct InputTokensDetails {
     cached_tokens: u32,
 }
 
-#[allow(dead_code)]
 #[derive(Default, Clone, Debug, Deserialize)]
 struct OutputTokensDetails {
     reasoning_tokens: u32,
@@ -906,6 +950,29 @@ impl Default for OpenAiConfig {
     }
 }
 
+impl OpenAiConfig {
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        if self.enabled {
+            report.require("openai.api_key", &self.api_key, "openai.enabled = true");
+        }
+        // 無効でもモデル情報は他モジュールから参照される
+        if get_offline_model_info(&self.model).is_err() {
+            let list: Vec<_> = MODEL_LIST.iter().map(|info| info.name).collect();
+            report.add(
+                "openai.model",
+                format!(
+                    "unknown model: {} (available: {})",
+                    self.model,
+                    list.join(", ")
+                ),
+            );
+        }
+        if !self.storage_dir.is_empty() {
+            report.check_dir_writable("openai.storage_dir", &self.storage_dir);
+        }
+    }
+}
+
 /// OpenAI システムモジュール。
 pub struct OpenAi {
     config: OpenAiConfig,
@@ -916,6 +983,7 @@ pub struct OpenAi {
     model_info_online: Option<CachedModelInfo>,
 
     rate_limit: Option<RateLimit>,
+    token_usage: TokenUsage,
 }
 
 /// 特別な案内をすべきかもしれないエラー。
@@ -973,14 +1041,13 @@ impl OpenAi {
             model_info_offline: *info,
             model_info_online: None,
             rate_limit: None,
+            token_usage: Default::default(),
         })
     }
 
-    /*
     pub fn model_name(&self) -> &str {
         self.model_name
     }
-    */
 
     pub async fn model_info(&mut self) -> Result<ModelInfo> {
         let offline = self.model_info_offline();
@@ -1038,6 +1105,9 @@ impl OpenAi {
 
         info!("[openai] model request");
         self.check_enabled()?;
+        if let Some(bin) = dryrun::read("openai", &url_model(model)) {
+            return Ok(serde_json::from_slice(&bin)?);
+        }
 
         let resp = netutil::send_with_retry(|| {
             self.client
@@ -1149,11 +1219,22 @@ impl OpenAi {
 
     /// [Self::post_json] の結果を文字列として返す。
     /// HTTP エラーも含めてエラーにする。
+    ///
+    /// ドライランモードではフィクスチャを返す。
+    /// なければ Response API のみ固定の応答を返し、それ以外はエラーにする。
     async fn post_json_text(
         &mut self,
         url: &str,
         body: &(impl Serialize + std::fmt::Debug),
     ) -> Result<String> {
+        if dryrun::is_enabled() {
+            self.check_enabled()?;
+            return match dryrun::write("openai", "POST", url, body) {
+                Some(bin) => Ok(String::from_utf8(bin)?),
+                None if url == URL_RESPONSE => Ok(DRY_RUN_RESPONSE.to_string()),
+                None => bail!("dry-run: no fixture for {url}"),
+            };
+        }
         let resp = self.post_json(url, body).await?;
         let text = netutil::check_http_resp(resp).await?;
         info!("{text}");
@@ -1168,6 +1249,11 @@ impl OpenAi {
         url: &str,
         body: &(impl Serialize + std::fmt::Debug),
     ) -> Result<Vec<u8>> {
+        if dryrun::is_enabled() {
+            self.check_enabled()?;
+            return dryrun::write("openai", "POST", url, body)
+                .with_context(|| format!("dry-run: no fixture for {url}"));
+        }
         let resp = self.post_json(url, body).await?;
         let bin = netutil::check_http_resp_bin(resp).await?;
         info!("[openai] binary received: size={}", bin.len());
@@ -1181,6 +1267,11 @@ impl OpenAi {
             .map(|rate_limit| rate_limit.calc_expected_current())
     }
 
+    /// 起動してからの API 使用量の累計。
+    pub fn token_usage(&self) -> TokenUsage {
+        self.token_usage
+    }
+
     /// OpenAI Reponse API を使用する。
     pub async fn chat(
         &mut self,
@@ -1211,6 +1302,7 @@ impl OpenAi {
 
         let json_str = self.post_json_text(URL_RESPONSE, &body).await?;
         let resp: ResponseObject = netutil::convert_from_json(&json_str)?;
+        self.token_usage.add(&resp.usage);
 
         Ok(resp)
     }
@@ -1354,6 +1446,13 @@ mod tests {
         assert!((0.120 - v).abs() < EPS);
     }
 
+    #[test]
+    fn dry_run_response() {
+        let resp: ResponseObject = netutil::convert_from_json(DRY_RUN_RESPONSE).unwrap();
+        assert_eq!(resp.output_text(), "(dry-run)");
+        assert_eq!(resp.func_call_iter().count(), 0);
+    }
+
     #[tokio::test]
     #[serial(openai)]
     #[ignore]
@@ -10,6 +10,8 @@ mod web;
 
 use super::function::FunctionTable;
 
+pub use web::weather_report;
+
 /// このモジュール以下の全ての関数を [FunctionTable] に登録する。
 pub fn register_all<T: 'static>(func_table: &mut FunctionTable<T>) {
     system::register_all(func_table);
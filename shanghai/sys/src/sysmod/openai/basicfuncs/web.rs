@@ -44,9 +44,17 @@ fn register_get_weather_areas<T: 'static>(func_table: &mut FunctionTable<T>) {
 
 /// 気象情報を取得する。
 async fn get_weather_report(args: &FuncArgs) -> Result<String> {
-    const TIMEOUT: Duration = Duration::from_secs(10);
     let area = get_arg_str(args, "area")?;
 
+    weather_report(area).await
+}
+
+/// `area` の気象情報を AI 向けの JSON 文字列で取得する。
+///
+/// `shanghai weather` サブコマンドからも使われる。
+pub async fn weather_report(area: &str) -> Result<String> {
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
     // 引数の都市名をコードに変換
     let code = weather::office_name_to_code(area).ok_or_else(|| {
         anyhow!(
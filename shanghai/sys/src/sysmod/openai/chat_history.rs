@@ -3,6 +3,7 @@
 use crate::sysmod::openai::{InputItem, Role, WebSearchCall};
 
 use anyhow::{Result, ensure};
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use tiktoken_rs::CoreBPE;
 
@@ -24,6 +25,7 @@ pub struct ChatHistory {
 }
 
 /// 履歴データ。
+#[derive(Clone, Serialize, Deserialize)]
 struct Element {
     /// メッセージのリスト。
     /// 削除は [Element] 単位で行われる。
@@ -32,6 +34,12 @@ struct Element {
     token_count: usize,
 }
 
+/// 永続化用の会話履歴。[ChatHistory::snapshot] で作成する。
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ChatHistorySnapshot {
+    history: Vec<Element>,
+}
+
 impl ChatHistory {
     /// コンストラクタ。
     ///
@@ -193,6 +201,24 @@ impl ChatHistory {
         Ok(())
     }
 
+    /// 永続化用に全履歴をコピーする。
+    pub fn snapshot(&self) -> ChatHistorySnapshot {
+        ChatHistorySnapshot {
+            history: self.history.iter().cloned().collect(),
+        }
+    }
+
+    /// [Self::snapshot] の内容で全履歴を置き換える。
+    ///
+    /// トークン数合計上限を超える分は古いものから削除される。
+    pub fn restore(&mut self, snapshot: ChatHistorySnapshot) {
+        self.clear();
+        for elem in snapshot.history {
+            // 単体で上限を超えるものは捨てる
+            let _ = self.push(elem.items, elem.token_count);
+        }
+    }
+
     /// 全履歴をクリアする。
     pub fn clear(&mut self) {
         self.history.clear();
@@ -247,4 +273,18 @@ mod tests {
         // https://platform.openai.com/tokenizer
         assert_eq!(7, count);
     }
+
+    #[test]
+    fn snapshot() {
+        let mut hist = ChatHistory::new("gpt-4o");
+        hist.push_input_message(Role::User, "こんにちは").unwrap();
+        hist.push_output_message("こんにちは、管理人形です。")
+            .unwrap();
+        let json = serde_json::to_string(&hist.snapshot()).unwrap();
+
+        let mut restored = ChatHistory::new("gpt-4o");
+        restored.restore(serde_json::from_str(&json).unwrap());
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.usage(), hist.usage());
+    }
 }
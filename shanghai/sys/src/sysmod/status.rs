@@ -0,0 +1,119 @@
+//! 監視対象の状態遷移。
+//!
+//! [super::uptime] と [super::watchdog] で共通に使う。
+//! 一時的な失敗で通知しないよう、現在の状態と異なる結果が
+//! 設定の `flap_threshold` 回続いたら遷移する。
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// 監視対象の状態。
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Status {
+    /// 起動直後等で未確定。
+    #[default]
+    Unknown,
+    /// 稼働中。
+    Up,
+    /// 停止中。
+    Down,
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Status::Unknown => "UNKNOWN",
+            Status::Up => "UP",
+            Status::Down => "DOWN",
+        };
+        f.write_str(s)
+    }
+}
+
+/// 状態遷移の判定器。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StatusTracker {
+    /// 現在の状態。
+    pub status: Status,
+    /// 現在の状態になった時刻。
+    pub since: Option<DateTime<Local>>,
+    /// 現在の状態と異なる結果と、その連続回数。
+    #[serde(skip)]
+    pending: Option<(Status, u32)>,
+}
+
+impl StatusTracker {
+    /// 確認結果を反映する。
+    ///
+    /// 状態が遷移した場合は遷移前の状態を返す。
+    pub fn update(&mut self, up: bool, threshold: u32, now: DateTime<Local>) -> Option<Status> {
+        let observed = if up { Status::Up } else { Status::Down };
+        if observed == self.status {
+            self.pending = None;
+            return None;
+        }
+
+        let count = match self.pending {
+            Some((status, count)) if status == observed => count + 1,
+            _ => 1,
+        };
+        if count < threshold {
+            self.pending = Some((observed, count));
+            return None;
+        }
+
+        let old = self.status;
+        self.status = observed;
+        self.since = Some(now);
+        self.pending = None;
+
+        Some(old)
+    }
+}
+
+/// `name` と `type` だけを指定した監視対象の設定を作る。テスト用。
+///
+/// 他のフィールドは toml 設定で省略した場合と同じデフォルト値になる。
+#[cfg(test)]
+pub(crate) fn test_target<T: serde::de::DeserializeOwned>(name: &str, kind: &str) -> T {
+    let mut table = toml::Table::new();
+    table.insert("name".to_string(), name.into());
+    table.insert("type".to_string(), kind.into());
+    table.try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn flap() {
+        let t0 = Local.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let at = |min: i64| t0 + Duration::minutes(min);
+        let mut tracker = StatusTracker::default();
+
+        // 未確定の間も閾値回続くまで遷移しない
+        assert_eq!(tracker.update(false, 3, at(0)), None);
+        assert_eq!(tracker.update(true, 3, at(1)), None);
+        assert_eq!(tracker.update(true, 3, at(2)), None);
+        assert_eq!(tracker.update(true, 3, at(3)), Some(Status::Unknown));
+        assert_eq!(tracker.status, Status::Up);
+        assert_eq!(tracker.since, Some(at(3)));
+
+        // 途中で成功すると数え直し
+        assert_eq!(tracker.update(false, 3, at(4)), None);
+        assert_eq!(tracker.update(false, 3, at(5)), None);
+        assert_eq!(tracker.update(true, 3, at(6)), None);
+        assert_eq!(tracker.update(false, 3, at(7)), None);
+        assert_eq!(tracker.update(false, 3, at(8)), None);
+        assert_eq!(tracker.update(false, 3, at(9)), Some(Status::Up));
+        assert_eq!(tracker.status, Status::Down);
+        assert_eq!(tracker.since, Some(at(9)));
+
+        // 閾値 1 ならば即時
+        assert_eq!(tracker.update(true, 1, at(10)), Some(Status::Down));
+        assert_eq!(tracker.status, Status::Up);
+    }
+}
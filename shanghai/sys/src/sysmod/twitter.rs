@@ -1,19 +1,21 @@
 //! Twitter 機能。
 
 use super::SystemModule;
+use crate::config::{self, ValidationReport};
+use crate::dryrun;
 use crate::sysmod::openai::InputContent;
 use crate::sysmod::openai::InputItem;
 use crate::sysmod::openai::Role;
-use crate::taskserver::Control;
-use crate::{config, taskserver};
+use crate::taskserver::event::{GitPushed, HealthReported};
+use crate::taskserver::{Control, schedule::Schedule};
+use crate::{state, taskserver};
 use utils::graphics::FontRenderer;
 use utils::netutil;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use base64::{Engine as _, engine::general_purpose};
-use chrono::NaiveTime;
 use log::warn;
-use log::{debug, info};
+use log::{debug, error, info};
 use rand::RngExt;
 use reqwest::multipart;
 use serde::{Deserialize, Serialize};
@@ -184,6 +186,10 @@ pub struct TwitterConfig {
     tlcheck_enabled: bool,
     /// 起動時に1回だけタイムライン確認タスクを起動する。デバッグ用。
     debug_exec_once: bool,
+    /// タイムライン確認タスクの起動スケジュール。
+    /// 書式は [crate::taskserver::schedule] を参照。
+    #[serde(default = "default_tlcheck_schedule")]
+    tlcheck_schedule: String,
     /// ツイートを実際にはせずにログにのみ出力する。
     fake_tweet: bool,
     /// Twitter API のアカウント情報。
@@ -211,11 +217,16 @@ pub struct TwitterConfig {
     prompt: TwitterPrompt,
 }
 
+fn default_tlcheck_schedule() -> String {
+    "*/5 * * * *".to_string()
+}
+
 impl Default for TwitterConfig {
     fn default() -> Self {
         Self {
             tlcheck_enabled: false,
             debug_exec_once: false,
+            tlcheck_schedule: default_tlcheck_schedule(),
             fake_tweet: true,
             consumer_key: "".to_string(),
             consumer_secret: "".to_string(),
@@ -229,6 +240,22 @@ impl Default for TwitterConfig {
     }
 }
 
+impl TwitterConfig {
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        report.check_schedule("twitter.tlcheck_schedule", &self.tlcheck_schedule);
+        if !self.fake_tweet {
+            let reason = "twitter.fake_tweet = false";
+            report.require("twitter.consumer_key", &self.consumer_key, reason);
+            report.require("twitter.consumer_secret", &self.consumer_secret, reason);
+            report.require("twitter.access_token", &self.access_token, reason);
+            report.require("twitter.access_secret", &self.access_secret, reason);
+        }
+        if !self.font_file.is_empty() {
+            report.check_file("twitter.font_file", &self.font_file);
+        }
+    }
+}
+
 /// Twitter 応答設定データの要素。
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct TimelineCheckRule {
@@ -281,7 +308,7 @@ impl Default for TwitterPrompt {
 pub struct Twitter {
     config: TwitterConfig,
 
-    wakeup_list: Vec<NaiveTime>,
+    tlcheck_schedule: Schedule,
 
     font: Option<FontRenderer>,
 
@@ -302,6 +329,15 @@ pub struct Twitter {
     id_username_cache: HashMap<String, String>,
 }
 
+/// 再起動をまたいで保持する状態。[state] に保存する。
+#[derive(Default, Serialize, Deserialize)]
+struct TwitterState {
+    tl_check_since_id: Option<String>,
+    my_user_cache: Option<User>,
+    username_user_cache: HashMap<String, User>,
+    id_username_cache: HashMap<String, String>,
+}
+
 struct Reply {
     to_tw_id: String,
     to_user_id: String,
@@ -310,10 +346,14 @@ struct Reply {
 }
 
 impl Twitter {
-    pub fn new(wakeup_list: Vec<NaiveTime>) -> Result<Self> {
+    pub fn new() -> Result<Self> {
         info!("[twitter] initialize");
 
         let config = config::get(|cfg| cfg.twitter.clone());
+        let tlcheck_schedule = config
+            .tlcheck_schedule
+            .parse()
+            .context("twitter.tlcheck_schedule")?;
 
         let font = if !config.font_file.is_empty() {
             let ttf_bin = fs::read(&config.font_file)?;
@@ -322,14 +362,16 @@ impl Twitter {
             None
         };
 
+        let state: TwitterState = state::load("twitter").unwrap_or_default();
+
         Ok(Twitter {
             config,
-            wakeup_list,
+            tlcheck_schedule,
             font,
-            tl_check_since_id: None,
-            my_user_cache: None,
-            username_user_cache: HashMap::new(),
-            id_username_cache: HashMap::new(),
+            tl_check_since_id: state.tl_check_since_id,
+            my_user_cache: state.my_user_cache,
+            username_user_cache: state.username_user_cache,
+            id_username_cache: state.id_username_cache,
         })
     }
 
@@ -678,7 +720,7 @@ impl Twitter {
         self.tweet_raw(param).await
     }
 
-    /// [TwitterConfig::fake_tweet] 設定に対応したツイート。
+    /// [TwitterConfig::fake_tweet] 設定とドライランモードに対応したツイート。
     async fn tweet_raw(&mut self, mut param: TweetParam) -> Result<()> {
         // tl_check_since_id が None なら自分の最新ツイート ID を取得して設定する
         self.get_since_id().await?;
@@ -695,7 +737,11 @@ impl Twitter {
             }
         }
 
-        if !self.config.fake_tweet {
+        if dryrun::is_enabled() {
+            dryrun::write("twitter", "POST", URL_TWEETS, &param);
+
+            Ok(())
+        } else if !self.config.fake_tweet {
             // real tweet!
             self.tweets_post(param).await?;
 
@@ -723,7 +769,7 @@ impl Twitter {
     /// <https://developer.twitter.com/en/docs/twitter-api/v1/media/upload-media/api-reference/post-media-upload>
     /// <https://developer.twitter.com/en/docs/twitter-api/v1/media/upload-media/uploading-media/media-best-practices>
     pub async fn media_upload<T: Into<reqwest::Body>>(&self, bin: T) -> Result<u64> {
-        if self.config.fake_tweet {
+        if self.config.fake_tweet || dryrun::is_enabled() {
             info!("fake upload");
 
             return Ok(0);
@@ -752,6 +798,26 @@ impl Twitter {
         twitter.twitter_task(&ctrl).await
     }
 
+    /// [GitPushed] イベントを受けてツイートする。
+    async fn on_git_pushed(ctrl: Control, event: GitPushed) -> Result<()> {
+        let mut twitter = ctrl.sysmods().twitter.lock().await;
+        twitter.tweet(&event.message()).await
+    }
+
+    /// [HealthReported] イベントを受けてツイートする。
+    ///
+    /// グラフが添付されていれば画像としてアップロードする。
+    async fn on_health_reported(ctrl: Control, event: HealthReported) -> Result<()> {
+        let mut twitter = ctrl.sysmods().twitter.lock().await;
+        match event.chart {
+            Some(chart) => {
+                let media_id = twitter.media_upload(chart).await?;
+                twitter.tweet_custom(&event.text, None, &[media_id]).await
+            }
+            None => twitter.tweet(&event.text).await,
+        }
+    }
+
     /// 自身の Twitter ID を返す。
     /// [Self::users_me] の キャッシュ付きバージョン。
     async fn get_my_id(&mut self) -> Result<User> {
@@ -997,6 +1063,8 @@ impl Twitter {
 impl SystemModule for Twitter {
     fn on_start(&mut self, ctrl: &Control) {
         info!("[twitter] on_start");
+        taskserver::spawn_event_task(ctrl, "tw-git-pushed", Twitter::on_git_pushed);
+        taskserver::spawn_event_task(ctrl, "tw-health-report", Twitter::on_health_reported);
         if self.config.tlcheck_enabled {
             if self.config.debug_exec_once {
                 taskserver::spawn_oneshot_task(ctrl, "tw-check", Twitter::twitter_task_entry);
@@ -1004,12 +1072,25 @@ impl SystemModule for Twitter {
                 taskserver::spawn_periodic_task(
                     ctrl,
                     "tw-check",
-                    &self.wakeup_list,
+                    &self.tlcheck_schedule,
                     Twitter::twitter_task_entry,
                 );
             }
         }
     }
+
+    fn on_stop(&mut self, _ctrl: &Control) {
+        info!("[twitter] on_stop");
+        let state = TwitterState {
+            tl_check_since_id: self.tl_check_since_id.clone(),
+            my_user_cache: self.my_user_cache.clone(),
+            username_user_cache: self.username_user_cache.clone(),
+            id_username_cache: self.id_username_cache.clone(),
+        };
+        if let Err(e) = state::save("twitter", &state) {
+            error!("[twitter] save state failed: {e:#}");
+        }
+    }
 }
 
 /// HTTP header や query を表すデータ構造。
@@ -0,0 +1,523 @@
+//! 外部サービスの死活監視。
+//!
+//! 設定された監視対象 ([TargetConfig]) に定期的に [probe] で疎通を確認し、
+//! 応答時間と成否を1時間ごとに集計した [history] を記録する。
+//! 一時的な失敗で通知しないよう、[super::status] で状態遷移を判定し、
+//! 停止と回復を [Notify] として発行する。
+//!
+//! ```toml
+//! [uptime]
+//! enabled = true
+//! check_schedule = "* * * * *"
+//! discord_ch = 123456789
+//! flap_threshold = 3
+//!
+//! [[uptime.targets]]
+//! name = "Blog"
+//! type = "http"
+//! url = "https://example.com/"
+//! expected_status = 200
+//! body_contains = "Welcome"
+//!
+//! [[uptime.targets]]
+//! name = "NAS SSH"
+//! type = "tcp"
+//! host = "192.168.0.10"
+//! port = 22
+//!
+//! [[uptime.targets]]
+//! name = "DNS"
+//! type = "dns"
+//! host = "example.com"
+//! ```
+//!
+//! 稼働履歴と状態は [SystemModule::on_stop] と1時間ごとに保存し、起動時に復元する。
+
+pub mod history;
+pub mod probe;
+
+use super::SystemModule;
+use super::status::{Status, StatusTracker};
+use crate::config::{self, ValidationReport};
+use crate::state;
+use crate::taskserver;
+use crate::taskserver::event::Notify;
+use crate::taskserver::{Control, schedule::Schedule};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local};
+use history::TargetHistory;
+use log::{error, info, warn};
+use probe::ProbeResult;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+/// 稼働履歴の保存日数。
+const HISTORY_DAYS: i64 = 31;
+/// [Uptime::events] の最大サイズ。
+const EVENTS_MAX: usize = 100;
+
+/// 死活監視設定データ。toml 設定に対応する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UptimeConfig {
+    /// 死活監視機能を有効化する。
+    enabled: bool,
+    /// 確認タスクの起動スケジュール。
+    /// 書式は [crate::taskserver::schedule] を参照。
+    check_schedule: String,
+    /// 停止と回復の通知先 Discord チャネル ID。0 ならば通知しない。
+    #[serde(default)]
+    discord_ch: u64,
+    /// 現在の状態と異なる結果がこの回数続いたら状態を変える。
+    #[serde(default = "default_flap_threshold")]
+    flap_threshold: u32,
+    /// 監視対象のリスト。
+    #[serde(default)]
+    targets: Vec<TargetConfig>,
+}
+
+fn default_flap_threshold() -> u32 {
+    3
+}
+
+impl Default for UptimeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            check_schedule: "* * * * *".to_string(),
+            discord_ch: 0,
+            flap_threshold: default_flap_threshold(),
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl UptimeConfig {
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        report.check_monitor(
+            "uptime",
+            &self.check_schedule,
+            self.flap_threshold,
+            self.targets.iter().map(|target| target.name.as_str()),
+        );
+        for (i, target) in self.targets.iter().enumerate() {
+            target.validate(&format!("uptime.targets[{i}]"), report);
+        }
+    }
+}
+
+/// 確認方法。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeKind {
+    /// [TargetConfig::url] に GET して応答を確認する。
+    Http,
+    /// [TargetConfig::host] の [TargetConfig::port] に TCP 接続する。
+    Tcp,
+    /// [TargetConfig::host] の名前解決をする。
+    Dns,
+}
+
+/// 監視対象。toml 設定に対応する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TargetConfig {
+    /// 名前。通知と表示に使い、履歴のキーにもなる。
+    pub name: String,
+    /// 確認方法。
+    #[serde(rename = "type")]
+    pub kind: ProbeKind,
+    /// http の URL。
+    #[serde(default)]
+    pub url: String,
+    /// tcp と dns のホスト名またはアドレス。
+    #[serde(default)]
+    pub host: String,
+    /// tcp のポート番号。
+    #[serde(default)]
+    pub port: u16,
+    /// http で期待するステータスコード。
+    #[serde(default = "default_expected_status")]
+    pub expected_status: u16,
+    /// http の応答本文に含まれるべき文字列。空文字列ならば確認しない。
+    #[serde(default)]
+    pub body_contains: String,
+    /// タイムアウト (秒)。
+    #[serde(default = "default_timeout_sec")]
+    pub timeout_sec: u64,
+}
+
+fn default_expected_status() -> u16 {
+    200
+}
+
+fn default_timeout_sec() -> u64 {
+    10
+}
+
+impl TargetConfig {
+    fn validate(&self, path: &str, report: &mut ValidationReport) {
+        if self.name.is_empty() {
+            report.add(&format!("{path}.name"), "must not be empty");
+        }
+        match self.kind {
+            ProbeKind::Http => {
+                if !self.url.starts_with("http://") && !self.url.starts_with("https://") {
+                    report.add(&format!("{path}.url"), "must be an http(s) URL");
+                }
+                if !(100..=599).contains(&self.expected_status) {
+                    report.add(&format!("{path}.expected_status"), "invalid status code");
+                }
+            }
+            ProbeKind::Tcp => {
+                report.require(&format!("{path}.host"), &self.host, "type = \"tcp\"");
+                if self.port == 0 {
+                    report.add(&format!("{path}.port"), "must be 1 or more");
+                }
+            }
+            ProbeKind::Dns => {
+                report.require(&format!("{path}.host"), &self.host, "type = \"dns\"");
+            }
+        }
+        if self.timeout_sec == 0 {
+            report.add(&format!("{path}.timeout_sec"), "must be 1 or more");
+        }
+    }
+
+    /// 確認先の表示用文字列。
+    pub fn address(&self) -> String {
+        match self.kind {
+            ProbeKind::Http => self.url.clone(),
+            ProbeKind::Tcp => format!("{}:{}", self.host, self.port),
+            ProbeKind::Dns => self.host.clone(),
+        }
+    }
+}
+
+/// 最後の確認結果。
+#[derive(Debug, Clone)]
+pub struct LastCheck {
+    /// 確認時刻。
+    pub time: DateTime<Local>,
+    /// 結果。
+    pub result: ProbeResult,
+}
+
+/// 監視対象ごとの記録。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetRecord {
+    /// 状態。
+    pub tracker: StatusTracker,
+    /// 稼働履歴。
+    pub history: TargetHistory,
+    /// 最後の確認結果。保存しない。
+    #[serde(skip)]
+    pub last: Option<LastCheck>,
+}
+
+/// 状態遷移の記録。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UptimeEvent {
+    /// 遷移した時刻。
+    pub time: DateTime<Local>,
+    /// 監視対象の名前。
+    pub target: String,
+    /// 遷移後の状態。
+    pub status: Status,
+    /// 停止の場合はその理由。
+    pub message: String,
+}
+
+/// 永続化する状態。
+#[derive(Default, Serialize, Deserialize)]
+struct UptimeState {
+    targets: BTreeMap<String, TargetRecord>,
+    events: VecDeque<UptimeEvent>,
+}
+
+/// 死活監視システムモジュール。
+pub struct Uptime {
+    /// 設定データ。
+    config: UptimeConfig,
+    /// 確認タスクの起動スケジュール。
+    schedule: Schedule,
+    /// HTTP クライアント。
+    client: Client,
+    /// 監視対象の名前から記録へのマップ。
+    targets: BTreeMap<String, TargetRecord>,
+    /// 状態遷移の記録。新しいものが後ろ。最大サイズは [EVENTS_MAX]。
+    events: VecDeque<UptimeEvent>,
+    /// 最後に保存した時刻の区間 (時)。変わったら保存する。
+    last_saved_hour: Option<i64>,
+}
+
+impl Uptime {
+    /// コンストラクタ。
+    ///
+    /// 設定の読み込みと状態の復元のみ行い、async task の初期化は [Self::on_start] で行う。
+    pub fn new() -> Result<Self> {
+        info!("[uptime] initialize");
+
+        let config: UptimeConfig = config::get(|cfg| cfg.uptime.clone());
+        let schedule = config
+            .check_schedule
+            .parse()
+            .context("uptime.check_schedule")?;
+        let client = Client::builder().build()?;
+
+        // 設定から削除された監視対象の記録は捨てる
+        let mut state: UptimeState = state::load("uptime").unwrap_or_default();
+        let targets = config
+            .targets
+            .iter()
+            .map(|target| {
+                let record = state.targets.remove(&target.name).unwrap_or_default();
+                (target.name.clone(), record)
+            })
+            .collect();
+
+        Ok(Self {
+            config,
+            schedule,
+            client,
+            targets,
+            events: state.events,
+            last_saved_hour: None,
+        })
+    }
+
+    /// 監視対象の設定と記録を設定順に返す。
+    pub fn targets(&self) -> impl Iterator<Item = (&TargetConfig, &TargetRecord)> {
+        self.config
+            .targets
+            .iter()
+            .filter_map(|target| Some((target, self.targets.get(&target.name)?)))
+    }
+
+    /// 状態遷移の記録。新しいものが後ろ。
+    pub fn events(&self) -> &VecDeque<UptimeEvent> {
+        &self.events
+    }
+
+    /// 確認結果を反映し、状態が遷移したら [Notify] を発行する。
+    ///
+    /// 未確定から稼働への遷移は通知しない。
+    fn apply_results(
+        &mut self,
+        ctrl: &Control,
+        now: DateTime<Local>,
+        results: Vec<(String, ProbeResult)>,
+    ) {
+        for (name, result) in results {
+            // 確認中に設定が変更された
+            let Some(record) = self.targets.get_mut(&name) else {
+                continue;
+            };
+
+            record.history.record(now, result.up, result.latency_ms);
+            record.history.prune(now - Duration::days(HISTORY_DAYS));
+            let since = record.tracker.since;
+            if let Some(old) = record
+                .tracker
+                .update(result.up, self.config.flap_threshold, now)
+            {
+                let new = record.tracker.status;
+                let text = transition_message(&name, old, new, since, now, &result);
+                info!("[uptime] {text}");
+                if old != Status::Unknown || new == Status::Down {
+                    ctrl.publish(Notify {
+                        text,
+                        discord_ch: self.config.discord_ch,
+                        line_to: String::new(),
+                    });
+                }
+
+                if self.events.len() >= EVENTS_MAX {
+                    self.events.pop_front();
+                }
+                self.events.push_back(UptimeEvent {
+                    time: now,
+                    target: name.clone(),
+                    status: new,
+                    message: result.message.clone(),
+                });
+            }
+            record.last = Some(LastCheck { time: now, result });
+        }
+
+        let hour = now.timestamp() / 3600;
+        if self.last_saved_hour != Some(hour) {
+            self.save();
+            self.last_saved_hour = Some(hour);
+        }
+    }
+
+    /// 状態を保存する。
+    fn save(&self) {
+        let state = UptimeState {
+            targets: self.targets.clone(),
+            events: self.events.clone(),
+        };
+        if let Err(e) = state::save("uptime", &state) {
+            error!("[uptime] save state failed: {e:#}");
+        }
+    }
+
+    /// 確認タスク。
+    ///
+    /// 確認中はロックを解放し、全監視対象を並行して確認する。
+    async fn check_task(ctrl: Control) -> Result<()> {
+        let (client, targets) = {
+            let uptime = ctrl.sysmods().uptime.lock().await;
+            (uptime.client.clone(), uptime.config.targets.clone())
+        };
+
+        let handles: Vec<_> = targets
+            .into_iter()
+            .map(|target| {
+                let client = client.clone();
+                let name = target.name.clone();
+                let handle = tokio::spawn(async move {
+                    let result = probe::probe(&client, &target).await;
+                    if !result.up {
+                        warn!("[uptime] {} failed: {}", target.name, result.message);
+                    }
+                    result
+                });
+                (name, handle)
+            })
+            .collect();
+        // 確認が panic した監視対象は停止として扱い、他の結果は反映する
+        let mut results = Vec::with_capacity(handles.len());
+        for (name, handle) in handles {
+            let result = handle.await.unwrap_or_else(|e| {
+                error!("[uptime] {name} probe aborted: {e}");
+                ProbeResult {
+                    up: false,
+                    latency_ms: 0.0,
+                    message: format!("probe aborted: {e}"),
+                }
+            });
+            results.push((name, result));
+        }
+
+        let mut uptime = ctrl.sysmods().uptime.lock().await;
+        uptime.apply_results(&ctrl, Local::now(), results);
+
+        Ok(())
+    }
+}
+
+impl SystemModule for Uptime {
+    fn on_start(&mut self, ctrl: &Control) {
+        info!("[uptime] on_start");
+        if self.config.enabled {
+            taskserver::spawn_periodic_task(
+                ctrl,
+                "uptime-check",
+                &self.schedule,
+                Uptime::check_task,
+            );
+        }
+    }
+
+    fn on_stop(&mut self, _ctrl: &Control) {
+        info!("[uptime] on_stop");
+        self.save();
+    }
+}
+
+/// 状態遷移の通知メッセージを作る。
+fn transition_message(
+    name: &str,
+    old: Status,
+    new: Status,
+    since: Option<DateTime<Local>>,
+    now: DateTime<Local>,
+    result: &ProbeResult,
+) -> String {
+    match new {
+        Status::Down => format!("[Uptime] {name} is DOWN: {}", result.message),
+        _ => match (old, since) {
+            (Status::Down, Some(since)) => {
+                let down = now - since;
+                format!(
+                    "[Uptime] {name} is UP (down for {}h {}m)",
+                    down.num_hours(),
+                    down.num_minutes() % 60
+                )
+            }
+            _ => format!("[Uptime] {name} is UP"),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysmod::status::test_target;
+    use chrono::TimeZone;
+
+    #[test]
+    fn validate() {
+        let mut config = UptimeConfig::default();
+        let mut http: TargetConfig = test_target("web", "http");
+        http.url = "https://example.com/".to_string();
+        let mut tcp: TargetConfig = test_target("ssh", "tcp");
+        tcp.host = "localhost".to_string();
+        tcp.port = 22;
+        config.targets = vec![http, tcp];
+        let mut report = ValidationReport::default();
+        config.validate(&mut report);
+        assert!(report.is_empty());
+
+        let mut http: TargetConfig = test_target("web", "http");
+        http.url = "ftp://example.com/".to_string();
+        http.expected_status = 0;
+        config.targets = vec![http, test_target("web", "tcp"), test_target("", "dns")];
+        config.flap_threshold = 0;
+        let mut report = ValidationReport::default();
+        config.validate(&mut report);
+        assert_eq!(
+            report.paths(),
+            [
+                "uptime.flap_threshold",
+                "uptime.targets[1].name",
+                "uptime.targets[0].url",
+                "uptime.targets[0].expected_status",
+                "uptime.targets[1].host",
+                "uptime.targets[1].port",
+                "uptime.targets[2].name",
+                "uptime.targets[2].host",
+            ]
+        );
+    }
+
+    #[test]
+    fn message() {
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let down = ProbeResult {
+            up: false,
+            latency_ms: 10000.0,
+            message: "timeout (10 s)".to_string(),
+        };
+        let up = ProbeResult {
+            up: true,
+            latency_ms: 20.0,
+            message: String::new(),
+        };
+
+        assert_eq!(
+            transition_message("Blog", Status::Up, Status::Down, None, now, &down),
+            "[Uptime] Blog is DOWN: timeout (10 s)"
+        );
+        let since = now - Duration::minutes(75);
+        assert_eq!(
+            transition_message("Blog", Status::Down, Status::Up, Some(since), now, &up),
+            "[Uptime] Blog is UP (down for 1h 15m)"
+        );
+        assert_eq!(
+            transition_message("Blog", Status::Unknown, Status::Up, None, now, &up),
+            "[Uptime] Blog is UP"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! 監視対象ごとの稼働履歴。
+//!
+//! 1時間ごとに確認回数、成功回数、応答時間の合計を集計して保持する。
+//! 稼働率は集計単位である1時間の粒度で計算する。
+
+use chrono::{DateTime, Duration, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// 集計単位 (秒)。
+const BUCKET_SECS: i64 = 3600;
+
+/// 1時間分の集計。
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HourBucket {
+    /// 開始時刻 (UNIX 時刻)。
+    pub start: i64,
+    /// 確認回数。
+    pub checks: u32,
+    /// 成功回数。
+    pub ups: u32,
+    /// 成功時の応答時間 (ms) の合計。
+    pub latency_ms_sum: f64,
+}
+
+/// 監視対象1つの稼働履歴。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetHistory {
+    /// 時刻順の集計。
+    buckets: VecDeque<HourBucket>,
+}
+
+impl TargetHistory {
+    /// 確認結果を記録する。
+    pub fn record(&mut self, time: DateTime<Local>, up: bool, latency_ms: f64) {
+        let t = time.timestamp();
+        let start = t - t.rem_euclid(BUCKET_SECS);
+        if self.buckets.back().is_none_or(|last| last.start < start) {
+            self.buckets.push_back(HourBucket {
+                start,
+                checks: 0,
+                ups: 0,
+                latency_ms_sum: 0.0,
+            });
+        }
+        // 時刻が戻された場合は最新の区間に入れる
+        let bucket = self.buckets.back_mut().unwrap();
+        bucket.checks += 1;
+        if up {
+            bucket.ups += 1;
+            bucket.latency_ms_sum += latency_ms;
+        }
+    }
+
+    /// `oldest` より前に終了した区間を削除する。
+    pub fn prune(&mut self, oldest: DateTime<Local>) {
+        let oldest = oldest.timestamp();
+        while self
+            .buckets
+            .front()
+            .is_some_and(|b| b.start + BUCKET_SECS <= oldest)
+        {
+            self.buckets.pop_front();
+        }
+    }
+
+    /// `now` までの `period` の区間を返す。
+    fn buckets_in(
+        &self,
+        now: DateTime<Local>,
+        period: Duration,
+    ) -> impl Iterator<Item = &HourBucket> {
+        let from = (now - period).timestamp();
+        self.buckets
+            .iter()
+            .filter(move |b| b.start + BUCKET_SECS > from)
+    }
+
+    /// `now` までの `period` の稼働率 (%)。確認していなければ None。
+    pub fn uptime_percent(&self, now: DateTime<Local>, period: Duration) -> Option<f64> {
+        let (checks, ups) = self
+            .buckets_in(now, period)
+            .fold((0, 0), |(c, u), b| (c + b.checks, u + b.ups));
+
+        (checks > 0).then(|| 100.0 * ups as f64 / checks as f64)
+    }
+
+    /// `now` までの `period` の成功時の平均応答時間 (ms)。成功していなければ None。
+    pub fn avg_latency_ms(&self, now: DateTime<Local>, period: Duration) -> Option<f64> {
+        let (ups, sum) = self
+            .buckets_in(now, period)
+            .fold((0, 0.0), |(u, s), b| (u + b.ups, s + b.latency_ms_sum));
+
+        (ups > 0).then(|| sum / ups as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn uptime() {
+        let base = Local
+            .timestamp_opt(1_700_000_000 - 1_700_000_000 % 3600, 0)
+            .unwrap();
+        let mut h = TargetHistory::default();
+        let now = base + Duration::days(10);
+        assert_eq!(h.uptime_percent(now, Duration::days(1)), None);
+
+        // 10 日前: 全滅
+        for min in 0..60 {
+            h.record(base + Duration::minutes(min), false, 0.0);
+        }
+        // 直近 1 時間: 4 回中 3 回成功
+        for (min, up) in [(0, true), (1, true), (2, false), (3, true)] {
+            h.record(
+                now - Duration::minutes(30) + Duration::minutes(min),
+                up,
+                20.0,
+            );
+        }
+
+        assert_eq!(h.uptime_percent(now, Duration::days(1)), Some(75.0));
+        assert_eq!(h.avg_latency_ms(now, Duration::days(1)), Some(20.0));
+        let week = h.uptime_percent(now, Duration::days(7)).unwrap();
+        assert_eq!(week, 75.0);
+        let month = h.uptime_percent(now, Duration::days(30)).unwrap();
+        assert!((month - 100.0 * 3.0 / 64.0).abs() < 1e-9, "{month}");
+
+        h.prune(now - Duration::days(7));
+        assert_eq!(h.buckets.len(), 1);
+        assert_eq!(h.uptime_percent(now, Duration::days(30)), Some(75.0));
+    }
+
+    #[test]
+    fn clock_back() {
+        let base = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        let mut h = TargetHistory::default();
+        h.record(base, true, 10.0);
+        h.record(base - Duration::hours(2), false, 0.0);
+
+        assert_eq!(h.buckets.len(), 1);
+        assert_eq!(h.buckets[0].checks, 2);
+        assert_eq!(h.buckets[0].ups, 1);
+    }
+}
@@ -0,0 +1,142 @@
+//! 監視対象への疎通確認。
+
+use super::{ProbeKind, TargetConfig};
+use anyhow::{Result, bail, ensure};
+use reqwest::Client;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpStream, lookup_host};
+
+/// 1回の疎通確認の結果。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeResult {
+    /// 成功ならば true。
+    pub up: bool,
+    /// 応答時間 (ms)。失敗の場合は失敗までの時間。
+    pub latency_ms: f64,
+    /// 失敗の場合はその理由。成功の場合は空。
+    pub message: String,
+}
+
+/// `target` の疎通を確認する。
+///
+/// タイムアウトを含め、失敗は [ProbeResult::up] が false の結果として返す。
+pub async fn probe(client: &Client, target: &TargetConfig) -> ProbeResult {
+    let timeout = Duration::from_secs(target.timeout_sec);
+    let start = Instant::now();
+    let result = match tokio::time::timeout(timeout, probe_inner(client, target)).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("timeout ({} s)", target.timeout_sec)),
+    };
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+
+    match result {
+        Ok(()) => ProbeResult {
+            up: true,
+            latency_ms,
+            message: String::new(),
+        },
+        Err(e) => ProbeResult {
+            up: false,
+            latency_ms,
+            message: format!("{e:#}"),
+        },
+    }
+}
+
+async fn probe_inner(client: &Client, target: &TargetConfig) -> Result<()> {
+    match target.kind {
+        ProbeKind::Http => {
+            let resp = client.get(&target.url).send().await?;
+            let status = resp.status().as_u16();
+            // 本文の確認が不要ならば読まない
+            let body = if target.body_contains.is_empty() {
+                String::new()
+            } else {
+                resp.text().await?
+            };
+            check_http_response(target, status, &body)
+        }
+        ProbeKind::Tcp => {
+            TcpStream::connect((target.host.as_str(), target.port)).await?;
+            Ok(())
+        }
+        ProbeKind::Dns => {
+            let mut addrs = lookup_host((target.host.as_str(), 0)).await?;
+            ensure!(addrs.next().is_some(), "no address");
+            Ok(())
+        }
+    }
+}
+
+/// HTTP 応答が期待通りか確認する。
+fn check_http_response(target: &TargetConfig, status: u16, body: &str) -> Result<()> {
+    if status != target.expected_status {
+        bail!(
+            "unexpected status: {status} (expected {})",
+            target.expected_status
+        );
+    }
+    if !target.body_contains.is_empty() && !body.contains(&target.body_contains) {
+        bail!("body does not contain {:?}", target.body_contains);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn target(kind: ProbeKind) -> TargetConfig {
+        TargetConfig {
+            name: "test".to_string(),
+            kind,
+            url: String::new(),
+            host: String::new(),
+            port: 0,
+            expected_status: 200,
+            body_contains: String::new(),
+            timeout_sec: 5,
+        }
+    }
+
+    #[test]
+    fn http_response() {
+        let mut t = target(ProbeKind::Http);
+        assert!(check_http_response(&t, 200, "").is_ok());
+        let e = check_http_response(&t, 503, "").unwrap_err();
+        assert_eq!(e.to_string(), "unexpected status: 503 (expected 200)");
+
+        t.expected_status = 401;
+        t.body_contains = "Login".to_string();
+        assert!(check_http_response(&t, 401, "<h1>Login</h1>").is_ok());
+        assert!(check_http_response(&t, 401, "<h1>Welcome</h1>").is_err());
+    }
+
+    #[tokio::test]
+    async fn tcp_dns() {
+        let client = Client::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut t = target(ProbeKind::Tcp);
+        t.host = "127.0.0.1".to_string();
+        t.port = port;
+        let result = probe(&client, &t).await;
+        assert!(result.up, "{result:?}");
+        assert!(result.message.is_empty());
+
+        // 閉じたポート
+        drop(listener);
+        let result = probe(&client, &t).await;
+        assert!(!result.up);
+        assert!(!result.message.is_empty());
+
+        let mut t = target(ProbeKind::Dns);
+        t.host = "localhost".to_string();
+        assert!(probe(&client, &t).await.up);
+        t.host = "not-exist.invalid".to_string();
+        assert!(!probe(&client, &t).await.up);
+    }
+}
@@ -0,0 +1,587 @@
+//! ローカルのサービスとプロセスの監視。
+//!
+//! 設定された監視対象 ([WatchTarget]) の systemd ユニットを [systemd] で、
+//! プロセスを [process] で定期的に確認する。
+//! 停止と回復を [Notify] として発行し、オーナーの通知先に知らせる。
+//!
+//! 停止中の監視対象に再起動コマンドが設定されていれば実行する。
+//! 再起動を繰り返さないよう、1時間あたりの実行回数を
+//! [WatchTarget::restart_per_hour] までに制限する。
+//!
+//! ```toml
+//! [watchdog]
+//! enabled = true
+//! check_schedule = "* * * * *"
+//! discord_ch = 123456789
+//! line_to = "U0123456789abcdef"
+//! flap_threshold = 2
+//!
+//! [[watchdog.targets]]
+//! name = "nginx"
+//! type = "unit"
+//! unit = "nginx.service"
+//! restart_command = ["sudo", "systemctl", "restart", "nginx.service"]
+//! restart_per_hour = 3
+//!
+//! [[watchdog.targets]]
+//! name = "motion"
+//! type = "process"
+//! comm = "motion"
+//! ```
+
+pub mod process;
+pub mod systemd;
+
+use super::SystemModule;
+use super::status::{Status, StatusTracker};
+use crate::config::{self, ValidationReport};
+use crate::dryrun;
+use crate::taskserver;
+use crate::taskserver::event::Notify;
+use crate::taskserver::{Control, schedule::Schedule};
+use anyhow::{Context, Result, bail, ensure};
+use chrono::{DateTime, Local};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// 再起動コマンドのタイムアウト。
+const RESTART_TIMEOUT: Duration = Duration::from_secs(60);
+/// 再起動回数を数える期間。
+const RESTART_WINDOW: Duration = Duration::from_secs(3600);
+
+/// 監視設定データ。toml 設定に対応する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    /// 監視機能を有効化する。
+    enabled: bool,
+    /// 確認タスクの起動スケジュール。
+    /// 書式は [crate::taskserver::schedule] を参照。
+    check_schedule: String,
+    /// 通知先 Discord チャネル ID。0 ならば通知しない。
+    #[serde(default)]
+    discord_ch: u64,
+    /// 通知先の LINE ユーザまたはグループ ID。空ならば通知しない。
+    #[serde(default)]
+    line_to: String,
+    /// 現在の状態と異なる結果がこの回数続いたら状態を変える。
+    #[serde(default = "default_flap_threshold")]
+    flap_threshold: u32,
+    /// 監視対象のリスト。
+    #[serde(default)]
+    targets: Vec<WatchTarget>,
+}
+
+fn default_flap_threshold() -> u32 {
+    2
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            check_schedule: "* * * * *".to_string(),
+            discord_ch: 0,
+            line_to: String::new(),
+            flap_threshold: default_flap_threshold(),
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl WatchdogConfig {
+    pub(crate) fn validate(&self, report: &mut ValidationReport) {
+        report.check_monitor(
+            "watchdog",
+            &self.check_schedule,
+            self.flap_threshold,
+            self.targets.iter().map(|target| target.name.as_str()),
+        );
+        for (i, target) in self.targets.iter().enumerate() {
+            target.validate(&format!("watchdog.targets[{i}]"), report);
+        }
+    }
+}
+
+/// 確認方法。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchKind {
+    /// [WatchTarget::unit] の systemd ユニットが稼働中かを確認する。
+    Unit,
+    /// 名前が [WatchTarget::comm] のプロセスが存在するかを確認する。
+    Process,
+}
+
+/// 監視対象。toml 設定に対応する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchTarget {
+    /// 名前。通知に使う。
+    pub name: String,
+    /// 確認方法。
+    #[serde(rename = "type")]
+    pub kind: WatchKind,
+    /// unit のユニット名。
+    #[serde(default)]
+    pub unit: String,
+    /// process のプロセス名。
+    /// 16 文字以上の場合はカーネルと同様に先頭 15 バイトで比較する。
+    #[serde(default)]
+    pub comm: String,
+    /// 停止中に実行する再起動コマンドと引数。空ならば再起動しない。
+    #[serde(default)]
+    pub restart_command: Vec<String>,
+    /// 1時間あたりの再起動コマンドの最大実行回数。
+    #[serde(default = "default_restart_per_hour")]
+    pub restart_per_hour: u32,
+}
+
+fn default_restart_per_hour() -> u32 {
+    3
+}
+
+impl WatchTarget {
+    fn validate(&self, path: &str, report: &mut ValidationReport) {
+        if self.name.is_empty() {
+            report.add(&format!("{path}.name"), "must not be empty");
+        }
+        match self.kind {
+            WatchKind::Unit => {
+                report.require(&format!("{path}.unit"), &self.unit, "type = \"unit\"");
+                if self.unit.starts_with('-') {
+                    report.add(&format!("{path}.unit"), "must not start with '-'");
+                }
+            }
+            WatchKind::Process => {
+                report.require(&format!("{path}.comm"), &self.comm, "type = \"process\"");
+            }
+        }
+        if !self.restart_command.is_empty() {
+            if self.restart_command[0].is_empty() {
+                report.add(
+                    &format!("{path}.restart_command"),
+                    "program must not be empty",
+                );
+            }
+            if self.restart_per_hour == 0 {
+                report.add(&format!("{path}.restart_per_hour"), "must be 1 or more");
+            }
+        }
+    }
+}
+
+/// 1時間あたりの再起動回数の制限。
+#[derive(Debug, Clone, Default)]
+pub struct RestartBudget {
+    /// 直近 [RESTART_WINDOW] 以内の再起動時刻。古いものが前。
+    history: VecDeque<Instant>,
+}
+
+impl RestartBudget {
+    /// 直近 [RESTART_WINDOW] 以内の再起動回数が `per_hour` 未満ならば記録して true を返す。
+    pub fn try_acquire(&mut self, now: Instant, per_hour: u32) -> bool {
+        while self
+            .history
+            .front()
+            .is_some_and(|&t| now.saturating_duration_since(t) >= RESTART_WINDOW)
+        {
+            self.history.pop_front();
+        }
+        if self.history.len() >= per_hour as usize {
+            return false;
+        }
+        self.history.push_back(now);
+
+        true
+    }
+}
+
+/// 監視対象ごとの状態。
+#[derive(Debug, Clone, Default)]
+struct TargetState {
+    /// 状態。
+    tracker: StatusTracker,
+    /// 再起動回数の制限。
+    budget: RestartBudget,
+    /// 再起動回数の上限に達したことを通知済み。回復したら戻す。
+    budget_notified: bool,
+}
+
+/// 1回の確認結果。
+#[derive(Debug, Clone)]
+struct CheckResult {
+    /// 稼働中。
+    up: bool,
+    /// 停止の場合はその理由。
+    message: String,
+}
+
+/// [Watchdog::apply_results] の結果。
+#[derive(Debug, Default)]
+struct Actions {
+    /// 通知するテキスト。
+    texts: Vec<String>,
+    /// 再起動する監視対象の名前と再起動コマンド。
+    restarts: Vec<(String, Vec<String>)>,
+}
+
+/// 監視システムモジュール。
+pub struct Watchdog {
+    /// 設定データ。
+    config: WatchdogConfig,
+    /// 確認タスクの起動スケジュール。
+    schedule: Schedule,
+    /// 監視対象の名前から状態へのマップ。
+    targets: BTreeMap<String, TargetState>,
+}
+
+impl Watchdog {
+    /// コンストラクタ。
+    ///
+    /// 設定の読み込みのみ行い、async task の初期化は [Self::on_start] で行う。
+    pub fn new() -> Result<Self> {
+        info!("[watchdog] initialize");
+
+        let config: WatchdogConfig = config::get(|cfg| cfg.watchdog.clone());
+        let schedule = config
+            .check_schedule
+            .parse()
+            .context("watchdog.check_schedule")?;
+        let targets = config
+            .targets
+            .iter()
+            .map(|target| (target.name.clone(), TargetState::default()))
+            .collect();
+
+        Ok(Self {
+            config,
+            schedule,
+            targets,
+        })
+    }
+
+    /// 通知する。
+    fn notify(&self, ctrl: &Control, text: String) {
+        info!("[watchdog] {text}");
+        ctrl.publish(Notify {
+            text,
+            discord_ch: self.config.discord_ch,
+            line_to: self.config.line_to.clone(),
+        });
+    }
+
+    /// 確認結果を反映し、通知するテキストと再起動するものを返す。
+    ///
+    /// 未確定から稼働への遷移は通知しない。
+    /// 再起動は停止中で、今回の確認でも停止していたものだけを対象とする。
+    fn apply_results(
+        &mut self,
+        now: DateTime<Local>,
+        instant: Instant,
+        results: Vec<(WatchTarget, CheckResult)>,
+    ) -> Actions {
+        let threshold = self.config.flap_threshold;
+        let mut actions = Actions::default();
+        let texts = &mut actions.texts;
+        for (target, result) in results {
+            // 確認中に設定が変更された
+            let Some(state) = self.targets.get_mut(&target.name) else {
+                continue;
+            };
+
+            if let Some(old) = state.tracker.update(result.up, threshold, now) {
+                match state.tracker.status {
+                    Status::Down => {
+                        texts.push(format!(
+                            "[Watchdog] {} is DOWN: {}",
+                            target.name, result.message
+                        ));
+                    }
+                    _ => {
+                        state.budget_notified = false;
+                        if old != Status::Unknown {
+                            texts.push(format!("[Watchdog] {} is UP", target.name));
+                        }
+                    }
+                }
+            }
+
+            // 回復しかけているものは再起動しない
+            if result.up
+                || state.tracker.status != Status::Down
+                || target.restart_command.is_empty()
+            {
+                continue;
+            }
+            if state.budget.try_acquire(instant, target.restart_per_hour) {
+                actions.restarts.push((target.name, target.restart_command));
+            } else if !state.budget_notified {
+                state.budget_notified = true;
+                texts.push(format!(
+                    "[Watchdog] {}: restart skipped (limit {} per hour reached)",
+                    target.name, target.restart_per_hour
+                ));
+            }
+        }
+
+        actions
+    }
+
+    /// 確認タスク。
+    ///
+    /// 確認中と再起動中はロックを解放する。
+    async fn check_task(ctrl: Control) -> Result<()> {
+        let targets = {
+            let watchdog = ctrl.sysmods().watchdog.lock().await;
+            watchdog.config.targets.clone()
+        };
+
+        let mut results = Vec::with_capacity(targets.len());
+        for target in targets {
+            let result = check(&target).await;
+            if !result.up {
+                warn!(
+                    "[watchdog] {} is not running: {}",
+                    target.name, result.message
+                );
+            }
+            results.push((target, result));
+        }
+
+        let restarts = {
+            let mut watchdog = ctrl.sysmods().watchdog.lock().await;
+            let actions = watchdog.apply_results(Local::now(), Instant::now(), results);
+            for text in actions.texts {
+                watchdog.notify(&ctrl, text);
+            }
+            actions.restarts
+        };
+
+        for (name, command) in restarts {
+            if dryrun::is_enabled() {
+                info!("[watchdog] dry-run: restart {name} skipped: {command:?}");
+                continue;
+            }
+            info!("[watchdog] restart {name}: {command:?}");
+            let text = match run_restart(&command).await {
+                Ok(()) => format!("[Watchdog] {name}: restart command succeeded"),
+                Err(e) => {
+                    error!("[watchdog] restart {name} failed: {e:#}");
+                    format!("[Watchdog] {name}: restart command failed: {e:#}")
+                }
+            };
+            let watchdog = ctrl.sysmods().watchdog.lock().await;
+            watchdog.notify(&ctrl, text);
+        }
+
+        Ok(())
+    }
+}
+
+impl SystemModule for Watchdog {
+    fn on_start(&mut self, ctrl: &Control) {
+        info!("[watchdog] on_start");
+        if self.config.enabled {
+            taskserver::spawn_periodic_task(
+                ctrl,
+                "watchdog-check",
+                &self.schedule,
+                Watchdog::check_task,
+            );
+        }
+    }
+}
+
+/// 監視対象を1回確認する。
+///
+/// 確認自体に失敗した場合も停止とみなす。
+async fn check(target: &WatchTarget) -> CheckResult {
+    let result = match target.kind {
+        WatchKind::Unit => check_unit(&target.unit).await,
+        WatchKind::Process => check_process(&target.comm).await,
+    };
+
+    match result {
+        Ok(None) => CheckResult {
+            up: true,
+            message: String::new(),
+        },
+        Ok(Some(message)) => CheckResult { up: false, message },
+        Err(e) => CheckResult {
+            up: false,
+            message: format!("{e:#}"),
+        },
+    }
+}
+
+/// 稼働中ならば None を、停止中ならばその理由を返す。
+async fn check_unit(unit: &str) -> Result<Option<String>> {
+    if systemd::is_active(unit).await? {
+        return Ok(None);
+    }
+    let status = systemd::show(unit).await?;
+
+    Ok(Some(status.summary()))
+}
+
+/// 存在すれば None を、存在しなければその理由を返す。
+async fn check_process(comm: &str) -> Result<Option<String>> {
+    let comm = comm.to_string();
+    let pids =
+        tokio::task::spawn_blocking(move || process::find_processes(Path::new("/proc"), &comm))
+            .await??;
+
+    Ok(pids.is_empty().then(|| "process not found".to_string()))
+}
+
+/// 再起動コマンドを実行する。
+///
+/// 終了コードが 0 以外ならば標準エラー出力を含めてエラーとする。
+async fn run_restart(command: &[String]) -> Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("empty command");
+    };
+    let output = Command::new(program).args(args).kill_on_drop(true).output();
+    let output = tokio::time::timeout(RESTART_TIMEOUT, output)
+        .await
+        .with_context(|| format!("timeout ({} s)", RESTART_TIMEOUT.as_secs()))??;
+    ensure!(
+        output.status.success(),
+        "{}: {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysmod::status::test_target;
+
+    #[test]
+    fn validate() {
+        let mut config = WatchdogConfig::default();
+        let mut unit: WatchTarget = test_target("nginx", "unit");
+        unit.unit = "nginx.service".to_string();
+        unit.restart_command = vec!["systemctl".to_string(), "restart".to_string()];
+        let mut process: WatchTarget = test_target("motion", "process");
+        process.comm = "motion".to_string();
+        config.targets = vec![unit, process];
+        let mut report = ValidationReport::default();
+        config.validate(&mut report);
+        assert!(report.is_empty());
+
+        let mut unit: WatchTarget = test_target("nginx", "unit");
+        unit.restart_command = vec![String::new()];
+        unit.restart_per_hour = 0;
+        let mut option: WatchTarget = test_target("option", "unit");
+        option.unit = "--help".to_string();
+        config.targets = vec![
+            unit,
+            test_target("nginx", "process"),
+            test_target("", "unit"),
+            option,
+        ];
+        config.flap_threshold = 0;
+        let mut report = ValidationReport::default();
+        config.validate(&mut report);
+        assert_eq!(
+            report.paths(),
+            [
+                "watchdog.flap_threshold",
+                "watchdog.targets[1].name",
+                "watchdog.targets[0].unit",
+                "watchdog.targets[0].restart_command",
+                "watchdog.targets[0].restart_per_hour",
+                "watchdog.targets[1].comm",
+                "watchdog.targets[2].name",
+                "watchdog.targets[2].unit",
+                "watchdog.targets[3].unit",
+            ]
+        );
+    }
+
+    #[test]
+    fn budget() {
+        let t0 = Instant::now();
+        let at = |min: u64| t0 + Duration::from_secs(min * 60);
+        let mut budget = RestartBudget::default();
+
+        assert!(budget.try_acquire(at(0), 3));
+        assert!(budget.try_acquire(at(10), 3));
+        assert!(budget.try_acquire(at(20), 3));
+        assert!(!budget.try_acquire(at(30), 3));
+        assert!(!budget.try_acquire(at(59), 3));
+        assert_eq!(budget.history.len(), 3);
+
+        // 1時間経過した分から使えるようになる
+        assert!(budget.try_acquire(at(60), 3));
+        assert!(!budget.try_acquire(at(61), 3));
+        // 10 分と 20 分の分が期限切れ
+        assert!(budget.try_acquire(at(80), 3));
+        assert_eq!(budget.history.len(), 2);
+
+        assert!(!budget.try_acquire(at(200), 0));
+    }
+
+    #[test]
+    fn apply_results() {
+        let mut nginx: WatchTarget = test_target("nginx", "unit");
+        nginx.unit = "nginx.service".to_string();
+        nginx.restart_command = vec!["true".to_string()];
+        let config = WatchdogConfig {
+            targets: vec![nginx.clone()],
+            ..Default::default()
+        };
+        let mut watchdog = Watchdog {
+            schedule: config.check_schedule.parse().unwrap(),
+            targets: [(nginx.name.clone(), TargetState::default())].into(),
+            config,
+        };
+
+        let now = Local::now();
+        let instant = Instant::now();
+        let mut texts = Vec::new();
+        let mut restarts = 0;
+        for up in [false, false, true, true] {
+            let result = CheckResult {
+                up,
+                message: if up { "" } else { "inactive" }.to_string(),
+            };
+            let actions = watchdog.apply_results(now, instant, vec![(nginx.clone(), result)]);
+            texts.extend(actions.texts);
+            restarts += actions.restarts.len();
+        }
+
+        // 停止が確定した回だけ再起動し、回復しかけている間は再起動しない
+        assert_eq!(restarts, 1);
+        assert_eq!(
+            texts,
+            [
+                "[Watchdog] nginx is DOWN: inactive",
+                "[Watchdog] nginx is UP",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn restart_command() {
+        run_restart(&["true".to_string()]).await.unwrap();
+
+        let e = run_restart(&[
+            "sh".to_string(),
+            "-c".to_string(),
+            "echo oops >&2; exit 3".to_string(),
+        ])
+        .await
+        .unwrap_err();
+        assert!(format!("{e:#}").ends_with(": oops"), "{e:#}");
+
+        assert!(run_restart(&[]).await.is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! プロセスの存在確認。
+//!
+//! _/proc/&lt;pid&gt;/comm_ を走査してプロセス名で探す。
+
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// カーネルが保持するプロセス名の最大長 (TASK_COMM_LEN - 1)。
+pub const COMM_MAX_LEN: usize = 15;
+
+/// カーネルが保持する形に切り詰めたプロセス名。
+///
+/// カーネルは文字境界に関係なくバイト単位で切り詰める。
+pub fn truncate_comm(name: &str) -> &[u8] {
+    let bytes = name.as_bytes();
+    &bytes[..bytes.len().min(COMM_MAX_LEN)]
+}
+
+/// `proc_dir` (通常は `/proc`) 以下から名前が `comm` のプロセスの PID を探す。
+///
+/// ブロッキング処理なので非同期タスクからは spawn_blocking で呼ぶこと。
+/// 走査中に終了したプロセスは無視する。
+pub fn find_processes(proc_dir: &Path, comm: &str) -> Result<Vec<u32>> {
+    let comm = truncate_comm(comm);
+    let mut result = Vec::new();
+    let entries = std::fs::read_dir(proc_dir).with_context(|| format!("{}", proc_dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let Ok(name) = std::fs::read(entry.path().join("comm")) else {
+            continue;
+        };
+        if name.strip_suffix(b"\n").unwrap_or(&name) == comm {
+            result.push(pid);
+        }
+    }
+    result.sort();
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate() {
+        assert_eq!(truncate_comm("nginx"), b"nginx");
+        assert_eq!(truncate_comm("0123456789abcde"), b"0123456789abcde");
+        assert_eq!(truncate_comm("0123456789abcdef"), b"0123456789abcde");
+        // 文字の途中でも切る
+        assert_eq!(truncate_comm("0123456789abcあ"), b"0123456789abc\xe3\x81");
+    }
+
+    #[test]
+    fn find() {
+        let tmp = tempfile::tempdir().unwrap();
+        for (pid, comm) in [
+            ("1", "systemd"),
+            ("100", "motion"),
+            ("200", "motion"),
+            ("300", "0123456789abcde"),
+        ] {
+            let dir = tmp.path().join(pid);
+            std::fs::create_dir(&dir).unwrap();
+            std::fs::write(dir.join("comm"), format!("{comm}\n")).unwrap();
+        }
+        // プロセス以外のエントリ
+        std::fs::create_dir(tmp.path().join("self")).unwrap();
+        std::fs::write(tmp.path().join("uptime"), "1.0 2.0\n").unwrap();
+        // 終了したプロセス
+        std::fs::create_dir(tmp.path().join("400")).unwrap();
+
+        assert_eq!(find_processes(tmp.path(), "motion").unwrap(), [100, 200]);
+        assert_eq!(
+            find_processes(tmp.path(), "0123456789abcdefg").unwrap(),
+            [300]
+        );
+        assert!(find_processes(tmp.path(), "nginx").unwrap().is_empty());
+        assert!(find_processes(&tmp.path().join("none"), "motion").is_err());
+    }
+
+    #[test]
+    fn current() {
+        let comm = std::fs::read_to_string("/proc/self/comm").unwrap();
+        let pids = find_processes(Path::new("/proc"), comm.trim_end()).unwrap();
+        assert!(pids.contains(&std::process::id()), "{pids:?}");
+    }
+}
@@ -0,0 +1,174 @@
+//! systemd ユニットの状態確認。
+//!
+//! `systemctl is-active` で稼働中かを判定し、
+//! 停止していれば `systemctl show` で理由を調べる。
+
+use anyhow::{Context, Result, ensure};
+use std::collections::HashMap;
+use std::process::Output;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// `systemctl` のタイムアウト。
+const SYSTEMCTL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `systemctl show` で取得するプロパティ。
+const SHOW_PROPERTIES: &str = "LoadState,ActiveState,SubState,Result,ExecMainStatus,NRestarts";
+
+/// ユニットの詳細状態。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitStatus {
+    /// `loaded`, `not-found` 等。
+    pub load_state: String,
+    /// `active`, `failed` 等。
+    pub active_state: String,
+    /// `running`, `dead` 等。
+    pub sub_state: String,
+    /// 最後の停止理由。`success`, `exit-code` 等。
+    pub result: String,
+    /// メインプロセスの終了コード。
+    pub exec_main_status: Option<i32>,
+    /// systemd による自動再起動の回数。
+    pub n_restarts: Option<u32>,
+}
+
+impl UnitStatus {
+    /// 通知用の要約。
+    pub fn summary(&self) -> String {
+        if self.load_state != "loaded" {
+            return format!("unit {}", self.load_state);
+        }
+        let mut s = format!("{} ({})", self.active_state, self.sub_state);
+        if self.result != "success" {
+            s.push_str(&format!(", result={}", self.result));
+        }
+        if let Some(status) = self.exec_main_status.filter(|&status| status != 0) {
+            s.push_str(&format!(", status={status}"));
+        }
+        if let Some(n) = self.n_restarts.filter(|&n| n > 0) {
+            s.push_str(&format!(", restarts={n}"));
+        }
+
+        s
+    }
+}
+
+/// `systemctl is-active` の出力が稼働中を表すならば true を返す。
+///
+/// 停止中の場合は終了コードが 0 以外になるため、終了コードではなく出力で判定する。
+pub fn parse_is_active(stdout: &str) -> bool {
+    matches!(stdout.trim(), "active" | "reloading")
+}
+
+/// `systemctl show --property=...` の `Key=Value` 形式の出力を解析する。
+pub fn parse_show(stdout: &str) -> Result<UnitStatus> {
+    let props: HashMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    let get = |key: &str| props.get(key).map(|v| v.to_string()).unwrap_or_default();
+
+    let status = UnitStatus {
+        load_state: get("LoadState"),
+        active_state: get("ActiveState"),
+        sub_state: get("SubState"),
+        result: get("Result"),
+        exec_main_status: props.get("ExecMainStatus").and_then(|v| v.parse().ok()),
+        n_restarts: props.get("NRestarts").and_then(|v| v.parse().ok()),
+    };
+    ensure!(
+        !status.active_state.is_empty(),
+        "ActiveState not found: {stdout}"
+    );
+
+    Ok(status)
+}
+
+/// `systemctl` を実行する。
+///
+/// ユニット名がオプションとして解釈されないよう、`--` の後に置く。
+/// 終了しない場合は [SYSTEMCTL_TIMEOUT] で kill する。
+async fn systemctl(args: &[&str], unit: &str) -> Result<Output> {
+    let output = Command::new("systemctl")
+        .args(args)
+        .args(["--", unit])
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(SYSTEMCTL_TIMEOUT, output)
+        .await
+        .with_context(|| format!("systemctl timeout ({} s)", SYSTEMCTL_TIMEOUT.as_secs()))??;
+
+    Ok(output)
+}
+
+/// ユニットが稼働中ならば true を返す。
+pub async fn is_active(unit: &str) -> Result<bool> {
+    let output = systemctl(&["is-active"], unit).await?;
+
+    Ok(parse_is_active(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// ユニットの詳細状態を取得する。
+pub async fn show(unit: &str) -> Result<UnitStatus> {
+    let output = systemctl(&["show", &format!("--property={SHOW_PROPERTIES}")], unit).await?;
+    ensure!(
+        output.status.success(),
+        "systemctl show failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+
+    parse_show(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/res/test/systemd/",
+                $name
+            ))
+        };
+    }
+
+    #[test]
+    fn is_active() {
+        assert!(parse_is_active("active\n"));
+        assert!(parse_is_active("reloading\n"));
+        assert!(!parse_is_active("inactive\n"));
+        assert!(!parse_is_active("failed\n"));
+        assert!(!parse_is_active("activating\n"));
+        assert!(!parse_is_active(""));
+    }
+
+    #[test]
+    fn show() {
+        let status = parse_show(fixture!("show_failed")).unwrap();
+        assert_eq!(
+            status,
+            UnitStatus {
+                load_state: "loaded".to_string(),
+                active_state: "failed".to_string(),
+                sub_state: "failed".to_string(),
+                result: "exit-code".to_string(),
+                exec_main_status: Some(1),
+                n_restarts: Some(5),
+            }
+        );
+        assert_eq!(
+            status.summary(),
+            "failed (failed), result=exit-code, status=1, restarts=5"
+        );
+
+        let status = parse_show(fixture!("show_active")).unwrap();
+        assert_eq!(status.summary(), "active (running)");
+
+        let status = parse_show(fixture!("show_not_found")).unwrap();
+        assert_eq!(status.summary(), "unit not-found");
+
+        assert!(parse_show("").is_err());
+    }
+}
@@ -1,14 +1,31 @@
 //! 非同期タスクを管理する。
 
+pub mod admin;
+pub mod clock;
+pub mod event;
+pub mod lock;
+pub mod registry;
+pub mod schedule;
+pub mod sdnotify;
+
+use self::clock::{Clock, SystemClock};
+use self::event::{BootCompleted, Event, EventBus, Notify};
+use self::registry::{TaskEntry, TaskKind, TaskRegistry};
+use self::schedule::Schedule;
+use crate::config;
 use crate::sysmod::SystemModules;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use chrono::prelude::*;
-use log::{error, info, trace};
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
 use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 use tokio::select;
 use tokio::signal::unix::{SignalKind, signal};
-use tokio::sync::watch;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::AbortHandle;
 
 /// システムシャットダウン開始通知送信側 (単数)
 type CancelTx = watch::Sender<bool>;
@@ -28,10 +45,169 @@ pub struct Controller {
     /// また、シャットダウンシーケンスにおいて、全タスクの完了待ちのためにも使う。
     /// サーバ側は clone されたこれがすべて drop されるまで待機する。
     cancel_rx: std::sync::Mutex<Option<CancelRx>>,
+    /// 実行されたタスクの記録。
+    tasks: Arc<TaskRegistry>,
+    /// 生成されたタスクのハンドルのリスト。
+    ///
+    /// シャットダウン時に完了待ちと強制中断のために使う。
+    /// また、システムモジュールの再起動時にそのモジュールのタスクを中断するために使う。
+    handles: std::sync::Mutex<Vec<TaskHandle>>,
+    /// システムモジュールごとの再起動通知の送信側。
+    /// [Controller::wait_cancel_rx] で必要になった時に作成される。
+    owner_cancel: std::sync::Mutex<HashMap<&'static str, watch::Sender<bool>>>,
+    /// 周期タスクのスケジューリングに使う時計。
+    clock: Arc<dyn Clock>,
+    /// システムモジュール間のイベントバス。
+    events: EventBus,
+    /// [TaskServer::run] への終了リクエストの送信側。
+    exit_tx: mpsc::UnboundedSender<RunResult>,
+    /// 周期タスクを即時実行する関数。タスク名をキーとする。
+    triggers: std::sync::Mutex<HashMap<String, Trigger>>,
+    /// 起動完了の報告待ちの名前 ([Controller::expect_ready])。
+    pending_ready: watch::Sender<BTreeSet<&'static str>>,
 }
 
 pub type Control = Arc<Controller>;
 
+/// [trigger_periodic_task] で呼ばれる、周期タスクを1回分生成する関数。
+type Trigger = Arc<dyn Fn(&Control) + Send + Sync>;
+
+/// 生成されたタスクの中断用ハンドル。
+struct TaskHandle {
+    /// タスク名。
+    name: String,
+    /// 生成したシステムモジュール名。[Controller::with_task_owner] を参照。
+    owner: Option<&'static str>,
+    handle: AbortHandle,
+}
+
+thread_local! {
+    /// [Controller::with_task_owner] で設定される、現在のタスク生成元モジュール名。
+    static TASK_OWNER: std::cell::Cell<Option<&'static str>> = const { std::cell::Cell::new(None) };
+}
+
+tokio::task_local! {
+    /// タスクの生成元モジュール名。
+    /// タスク内で生成されたタスクはこれを引き継ぐ。
+    static TASK_OWNER_SCOPE: Option<&'static str>;
+}
+
+/// 現在のタスク生成元モジュール名を返す。
+fn current_task_owner() -> Option<&'static str> {
+    TASK_OWNER
+        .get()
+        .or_else(|| TASK_OWNER_SCOPE.try_with(|owner| *owner).ok().flatten())
+}
+
+/// タスクの完了待ちでのポーリング間隔。
+const TASK_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// タスクサーバ設定データ。toml 設定に対応する。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskServerConfig {
+    /// シャットダウン時に全タスクの完了を待つ最大時間 (秒)。
+    /// これを過ぎても完了しないタスクは強制的に中断される。
+    ///
+    /// systemd の TimeoutStopSec より短くすること。
+    #[serde(default = "default_shutdown_timeout_sec")]
+    pub shutdown_timeout_sec: u64,
+    /// システムモジュールのロック待ちがこの時間 (秒) を超えるたびに警告ログを出す。
+    /// 0 は無効。
+    #[serde(default = "default_lock_wait_warn_sec")]
+    pub lock_wait_warn_sec: u64,
+    /// systemd への起動完了通知 (READY=1) を、
+    /// 各システムモジュールの起動完了報告を待って遅延する最大時間 (秒)。
+    /// これを過ぎると、報告のないモジュールがあっても通知する。
+    ///
+    /// systemd の TimeoutStartSec より短くすること。
+    #[serde(default = "default_ready_timeout_sec")]
+    pub ready_timeout_sec: u64,
+    /// タスクの連続エラー ([TaskPolicy::alert_after]) の通知先 Discord チャネル ID。
+    /// 0 ならば通知しない。
+    #[serde(default)]
+    pub alert_discord_ch: u64,
+    /// タスクの連続エラーの通知先 LINE ユーザまたはグループ ID。空ならば通知しない。
+    #[serde(default)]
+    pub alert_line_to: String,
+}
+
+fn default_shutdown_timeout_sec() -> u64 {
+    15
+}
+
+fn default_lock_wait_warn_sec() -> u64 {
+    10
+}
+
+fn default_ready_timeout_sec() -> u64 {
+    60
+}
+
+impl Default for TaskServerConfig {
+    fn default() -> Self {
+        Self {
+            shutdown_timeout_sec: default_shutdown_timeout_sec(),
+            lock_wait_warn_sec: default_lock_wait_warn_sec(),
+            ready_timeout_sec: default_ready_timeout_sec(),
+            alert_discord_ch: 0,
+            alert_line_to: String::new(),
+        }
+    }
+}
+
+/// 周期タスクの実行ポリシー。toml 設定に対応する。
+///
+/// [crate::config::Config::tasks] にタスク名をキーとして設定する。
+///
+/// ```toml
+/// [tasks.camera-auto]
+/// retry = 3
+/// catch_up = true
+/// alert_after = 3
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TaskPolicy {
+    /// エラー終了時のリトライ回数。
+    pub retry: u32,
+    /// 1回目のリトライまでの待ち時間 (秒)。以降 2 倍ずつ増える。
+    pub retry_backoff_sec: u64,
+    /// リトライ待ち時間の上限 (秒)。
+    pub retry_backoff_max_sec: u64,
+    /// スケジュール上の起動時刻を逃していた場合 (サスペンドや時刻の変更等)、
+    /// 起床時に1回だけ実行する。
+    pub catch_up: bool,
+    /// この回数連続でエラー終了した場合に通知する。0 は無効。
+    /// 通知先は [TaskServerConfig::alert_discord_ch] と [TaskServerConfig::alert_line_to]。
+    pub alert_after: u32,
+}
+
+impl Default for TaskPolicy {
+    fn default() -> Self {
+        Self {
+            retry: 0,
+            retry_backoff_sec: 10,
+            retry_backoff_max_sec: 600,
+            catch_up: false,
+            alert_after: 0,
+        }
+    }
+}
+
+impl TaskPolicy {
+    /// `attempt` 回目 (1 origin) のリトライ前の待ち時間を返す。
+    fn backoff(&self, attempt: u32) -> std::time::Duration {
+        let factor = 1u64
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        let sec = self
+            .retry_backoff_sec
+            .saturating_mul(factor)
+            .min(self.retry_backoff_max_sec);
+        std::time::Duration::from_secs(sec)
+    }
+}
+
 /// [TaskServer::run] の返す実行終了種別。
 pub enum RunResult {
     Shutdown,
@@ -46,6 +222,8 @@ pub struct TaskServer {
     /// <https://tokio.rs/tokio/topics/shutdown>
     /// "Telling things to shut down" + "Waiting for things to finish shutting down"
     cancel_tx: CancelTx,
+    /// [Controller::request_exit] による終了リクエストの受信側。
+    exit_rx: mpsc::UnboundedReceiver<RunResult>,
 }
 
 impl Controller {
@@ -55,16 +233,258 @@ impl Controller {
     }
 
     /// キャンセル通知を待つ。
+    ///
+    /// システムモジュールに属するタスクでは、
+    /// そのモジュールの再起動 ([Self::stop_owned_tasks]) も通知される。
     pub async fn wait_cancel_rx(&self) {
         // mutex をロックして Receiver を取得し、その clone を作る
         let org = self.cancel_rx.lock().unwrap().as_mut().map(|rx| rx.clone());
-        if let Some(mut rx) = org {
-            // clone した Receiver 上で待つ
-            rx.changed().await.unwrap();
+        let owner_rx = current_task_owner().map(|owner| {
+            let mut owner_cancel = self.owner_cancel.lock().unwrap();
+            owner_cancel
+                .entry(owner)
+                .or_insert_with(|| watch::channel(false).0)
+                .subscribe()
+        });
+
+        let global = async {
+            if let Some(mut rx) = org {
+                // clone した Receiver 上で待つ
+                rx.changed().await.unwrap();
+            } else {
+                // 既に drop されていた場合はすぐに返る
+            }
+        };
+        let owner = async {
+            match owner_rx {
+                // 送信側の drop も再起動とみなす
+                Some(mut rx) => {
+                    let _ = rx.changed().await;
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = global => {}
+            _ = owner => {}
+        }
+    }
+
+    /// イベントを発行する。
+    ///
+    /// 受信側は [spawn_event_task] で登録する。
+    pub fn publish<E: Event>(&self, event: E) {
+        self.events.publish(event);
+    }
+
+    /// [TaskServer::run] に実行終了をリクエストする。
+    pub fn request_exit(&self, result: RunResult) {
+        // 受信側は run 終了まで存在する
+        let _ = self.exit_tx.send(result);
+    }
+
+    /// `name` の起動完了の報告 ([Self::report_ready]) を待つよう登録する。
+    ///
+    /// systemd への起動完了通知 ([sdnotify]) は全ての報告が揃うまで遅延される。
+    /// Discord への接続等、完了まで時間のかかる初期化を持つシステムモジュールが
+    /// [crate::sysmod::SystemModule::on_start] 内で呼ぶ。
+    pub fn expect_ready(&self, name: &'static str) {
+        self.pending_ready.send_modify(|pending| {
+            pending.insert(name);
+        });
+    }
+
+    /// `name` の起動完了を報告する。
+    pub fn report_ready(&self, name: &str) {
+        info!("[ready] {name}");
+        self.pending_ready
+            .send_if_modified(|pending| pending.remove(name));
+    }
+
+    /// 起動完了の報告待ちの名前リスト。
+    pub fn pending_ready(&self) -> Vec<&'static str> {
+        self.pending_ready.borrow().iter().copied().collect()
+    }
+
+    /// [Self::expect_ready] で登録された全ての起動完了の報告を待つ。
+    pub async fn wait_ready(&self) {
+        let mut rx = self.pending_ready.subscribe();
+        // 送信側は self が持っているので失敗しない
+        let _ = rx.wait_for(|pending| pending.is_empty()).await;
+    }
+
+    /// 全タスクの実行状況を名前順で取得する。
+    pub fn task_list(&self) -> Vec<TaskEntry> {
+        self.tasks.list()
+    }
+
+    /// タスクを生成し、そのハンドルを記録する。
+    ///
+    /// 生成元のモジュールはタスク内で生成されたタスクにも引き継がれる。
+    /// 完了済みのハンドルはこのタイミングで削除する。
+    fn spawn_tracked<F>(&self, name: &str, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.spawn_tracked_as(current_task_owner(), name, future);
+    }
+
+    /// 生成元のモジュールを指定して [Self::spawn_tracked] する。
+    fn spawn_tracked_as<F>(&self, owner: Option<&'static str>, name: &str, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let task_name = name.to_string();
+        let handle = self.rt.spawn(TASK_OWNER_SCOPE.scope(owner, async move {
+            let _name = lock::register_task_name(&task_name);
+            future.await
+        }));
+
+        let mut handles = self.handles.lock().unwrap();
+        handles.retain(|h| !h.handle.is_finished());
+        handles.push(TaskHandle {
+            name: name.to_string(),
+            owner,
+            handle: handle.abort_handle(),
+        });
+    }
+
+    /// `owner` を生成元モジュールとして `f` を実行する。
+    ///
+    /// `f` の実行中に生成されたタスクは `owner` に属するものとして記録され、
+    /// [Self::stop_owned_tasks] でまとめて停止できる。
+    pub(crate) fn with_task_owner<F, R>(&self, owner: &'static str, f: F) -> R
+    where
+        F: FnOnce() -> R,
+    {
+        let prev = TASK_OWNER.replace(Some(owner));
+        let result = f();
+        TASK_OWNER.set(prev);
+        result
+    }
+
+    /// `owner` に属するタスクにキャンセルを通知し、完了を待つ。
+    ///
+    /// `timeout` を過ぎても完了しないタスクは中断し、その名前のリストを返す。
+    pub(crate) async fn stop_owned_tasks(
+        &self,
+        owner: &str,
+        timeout: std::time::Duration,
+    ) -> Vec<String> {
+        if let Some(tx) = self.owner_cancel.lock().unwrap().remove(owner) {
+            tx.send_replace(true);
+        }
+
+        let wait = async {
+            while self.has_owned_tasks(owner) {
+                tokio::time::sleep(TASK_POLL_INTERVAL).await;
+            }
+        };
+        if tokio::time::timeout(timeout, wait).await.is_ok() {
+            vec![]
         } else {
-            // 既に drop されていた場合はすぐに返る
+            self.abort_owned_tasks(owner)
         }
     }
+
+    /// `owner` に属する未完了のタスクがあれば true を返す。
+    fn has_owned_tasks(&self, owner: &str) -> bool {
+        let handles = self.handles.lock().unwrap();
+        handles
+            .iter()
+            .any(|h| h.owner == Some(owner) && !h.handle.is_finished())
+    }
+
+    /// `owner` に属する未完了のタスクをすべて中断し、その名前のリストを返す。
+    fn abort_owned_tasks(&self, owner: &str) -> Vec<String> {
+        let mut handles = self.handles.lock().unwrap();
+        let mut names = vec![];
+        handles.retain(|h| {
+            if h.owner != Some(owner) {
+                return true;
+            }
+            if !h.handle.is_finished() {
+                h.handle.abort();
+                names.push(h.name.clone());
+            }
+            false
+        });
+        names
+    }
+
+    /// 未完了のタスク名のリストを返す。
+    fn unfinished_tasks(&self) -> Vec<String> {
+        let mut handles = self.handles.lock().unwrap();
+        handles.retain(|h| !h.handle.is_finished());
+        handles.iter().map(|h| h.name.clone()).collect()
+    }
+
+    /// 全タスクが完了するまで待つ。
+    async fn wait_all_tasks(&self) {
+        while !self.unfinished_tasks().is_empty() {
+            tokio::time::sleep(TASK_POLL_INTERVAL).await;
+        }
+    }
+
+    /// 未完了のタスクをすべて中断し、その名前のリストを返す。
+    fn abort_all_tasks(&self) -> Vec<String> {
+        let mut handles = self.handles.lock().unwrap();
+        let mut names = vec![];
+        for h in handles.drain(..) {
+            if !h.handle.is_finished() {
+                h.handle.abort();
+                names.push(h.name);
+            }
+        }
+        names
+    }
+}
+
+/// 使用時に毎回読み込むため、再ロードするだけで反映されるセクション。
+const LIVE_SECTIONS: &[&str] = &["taskserver"];
+
+/// 設定ファイルを再ロードし、変更のあったシステムモジュールに反映する。
+///
+/// 失敗した場合、設定データは変更されない。
+/// 詳細は [crate::sysmod::SystemModule::on_config_changed] を参照。
+///
+/// システムモジュールにも [LIVE_SECTIONS] にも該当しないセクション
+/// (`tasks`, `dry_run` 等) の変更は反映されず、再起動が必要である。
+/// その場合は警告を出し、そのセクション名を返す。
+pub async fn reload_config(ctrl: &Control) -> Result<Vec<String>> {
+    info!("reload config...");
+    let changes = config::reload()?;
+    let sections: Vec<_> = changes.sections().collect();
+    if sections.is_empty() {
+        info!("OK: reload config (no changes)");
+        return Ok(Vec::new());
+    }
+    info!("changed sections: {}", sections.join(", "));
+
+    ctrl.sysmods.on_config_changed(ctrl, &changes).await;
+
+    let pending = restart_required(&sections, |section| ctrl.sysmods.has_section(section));
+    if pending.is_empty() {
+        info!("OK: reload config");
+    } else {
+        warn!(
+            "reload config: restart required to apply: {}",
+            pending.join(", ")
+        );
+    }
+
+    Ok(pending)
+}
+
+/// `sections` のうち、再ロードでは反映されないものを返す。
+///
+/// * `is_sysmod` - システムモジュールのセクションならば true を返す関数。
+fn restart_required(sections: &[&str], is_sysmod: impl Fn(&str) -> bool) -> Vec<String> {
+    sections
+        .iter()
+        .filter(|&&section| !LIVE_SECTIONS.contains(&section) && !is_sysmod(section))
+        .map(|section| section.to_string())
+        .collect()
 }
 
 /// 1回限りのタスクを生成して実行開始する。
@@ -81,15 +501,21 @@ where
     // move するデータを準備する
     let name = name.to_string();
     let ctrl_move = Arc::clone(ctrl);
+    ctrl.tasks.register(&name, TaskKind::Oneshot);
+    let name_track = name.clone();
 
-    ctrl.rt.spawn(async move {
+    ctrl.spawn_tracked(&name_track, async move {
         info!("[{name}] start (one-shot)");
+        let started = ctrl_move.tasks.start(&name, TaskKind::Oneshot);
 
         // ctrl を clone して future へ move する
         let future = f(Arc::clone(&ctrl_move));
         let result = future.await;
         // drop clone of ctrl
 
+        ctrl_move
+            .tasks
+            .finish(&name, TaskKind::Oneshot, started, &result);
         if let Err(e) = result {
             error!("[{name}] finish (error): {e:?}");
         } else {
@@ -105,12 +531,18 @@ where
 {
     // move するデータを準備する
     let name = name.to_string();
+    // ctrl は move しない (タスクの寿命を延ばさないため) ので記録だけ共有する
+    let tasks = Arc::clone(&ctrl.tasks);
+    tasks.register(&name, TaskKind::Oneshot);
+    let name_track = name.clone();
 
-    ctrl.rt.spawn(async move {
+    ctrl.spawn_tracked(&name_track, async move {
         info!("[{name}] start (one-shot)");
+        let started = tasks.start(&name, TaskKind::Oneshot);
 
         let result = f.await;
 
+        tasks.finish(&name, TaskKind::Oneshot, started, &result);
         if let Err(e) = result {
             error!("[{name}] finish (error): {e:?}");
         } else {
@@ -120,17 +552,149 @@ where
     });
 }
 
+/// 連続エラーのアラートを [Notify] として発行する。
+fn notify_task_alert(ctrl: &Control, name: &str, count: u32, err: &anyhow::Error) {
+    let text = format!("[alert] task \"{name}\" failed {count} times in a row\n{err:#}");
+    error!("{text}");
+
+    let (discord_ch, line_to) = config::get(|cfg| {
+        (
+            cfg.taskserver.alert_discord_ch,
+            cfg.taskserver.alert_line_to.clone(),
+        )
+    });
+    ctrl.publish(Notify {
+        text,
+        discord_ch,
+        line_to,
+    });
+}
+
+/// 周期タスクを1回分実行する。
+///
+/// エラー終了した場合は `policy` に従ってリトライする。
+/// リトライ待ちの間にキャンセルされた場合は false を返す。
+async fn run_periodic_once<F, T>(ctrl: &Control, name: &str, policy: &TaskPolicy, f: &F) -> bool
+where
+    F: Fn(Control) -> T + Send + Sync + 'static,
+    T: Future<Output = Result<()>> + Send + Sync + 'static,
+{
+    let mut attempt = 0;
+    loop {
+        // ctrl を clone して future 内に move する
+        let future = f(Arc::clone(ctrl));
+        info!("[{name}] start (periodic)");
+        let started = ctrl.tasks.start(name, TaskKind::Periodic);
+        let result = future.await;
+        // drop clone of ctrl
+        let failures = ctrl
+            .tasks
+            .finish(name, TaskKind::Periodic, started, &result);
+        let Err(e) = result else {
+            info!("[{name}] finish (success)");
+            return true;
+        };
+        error!("[{name}] finish (error): {e:?}");
+        if policy.alert_after > 0 && failures == policy.alert_after {
+            notify_task_alert(ctrl, name, failures, &e);
+        }
+
+        attempt += 1;
+        if attempt > policy.retry {
+            return true;
+        }
+        let wait = policy.backoff(attempt);
+        warn!(
+            "[{name}] retry {attempt}/{} after {} sec",
+            policy.retry,
+            wait.as_secs()
+        );
+        select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = ctrl.wait_cancel_rx() => {
+                return false;
+            }
+        }
+    }
+}
+
+/// イベント受信タスクを生成する。
+///
+/// `E` 型のイベントが [Controller::publish] で発行されるたびに `f` を実行する。
+/// 受信はこの関数の呼び出し時点で開始されるため、
+/// [crate::sysmod::SystemModule::on_start] 内で呼べば起動後のイベントを取りこぼさない。
+///
+/// 同一タスク内では1つずつ順番に処理される。
+///
+/// F: [Control] とイベントを引数に、T を返す関数。
+/// T: Future<Output = anyhow::Result<()> かつスレッド間移動可能。
+pub fn spawn_event_task<E, F, T>(ctrl: &Control, name: &str, f: F)
+where
+    E: Event,
+    F: Fn(Control, E) -> T + Send + Sync + 'static,
+    T: Future<Output = Result<()>> + Send,
+{
+    // move するデータを準備する
+    let name = name.to_string();
+    let ctrl_move = Arc::clone(ctrl);
+    let mut rx = ctrl.events.subscribe::<E>();
+    ctrl.tasks.register(&name, TaskKind::Event);
+    info!(
+        "[{name}] registered as an event task: {}",
+        std::any::type_name::<E>()
+    );
+    let name_track = name.clone();
+
+    ctrl.spawn_tracked(&name_track, async move {
+        let ctrl = ctrl_move;
+        let cancel = ctrl.wait_cancel_rx();
+        tokio::pin!(cancel);
+        loop {
+            let event = select! {
+                event = rx.recv() => event,
+                _ = &mut cancel => {
+                    info!("[{name}] cancel event task");
+                    return;
+                }
+            };
+            let event = match event {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("[{name}] {count} event(s) dropped");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return;
+                }
+            };
+
+            info!("[{name}] start (event)");
+            let started = ctrl.tasks.start(&name, TaskKind::Event);
+            // ctrl を clone して future へ move する
+            let result = f(Arc::clone(&ctrl), event).await;
+            ctrl.tasks.finish(&name, TaskKind::Event, started, &result);
+            if let Err(e) = result {
+                error!("[{name}] finish (error): {e:?}");
+            } else {
+                info!("[{name}] finish (success)");
+            }
+        }
+        // drop ctrl
+    });
+}
+
 /// 周期タスクを生成する。
 ///
-/// wakeup_list: 起動時刻。以下を満たさないと panic する。
-/// * second 以下が 0 である。(分単位)
-/// * 昇順ソート済みである。
+/// schedule: 起動スケジュール。分単位で評価される。
+///
+/// リトライ等の実行ポリシーは設定ファイルから `name` をキーとして読み込まれる。
+/// 詳細は [TaskPolicy] を参照。
 ///
 /// F: [Control] を引数に、T を返す関数。
 /// T: Future<Output = anyhow::Result<()> かつスレッド間移動可能。
 ///
 /// つまり、F は [Control] を引数に、anyhow::Result<()> を返す async function。
-pub fn spawn_periodic_task<F, T>(ctrl: &Control, name: &str, wakeup_list: &[NaiveTime], f: F)
+pub fn spawn_periodic_task<F, T>(ctrl: &Control, name: &str, schedule: &Schedule, f: F)
 where
     F: Fn(Control) -> T + Send + Sync + 'static,
     T: Future<Output = Result<()>> + Send + Sync + 'static,
@@ -138,24 +702,14 @@ where
     // move するデータを準備する
     let name = name.to_string();
     let ctrl_move = Arc::clone(ctrl);
+    let schedule = schedule.clone();
+    let policy = config::get(|cfg| cfg.tasks.get(&name).cloned().unwrap_or_default());
+    let f = Arc::new(f);
 
-    // 空でなくソート済み、秒以下がゼロなのを確認後
-    // 今日のその時刻からなる NaiveDateTime に変換する
-    assert!(!wakeup_list.is_empty(), "time list is empty");
-    assert!(wakeup_list.is_sorted(), "time list is not sorted");
-    let today = Local::now().date_naive();
-    let mut dt_list: Vec<_> = wakeup_list
-        .iter()
-        .map(|time| {
-            assert_eq!(time.second(), 0);
-            assert_eq!(time.nanosecond(), 0);
-            today.and_time(*time)
-        })
-        .collect();
-
-    // wakeup time list を最初の LOG_LIMIT 個までログに出力する
+    // 直近の起動時刻を最初の LOG_LIMIT 個までログに出力する
     const LOG_LIMIT: usize = 5;
-    let log_iter = wakeup_list.iter().take(LOG_LIMIT);
+    let now = ctrl.clock.now();
+    let log_iter = schedule.iter_after(now).take(LOG_LIMIT);
     let mut str = log_iter.enumerate().fold(String::new(), |sum, (i, v)| {
         let str = if i == 0 {
             format!("{v}")
@@ -164,98 +718,202 @@ where
         };
         sum + &str
     });
-    if wakeup_list.len() > LOG_LIMIT {
-        str += &format!(", ... ({} items)", wakeup_list.len());
-    }
+    str += ", ...";
+    ctrl.tasks.register(&name, TaskKind::Periodic);
     info!("[{name}] registered as a periodic task");
+    info!("[{name}] schedule: {schedule}");
     info!("[{name}] wakeup time: {str}");
+    info!("[{name}] policy: {policy:?}");
+
+    // 即時実行用の関数を登録する
+    let trigger: Trigger = {
+        let owner = current_task_owner();
+        let name = name.clone();
+        let policy = policy.clone();
+        let f = Arc::clone(&f);
+        Arc::new(move |ctrl: &Control| {
+            let ctrl_move = Arc::clone(ctrl);
+            let name_move = name.clone();
+            let policy = policy.clone();
+            let f = Arc::clone(&f);
+            ctrl.spawn_tracked_as(owner, &name, async move {
+                run_periodic_once(&ctrl_move, &name_move, &policy, f.as_ref()).await;
+            });
+        })
+    };
+    ctrl.triggers.lock().unwrap().insert(name.clone(), trigger);
 
     // spawn async task
-    ctrl.rt.spawn(async move {
-        type CDuration = chrono::Duration;
-        type TDuration = tokio::time::Duration;
+    let name_track = name.clone();
+    ctrl.spawn_tracked(&name_track, async move {
+        let ctrl = ctrl_move;
+        periodic_loop(
+            &name,
+            ctrl.clock.as_ref(),
+            &schedule,
+            policy.catch_up,
+            ctrl.wait_cancel_rx(),
+            |next| {
+                let next = next.and_then(|next| next.and_local_timezone(Local).earliest());
+                ctrl.tasks.set_next_wakeup(&name, next);
+            },
+            || {
+                let ctrl = Arc::clone(&ctrl);
+                let name = name.clone();
+                let policy = policy.clone();
+                let f = Arc::clone(&f);
+                async move { run_periodic_once(&ctrl, &name, &policy, f.as_ref()).await }
+            },
+        )
+        .await;
+        // drop ctrl
+    });
+}
 
-        loop {
-            // 現在時刻を取得して分までに切り捨てる
-            let now = Local::now().naive_local();
-            let now_hmd = now.date().and_hms_opt(now.hour(), now.minute(), 0).unwrap();
-            let next_min = now_hmd + CDuration::try_minutes(1).unwrap();
-            trace!("[{name}] periodic task check: {now_hmd}");
-
-            // 起動時刻リスト内で二分探索
-            match dt_list.binary_search(&now_hmd) {
-                Ok(_ind) => {
-                    // 一致するものを発見したので続行
-                    trace!("[{name}] hit in time list: {now_hmd}");
-                }
-                Err(ind) => {
-                    // ind = insertion point
-                    trace!("[{name}] not found in time list: {now_hmd}");
-                    // 起きるべき時刻は dt_list[ind]
-                    if ind < dt_list.len() {
-                        let target_dt = dt_list[ind] + CDuration::try_seconds(1).unwrap();
-                        let sleep_duration = target_dt - Local::now().naive_local();
-                        let sleep_sec = sleep_duration.num_seconds().clamp(0, i64::MAX) as u64;
-                        trace!("[{name}] target: {target_dt}, sleep_sec: {sleep_sec}");
-                        select! {
-                            _ = tokio::time::sleep(TDuration::from_secs(sleep_sec)) => {}
-                            _ = ctrl_move.wait_cancel_rx() => {
-                                info!("[{name}] cancel periodic task");
-                                return;
-                            }
-                        }
+/// 周期タスク `name` をスケジュールとは別に今すぐ1回実行する。
+///
+/// リトライ等の実行ポリシーは通常の実行と同じものが適用される。
+/// 実行の完了は待たない。
+pub fn trigger_periodic_task(ctrl: &Control, name: &str) -> Result<()> {
+    let trigger = ctrl.triggers.lock().unwrap().get(name).cloned();
+    let trigger = trigger.with_context(|| format!("periodic task not found: {name}"))?;
+    info!("[{name}] triggered");
+    trigger(ctrl);
 
-                        trace!("[{name}] wake up");
-                    } else {
-                        // 一番後ろよりも現在時刻が後
-                        // 起動時刻リストをすべて1日ずつ後ろにずらす
-                        for dt in dt_list.iter_mut() {
-                            let tomorrow = dt.date() + CDuration::try_days(1).unwrap();
-                            let time = dt.time();
-                            *dt = tomorrow.and_time(time);
-                            trace!("[{name}] advance time list by 1 day");
-                        }
-                    }
-                    continue;
-                }
-            }
+    Ok(())
+}
 
-            // ctrl を clone して future 内に move する
-            let future = f(ctrl_move.clone());
-            info!("[{name}] start (periodic)");
-            let result = future.await;
-            // drop clone of ctrl
-            if let Err(e) = result {
-                error!("[{name}] finish (error): {e:?}");
-            } else {
-                info!("[{name}] finish (success)");
-            }
+/// 周期タスクのスケジューリングループ。
+///
+/// `clock` の示すローカル時刻が `schedule` に一致するたびに `run` を実行する。
+/// `run` が false を返すか、`cancel` が完了した時点で終了する。
+///
+/// * `catch_up` - [TaskPolicy::catch_up]。
+/// * `on_next` - 次回起動予定時刻が決まるたびに呼ばれる。
+///   スケジュール上にもう起動時刻がない場合は None が渡される。
+async fn periodic_loop<C, N, R, Fut>(
+    name: &str,
+    clock: &dyn Clock,
+    schedule: &Schedule,
+    catch_up: bool,
+    cancel: C,
+    mut on_next: N,
+    mut run: R,
+) where
+    C: Future<Output = ()>,
+    N: FnMut(Option<NaiveDateTime>),
+    R: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    type CDuration = chrono::Duration;
 
-            // 次の "分" を狙って sleep する
-            // 目標は安全のため hh:mm:05 を狙う
-            let target_dt = next_min + CDuration::try_seconds(5).unwrap();
-            // タスクの実行に1分以上かかると負になるが、
-            // chrono::Duration は負数を許している
-            // その場合は 0 に補正する
-            let sleep_duration = target_dt - Local::now().naive_local();
-            let sleep_sec = sleep_duration.num_seconds().clamp(0, i64::MAX) as u64;
-            trace!("[{name}] target: {target_dt}, sleep_sec: {sleep_sec}");
-            select! {
-                _ = tokio::time::sleep(tokio::time::Duration::from_secs(sleep_sec)) => {}
-                _ = ctrl_move.wait_cancel_rx() => {
-                    info!("[{name}] cancel periodic task");
-                    return;
-                }
+    tokio::pin!(cancel);
+    // 前回チェックした時刻 (分単位)
+    let mut prev_hmd: Option<NaiveDateTime> = None;
+    loop {
+        // 現在時刻を取得して分までに切り捨てる
+        let now = clock.now();
+        let now_hmd = now.date().and_hms_opt(now.hour(), now.minute(), 0).unwrap();
+        let next_min = now_hmd + CDuration::try_minutes(1).unwrap();
+        trace!("[{name}] periodic task check: {now_hmd}");
+
+        // 前回チェック時から今回までの間にスケジュール上の時刻があれば
+        // 起動し損ねている
+        let missed = prev_hmd
+            .and_then(|prev| schedule.next_after(prev))
+            .filter(|&next| next < now_hmd);
+        prev_hmd = Some(now_hmd);
+
+        if schedule.matches(now_hmd) {
+            // 一致したので続行
+            trace!("[{name}] hit in schedule: {now_hmd}");
+        } else if let Some(missed) = missed
+            && catch_up
+        {
+            // 逃した分を1回だけ実行する
+            warn!("[{name}] missed wakeup at {missed}, catch up now");
+        } else {
+            if let Some(missed) = missed {
+                warn!("[{name}] missed wakeup at {missed}");
             }
+            trace!("[{name}] not match in schedule: {now_hmd}");
+            // 起きるべき時刻はスケジュール上の次の時刻
+            let Some(next) = schedule.next_after(now_hmd) else {
+                error!("[{name}] no more wakeup time in schedule: {schedule}");
+                on_next(None);
+                return;
+            };
+            on_next(Some(next));
+            let target_dt = next + CDuration::try_seconds(1).unwrap();
+            trace!("[{name}] target: {target_dt}");
+            if !sleep_until(clock, target_dt, cancel.as_mut()).await {
+                info!("[{name}] cancel periodic task");
+                return;
+            }
+
             trace!("[{name}] wake up");
+            continue;
         }
-        // drop ctrl
-    });
+
+        if !run().await {
+            info!("[{name}] cancel periodic task");
+            return;
+        }
+
+        // 次の "分" を狙って sleep する
+        // 目標は安全のため hh:mm:05 を狙う
+        // タスクの実行に1分以上かかっていた場合は既に過ぎているので即座に起きる
+        let target_dt = next_min + CDuration::try_seconds(5).unwrap();
+        trace!("[{name}] target: {target_dt}");
+        if !sleep_until(clock, target_dt, cancel.as_mut()).await {
+            info!("[{name}] cancel periodic task");
+            return;
+        }
+        trace!("[{name}] wake up");
+    }
 }
 
+/// `clock` が `target` 以降を示すまで sleep する。
+///
+/// 時計が進んだ場合 (DST 開始やサスペンド、時刻の変更等) に備えて、
+/// 最大 [SLEEP_MAX] ごとに起きて現在時刻を確認し直す。
+/// 時計が戻った場合 (DST 終了等) は再び `target` に達するまで待つため、
+/// 同じ時刻に2回起動することはない。
+///
+/// `cancel` が先に完了した場合は false を返す。
+async fn sleep_until<C>(clock: &dyn Clock, target: NaiveDateTime, mut cancel: Pin<&mut C>) -> bool
+where
+    C: Future<Output = ()>,
+{
+    loop {
+        // chrono::Duration は負数を許しているが、std::time::Duration への変換は失敗する
+        // 既に過ぎている場合は終了
+        let Ok(duration) = (target - clock.now()).to_std() else {
+            return true;
+        };
+        if duration.is_zero() {
+            return true;
+        }
+        select! {
+            _ = tokio::time::sleep(duration.min(SLEEP_MAX)) => {}
+            _ = cancel.as_mut() => {
+                return false;
+            }
+        }
+    }
+}
+
+/// [sleep_until] で一度に sleep する最大時間。
+const SLEEP_MAX: std::time::Duration = std::time::Duration::from_secs(60);
+
 impl TaskServer {
     /// タスクサーバを生成して初期化する。
     pub fn new(sysmods: SystemModules) -> Self {
+        Self::with_clock(sysmods, Arc::new(SystemClock))
+    }
+
+    /// 時計を指定してタスクサーバを生成して初期化する。
+    pub fn with_clock(sysmods: SystemModules, clock: Arc<dyn Clock>) -> Self {
         let rt = tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
@@ -264,15 +922,28 @@ impl TaskServer {
         // tx は self へ move
         // rx は root Control へ move
         let (cancel_tx, cancel_rx) = watch::channel(false);
+        let (exit_tx, exit_rx) = mpsc::unbounded_channel();
 
         let internal = Controller {
             rt,
             sysmods,
             cancel_rx: std::sync::Mutex::new(Some(cancel_rx)),
+            tasks: Default::default(),
+            handles: Default::default(),
+            owner_cancel: Default::default(),
+            clock,
+            events: Default::default(),
+            exit_tx,
+            triggers: Default::default(),
+            pending_ready: watch::Sender::new(BTreeSet::new()),
         };
         let ctrl = Arc::new(internal);
 
-        TaskServer { ctrl, cancel_tx }
+        TaskServer {
+            ctrl,
+            cancel_tx,
+            exit_rx,
+        }
     }
 
     /// [spawn_oneshot_task] を内蔵の [Self::ctrl] を使って呼び出す。
@@ -284,6 +955,20 @@ impl TaskServer {
         spawn_oneshot_task(&self.ctrl, name, f);
     }
 
+    /// システムモジュールを開始せずに `f` を実行し、完了するまでブロックする。
+    ///
+    /// [crate::sysmod::SystemModule::on_start] を呼ばないため、bot や周期タスクは動かない。
+    /// ワンショットのサブコマンド ([crate::oneshot]) 用。
+    pub fn run_oneshot<F, T>(self, f: F) -> T::Output
+    where
+        F: FnOnce(Control) -> T,
+        T: Future,
+    {
+        let ctrl = Arc::clone(&self.ctrl);
+        self.ctrl.rt.block_on(f(ctrl))
+        // drop self (self.ctrl)
+    }
+
     /// 実行を開始し、完了するまでブロックする。
     ///
     /// self の所有権を consume するため、一度しか実行できない。
@@ -298,6 +983,11 @@ impl TaskServer {
         self.ctrl.rt.block_on(async move {
             // SystemModule 全体に on_start イベントを配送
             ctrl.sysmods.on_start(&ctrl).await;
+            ctrl.publish(BootCompleted);
+            // 管理用ソケット
+            spawn_oneshot_task(&ctrl, "admin", admin::server_task);
+            // systemd への起動完了と watchdog の通知
+            spawn_oneshot_task(&ctrl, "sd-notify", sdnotify::notify_task);
 
             // この async block をシグナル処理に使う
             let mut sigint = signal(SignalKind::interrupt()).unwrap();
@@ -305,6 +995,7 @@ impl TaskServer {
             let mut sighup = signal(SignalKind::hangup()).unwrap();
             let mut sigusr1 = signal(SignalKind::user_defined1()).unwrap();
             let mut sigusr2 = signal(SignalKind::user_defined2()).unwrap();
+            let mut exit_rx = self.exit_rx;
 
             let run_result = loop {
                 tokio::select! {
@@ -318,7 +1009,10 @@ impl TaskServer {
                     },
                     _ = sighup.recv() => {
                         info!("[signal] SIGHUP");
-                        break RunResult::Reboot
+                        if let Err(e) = reload_config(&ctrl).await {
+                            error!("reload config failed, keep running with the current config");
+                            error!("{e:#}");
+                        }
                     },
                     _ = sigusr1.recv() => {
                         info!("[signal] SIGUSR1");
@@ -332,21 +1026,244 @@ impl TaskServer {
                             break result
                         }
                     }
+                    Some(result) = exit_rx.recv() => {
+                        info!("[admin] exit requested");
+                        break result
+                    }
                 }
             };
 
+            if matches!(run_result, RunResult::Shutdown) {
+                sdnotify::notify_or_warn(sdnotify::STOPPING);
+            }
+
             // 値を true に設定して全タスクにキャンセルリクエストを通知する
             self.cancel_tx.send_replace(true);
             // 全タスク完了待ち
             // オリジナルの cancel_rx を self.ctrl から奪って drop しておく
             drop(ctrl.cancel_rx.lock().unwrap().take());
-            // 全 cancel_rx が drop されるまで待つ
-            info!("waiting for all tasks to be completed....");
-            self.cancel_tx.closed().await;
-            info!("OK: all tasks are completed");
+            // 全 cancel_rx が drop され、全タスクが完了するまで待つ
+            // 期限を過ぎたら残りのタスクを強制中断する
+            let timeout_sec = config::get(|cfg| cfg.taskserver.shutdown_timeout_sec);
+            info!("waiting for all tasks to be completed.... (timeout: {timeout_sec} sec)");
+            let wait_all = async {
+                self.cancel_tx.closed().await;
+                ctrl.wait_all_tasks().await;
+            };
+            let timeout = std::time::Duration::from_secs(timeout_sec);
+            if tokio::time::timeout(timeout, wait_all).await.is_ok() {
+                info!("OK: all tasks are completed");
+            } else {
+                let hung = ctrl.abort_all_tasks();
+                error!(
+                    "shutdown timeout: {} task(s) did not finish in {timeout_sec} sec",
+                    hung.len()
+                );
+                for name in hung {
+                    error!("abort hung task: {name}");
+                }
+            }
+
+            // SystemModule 全体に on_stop イベントを配送
+            ctrl.sysmods.on_stop(&ctrl).await;
 
             run_result
         })
         // drop self (self.ctrl)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::clock::FakeClock;
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// [periodic_loop] を `count` 回起動するまで実行し、各起動時の時計の時刻を返す。
+    ///
+    /// * `start` - 時計の初期値。
+    /// * `work` - 1回の実行にかかる時間。
+    /// * `jump` - (開始からの経過時間, 時計のずれ)。DST の切り替え等を再現する。
+    async fn run_loop(
+        start: &str,
+        schedule: &str,
+        catch_up: bool,
+        count: usize,
+        work: Duration,
+        jump: Option<(Duration, chrono::Duration)>,
+    ) -> Vec<String> {
+        let start = NaiveDateTime::parse_from_str(start, "%F %T").unwrap();
+        let clock = Arc::new(FakeClock::new(start));
+        let schedule: Schedule = schedule.parse().unwrap();
+        if let Some((after, delta)) = jump {
+            let clock = Arc::clone(&clock);
+            tokio::spawn(async move {
+                tokio::time::sleep(after).await;
+                clock.jump(delta);
+            });
+        }
+
+        let runs = Mutex::new(vec![]);
+        periodic_loop(
+            "test",
+            clock.as_ref(),
+            &schedule,
+            catch_up,
+            std::future::pending(),
+            |_| {},
+            || {
+                let mut runs = runs.lock().unwrap();
+                runs.push(clock.now().format("%F %R").to_string());
+                let cont = runs.len() < count;
+                async move {
+                    tokio::time::sleep(work).await;
+                    cont
+                }
+            },
+        )
+        .await;
+
+        runs.into_inner().unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn periodic_day_rollover() {
+        let runs = run_loop(
+            "2024-12-31 23:58:30",
+            "0 0 * * *",
+            false,
+            2,
+            Duration::ZERO,
+            None,
+        )
+        .await;
+        assert_eq!(runs, ["2025-01-01 00:00", "2025-01-02 00:00"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn periodic_long_task() {
+        // 実行中に過ぎた時刻は重ねて実行せず、終了後の時刻に合わせる
+        let runs = run_loop(
+            "2024-01-01 10:00:30",
+            "* * * * *",
+            false,
+            3,
+            Duration::from_secs(150),
+            None,
+        )
+        .await;
+        assert_eq!(
+            runs,
+            ["2024-01-01 10:00", "2024-01-01 10:03", "2024-01-01 10:05"]
+        );
+
+        // 実行中に逃した時刻は catch_up の場合のみすぐに実行する
+        let runs = run_loop(
+            "2024-01-01 10:00:30",
+            "*/5 * * * *",
+            false,
+            2,
+            Duration::from_secs(7 * 60),
+            None,
+        )
+        .await;
+        assert_eq!(runs, ["2024-01-01 10:00", "2024-01-01 10:10"]);
+        let runs = run_loop(
+            "2024-01-01 10:00:30",
+            "*/5 * * * *",
+            true,
+            2,
+            Duration::from_secs(7 * 60),
+            None,
+        )
+        .await;
+        assert_eq!(runs, ["2024-01-01 10:00", "2024-01-01 10:07"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn periodic_dst_start() {
+        // 01:55 過ぎに時計が 1 時間進み、02:30 が存在しなくなる
+        let jump = Some((Duration::from_secs(5 * 60 + 10), chrono::Duration::hours(1)));
+        let runs = run_loop(
+            "2024-03-31 01:50:30",
+            "30 2 * * *",
+            false,
+            1,
+            Duration::ZERO,
+            jump,
+        )
+        .await;
+        assert_eq!(runs, ["2024-04-01 02:30"]);
+        let runs = run_loop(
+            "2024-03-31 01:50:30",
+            "30 2 * * *",
+            true,
+            1,
+            Duration::ZERO,
+            jump,
+        )
+        .await;
+        assert_eq!(runs, ["2024-03-31 02:56"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn periodic_dst_end() {
+        // 02:35 過ぎに時計が 1 時間戻り、01:35 からもう一度繰り返す
+        // 2 回目の 02:00 と 02:30 では起動しない
+        let jump = Some((
+            Duration::from_secs(45 * 60 + 10),
+            chrono::Duration::hours(-1),
+        ));
+        let runs = run_loop(
+            "2024-10-27 01:50:30",
+            "*/30 * * * *",
+            false,
+            4,
+            Duration::ZERO,
+            jump,
+        )
+        .await;
+        assert_eq!(
+            runs,
+            [
+                "2024-10-27 02:00",
+                "2024-10-27 02:30",
+                "2024-10-27 03:00",
+                "2024-10-27 03:30"
+            ]
+        );
+    }
+
+    #[test]
+    fn reload_restart_required() {
+        let is_sysmod = |section: &str| ["health", "discord"].contains(&section);
+        assert!(restart_required(&["health", "taskserver"], is_sysmod).is_empty());
+        assert_eq!(
+            restart_required(&["discord", "dry_run", "tasks"], is_sysmod),
+            ["dry_run", "tasks"]
+        );
+    }
+
+    #[test]
+    fn policy_backoff() {
+        let policy = TaskPolicy {
+            retry_backoff_sec: 10,
+            retry_backoff_max_sec: 60,
+            ..Default::default()
+        };
+        let list: Vec<_> = (1..=5).map(|i| policy.backoff(i).as_secs()).collect();
+        assert_eq!(list, [10, 20, 40, 60, 60]);
+        assert_eq!(policy.backoff(100).as_secs(), 60);
+    }
+
+    #[test]
+    fn config_default() {
+        // 一部のキーだけを書いた [taskserver] も読める
+        let config: TaskServerConfig = toml::from_str("").unwrap();
+        let def = TaskServerConfig::default();
+        assert_eq!(config.shutdown_timeout_sec, def.shutdown_timeout_sec);
+        assert_eq!(config.lock_wait_warn_sec, def.lock_wait_warn_sec);
+        assert_eq!(config.ready_timeout_sec, def.ready_timeout_sec);
+    }
+}
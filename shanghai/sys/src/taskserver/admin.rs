@@ -0,0 +1,374 @@
+//! ローカル管理用の制御ソケット。
+//!
+//! [socket_path] に Unix ドメインソケットを作成し、実行中のシステムを操作する。
+//! ソケットは所有者のみアクセスできるディレクトリ内に作成し、
+//! 他のローカルユーザからは作成直後も接続できないようにする。
+//! プロトコルは 1 接続につき 1 行の [Request] と 1 行の [Response] で、いずれも JSON。
+//!
+//! クライアントは `shanghai ctl <command>` ([parse_command], [request])。
+//!
+//! ```text
+//! $ shanghai ctl trigger health-check
+//! {"ok":true,"data":{"triggered":"health-check"}}
+//! ```
+
+use super::registry::{TaskEntry, TaskResult};
+use super::{Control, RunResult};
+use crate::config;
+use anyhow::{Context, Result, anyhow, bail};
+use log::{LevelFilter, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::fs::{DirBuilder, Permissions};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+/// ソケットを置くディレクトリ名。[utils::dir::cache_dir] に作成する。
+const SOCKET_DIR: &str = "admin";
+/// ソケットファイル名。[SOCKET_DIR] に作成する。
+const SOCKET_FILE: &str = "shanghai.sock";
+/// リクエスト1行の最大サイズ。
+const REQUEST_MAX: u64 = 64 * 1024;
+/// サーバ側の送受信タイムアウト。
+const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+/// クライアント側の送受信タイムアウト。
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `shanghai ctl` のコマンド一覧。ヘルプ表示用。
+pub const COMMAND_HELP: &str = "\
+Commands:
+    tasks               List tasks and their status
+    trigger <task>      Run a periodic task now
+    log-level [level]   Get or set the max log level (off, error, warn, info, debug, trace)
+    flush-log           Flush log buffers
+    config              Print the running config (secrets are redacted)
+    shutdown            Shut down the system
+    reboot              Reboot the system
+";
+
+/// 制御リクエスト。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum Request {
+    /// タスク一覧。
+    Tasks,
+    /// 周期タスクの即時実行。
+    Trigger { name: String },
+    /// ログレベルの取得。`level` があれば設定する。
+    LogLevel { level: Option<String> },
+    /// ログのフラッシュ。
+    FlushLog,
+    /// 秘密情報を伏せた現在の設定。
+    Config,
+    /// シャットダウン。
+    Shutdown,
+    /// リブート。
+    Reboot,
+}
+
+/// 制御レスポンス。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+    /// 成功したか。
+    pub ok: bool,
+    /// 結果データ。
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub data: Value,
+    /// エラーメッセージ。
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<Result<Value>> for Response {
+    fn from(result: Result<Value>) -> Self {
+        match result {
+            Ok(data) => Self {
+                ok: true,
+                data,
+                error: None,
+            },
+            Err(e) => Self {
+                ok: false,
+                data: Value::Null,
+                error: Some(format!("{e:#}")),
+            },
+        }
+    }
+}
+
+/// ソケットファイルのパス。
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(utils::dir::cache_dir()?.join(SOCKET_DIR).join(SOCKET_FILE))
+}
+
+/// ソケットを置くディレクトリを所有者のみアクセスできる状態 (0700) にする。
+///
+/// bind 時のソケットファイルのパーミッションは umask に依存するため、
+/// ディレクトリで他のユーザからのアクセスを防ぐ。
+/// 既に存在する場合もパーミッションを設定し直す。
+fn prepare_socket_dir(dir: &Path) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    std::fs::set_permissions(dir, Permissions::from_mode(0o700))
+        .with_context(|| format!("Failed to chmod {}", dir.display()))?;
+
+    Ok(())
+}
+
+/// `shanghai ctl` 以降のコマンドライン引数を [Request] に変換する。
+pub fn parse_command(args: &[String]) -> Result<Request> {
+    let (cmd, rest) = args.split_first().context("command is required")?;
+    let req = match (cmd.as_str(), rest) {
+        ("tasks", []) => Request::Tasks,
+        ("trigger", [name]) => Request::Trigger { name: name.clone() },
+        ("log-level", []) => Request::LogLevel { level: None },
+        ("log-level", [level]) => Request::LogLevel {
+            level: Some(level.clone()),
+        },
+        ("flush-log", []) => Request::FlushLog,
+        ("config", []) => Request::Config,
+        ("shutdown", []) => Request::Shutdown,
+        ("reboot", []) => Request::Reboot,
+        _ => bail!("invalid command: {}", args.join(" ")),
+    };
+
+    Ok(req)
+}
+
+/// 実行中のシステムにリクエストを送り、レスポンスを受け取る。
+///
+/// tokio ランタイム外から呼ぶためのブロッキング版。
+pub fn request(req: &Request) -> Result<Response> {
+    use std::io::{BufRead, Write};
+
+    let path = socket_path()?;
+    let mut stream = std::os::unix::net::UnixStream::connect(&path)
+        .with_context(|| format!("Cannot connect to {} (not running?)", path.display()))?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut line = serde_json::to_string(req)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut line = String::new();
+    std::io::BufReader::new(stream).read_line(&mut line)?;
+    let resp = serde_json::from_str(&line).context("Invalid response")?;
+
+    Ok(resp)
+}
+
+/// 制御ソケットのサーバタスク。
+///
+/// キャンセルされるまで1接続ずつ順に処理し、終了時にソケットファイルを削除する。
+pub(crate) async fn server_task(ctrl: Control) -> Result<()> {
+    let path = socket_path()?;
+    if let Some(dir) = path.parent() {
+        prepare_socket_dir(dir)?;
+    }
+    // 前回の異常終了で残ったソケットファイルを削除する
+    if let Err(e) = std::fs::remove_file(&path)
+        && e.kind() != std::io::ErrorKind::NotFound
+    {
+        return Err(e).with_context(|| format!("Failed to remove {}", path.display()));
+    }
+    let listener =
+        UnixListener::bind(&path).with_context(|| format!("Failed to bind {}", path.display()))?;
+    std::fs::set_permissions(&path, Permissions::from_mode(0o600))?;
+    info!("[admin] listening on {}", path.display());
+
+    loop {
+        tokio::select! {
+            accept = listener.accept() => {
+                match accept {
+                    Ok((stream, _)) => {
+                        if let Err(e) = serve(&ctrl, stream).await {
+                            warn!("[admin] {e:#}");
+                        }
+                    }
+                    Err(e) => warn!("[admin] accept failed: {e}"),
+                }
+            }
+            _ = ctrl.wait_cancel_rx() => {
+                info!("[admin] cancel");
+                break;
+            }
+        }
+    }
+
+    drop(listener);
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+/// 1接続分のリクエストを処理する。
+async fn serve(ctrl: &Control, stream: UnixStream) -> Result<()> {
+    let (rd, mut wr) = stream.into_split();
+    let mut rd = BufReader::new(rd.take(REQUEST_MAX));
+
+    let mut line = String::new();
+    tokio::time::timeout(SERVER_TIMEOUT, rd.read_line(&mut line))
+        .await
+        .context("read timeout")??;
+
+    let resp: Response = match serde_json::from_str::<Request>(&line) {
+        Ok(req) => {
+            info!("[admin] request: {req:?}");
+            execute(ctrl, req).into()
+        }
+        Err(e) => Err(anyhow!("invalid request: {e}")).into(),
+    };
+
+    let mut line = serde_json::to_string(&resp)?;
+    line.push('\n');
+    tokio::time::timeout(SERVER_TIMEOUT, wr.write_all(line.as_bytes()))
+        .await
+        .context("write timeout")??;
+
+    Ok(())
+}
+
+/// リクエストを実行し、結果データを返す。
+fn execute(ctrl: &Control, req: Request) -> Result<Value> {
+    let data = match req {
+        Request::Tasks => ctrl.task_list().iter().map(task_to_json).collect(),
+        Request::Trigger { name } => {
+            super::trigger_periodic_task(ctrl, &name)?;
+            json!({ "triggered": name })
+        }
+        Request::LogLevel { level } => {
+            // 各出力先のフィルタより詳細にはならない
+            if let Some(level) = level {
+                let level: LevelFilter = level
+                    .parse()
+                    .map_err(|_| anyhow!("invalid log level: {level}"))?;
+                info!("[admin] set max log level: {level}");
+                log::set_max_level(level);
+            }
+            json!({ "level": log::max_level().as_str().to_lowercase() })
+        }
+        Request::FlushLog => {
+            log::logger().flush();
+            Value::Null
+        }
+        Request::Config => serde_json::to_value(config::redacted()?)?,
+        Request::Shutdown => {
+            ctrl.request_exit(RunResult::Shutdown);
+            Value::Null
+        }
+        Request::Reboot => {
+            ctrl.request_exit(RunResult::Reboot);
+            Value::Null
+        }
+    };
+
+    Ok(data)
+}
+
+fn task_to_json(entry: &TaskEntry) -> Value {
+    let (result, error) = match &entry.last_result {
+        None => (None, None),
+        Some(TaskResult::Success) => (Some("success"), None),
+        Some(TaskResult::Error(msg)) => (Some("error"), Some(msg.as_str())),
+    };
+
+    json!({
+        "name": entry.name,
+        "kind": entry.kind.to_string(),
+        "running": entry.running,
+        "next_wakeup": entry.next_wakeup.map(|dt| dt.to_rfc3339()),
+        "last_start": entry.last_start.map(|dt| dt.to_rfc3339()),
+        "last_duration_sec": entry.last_duration.map(|d| d.as_secs_f64()),
+        "last_result": result,
+        "last_error": error,
+        "run_count": entry.run_count,
+        "failure_count": entry.failure_count,
+        "consecutive_failures": entry.consecutive_failures,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mode = |p: &Path| std::fs::metadata(p).unwrap().permissions().mode() & 0o777;
+
+        let dir = tmp.path().join("a").join(SOCKET_DIR);
+        prepare_socket_dir(&dir).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+
+        // 既存のディレクトリも制限する
+        let dir = tmp.path().join("b");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::set_permissions(&dir, Permissions::from_mode(0o755)).unwrap();
+        prepare_socket_dir(&dir).unwrap();
+        assert_eq!(mode(&dir), 0o700);
+    }
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_command(&args("tasks")).unwrap(), Request::Tasks);
+        assert_eq!(
+            parse_command(&args("trigger health-check")).unwrap(),
+            Request::Trigger {
+                name: "health-check".to_string()
+            }
+        );
+        assert_eq!(
+            parse_command(&args("log-level")).unwrap(),
+            Request::LogLevel { level: None }
+        );
+        assert_eq!(
+            parse_command(&args("log-level debug")).unwrap(),
+            Request::LogLevel {
+                level: Some("debug".to_string())
+            }
+        );
+        assert_eq!(parse_command(&args("reboot")).unwrap(), Request::Reboot);
+
+        assert!(parse_command(&args("")).is_err());
+        assert!(parse_command(&args("trigger")).is_err());
+        assert!(parse_command(&args("tasks all")).is_err());
+        assert!(parse_command(&args("unknown")).is_err());
+    }
+
+    #[test]
+    fn json_format() {
+        let req = Request::Trigger {
+            name: "camera-auto".to_string(),
+        };
+        let json = serde_json::to_string(&req).unwrap();
+        assert_eq!(json, r#"{"cmd":"trigger","name":"camera-auto"}"#);
+        assert_eq!(serde_json::from_str::<Request>(&json).unwrap(), req);
+
+        let req: Request = serde_json::from_str(r#"{"cmd":"flush-log"}"#).unwrap();
+        assert_eq!(req, Request::FlushLog);
+        let req: Request = serde_json::from_str(r#"{"cmd":"log-level"}"#).unwrap();
+        assert_eq!(req, Request::LogLevel { level: None });
+
+        let resp: Response = Ok(json!({ "level": "info" })).into();
+        assert_eq!(
+            serde_json::to_string(&resp).unwrap(),
+            r#"{"ok":true,"data":{"level":"info"}}"#
+        );
+        let resp: Response = Err(anyhow!("not found")).into();
+        assert_eq!(
+            serde_json::to_string(&resp).unwrap(),
+            r#"{"ok":false,"error":"not found"}"#
+        );
+    }
+}
@@ -0,0 +1,64 @@
+//! タスクサーバが使う時計。
+//!
+//! スケジューリングはローカルタイムで行うため、現在時刻の取得元を差し替え可能にして
+//! 日付の変わり目や DST (夏時間) の切り替えをテストできるようにする。
+
+use chrono::{Local, NaiveDateTime};
+use std::sync::Mutex;
+
+/// 現在のローカル日時の取得元。
+///
+/// sleep には常に [tokio::time] を使う。
+pub trait Clock: Send + Sync {
+    /// 現在のローカル日時を返す。
+    fn now(&self) -> NaiveDateTime;
+}
+
+/// 実際のシステム時計。
+#[derive(Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Local::now().naive_local()
+    }
+}
+
+/// テスト用の時計。
+///
+/// 生成時に指定した日時から、[tokio::time::Instant] の経過に連動して進む。
+/// tokio の paused time (`#[tokio::test(start_paused = true)]`) と組み合わせると、
+/// sleep は即座に完了し、この時計だけがその分進む。
+///
+/// [Self::jump] で壁時計のみをずらすことで、DST の切り替えや
+/// 時刻修正による時計の飛びを再現できる。
+pub struct FakeClock {
+    /// 生成時の日時。
+    base: NaiveDateTime,
+    /// 生成時の tokio 時刻。
+    start: tokio::time::Instant,
+    /// [Self::jump] による累積のずれ。
+    offset: Mutex<chrono::Duration>,
+}
+
+impl FakeClock {
+    pub fn new(base: NaiveDateTime) -> Self {
+        Self {
+            base,
+            start: tokio::time::Instant::now(),
+            offset: Mutex::new(chrono::Duration::zero()),
+        }
+    }
+
+    /// 壁時計を `delta` だけずらす。負数も可。
+    pub fn jump(&self, delta: chrono::Duration) {
+        *self.offset.lock().unwrap() += delta;
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> NaiveDateTime {
+        let elapsed = chrono::Duration::from_std(self.start.elapsed()).unwrap();
+        self.base + elapsed + *self.offset.lock().unwrap()
+    }
+}
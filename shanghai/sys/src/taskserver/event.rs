@@ -0,0 +1,160 @@
+//! システムモジュール間のイベント配送。
+//!
+//! 発行側は [super::Controller::publish] でイベントを発行し、
+//! 受信側は [super::spawn_event_task] で受信タスクを登録する。
+//! イベントは型ごとに別のチャネルで配送される。
+//!
+//! 発行側は受信側を知る必要がなく、他のシステムモジュールをロックすることもない。
+
+use crate::sysmod::health::HistoryEntry;
+use log::trace;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// 受信側の処理が追いつかない場合に溜めておけるイベントの数 (型ごと)。
+/// これを超えると古いものから捨てられる。
+const CHANNEL_CAPACITY: usize = 64;
+
+/// イベントとして配送可能な型。
+pub trait Event: Debug + Clone + Send + Sync + 'static {}
+
+/// 起動処理 (全 [crate::sysmod::SystemModule] の on_start) が完了した。
+#[derive(Debug, Clone)]
+pub struct BootCompleted;
+impl Event for BootCompleted {}
+
+/// Github リポジトリに push された。
+#[derive(Debug, Clone)]
+pub struct GitPushed {
+    /// push された ref 名。
+    pub refname: String,
+    /// 差分比較ページの URL。
+    pub compare: String,
+}
+impl Event for GitPushed {}
+
+impl GitPushed {
+    /// 通知用のメッセージを作成する。
+    pub fn message(&self) -> String {
+        format!("Pushed to Github: {}\n{}", self.refname, self.compare)
+    }
+}
+
+/// ヘルスチェックの測定値が記録された。
+#[derive(Debug, Clone)]
+pub struct HealthSampled(pub HistoryEntry);
+impl Event for HealthSampled {}
+
+/// 定期ヘルスレポートが作成された。
+#[derive(Debug, Clone)]
+pub struct HealthReported {
+    /// レポート本文。
+    pub text: String,
+    /// 添付するグラフ (PNG)。
+    pub chart: Option<Vec<u8>>,
+}
+impl Event for HealthReported {}
+
+/// 指定の通知先にテキストを通知する。
+///
+/// タスクの連続エラー、ヘルスチェックのアラート、死活監視やサービス監視の状態遷移等に使う。
+#[derive(Debug, Clone)]
+pub struct Notify {
+    /// 通知本文。
+    pub text: String,
+    /// 通知先の Discord チャネル ID。0 ならば通知しない。
+    pub discord_ch: u64,
+    /// 通知先の LINE ユーザまたはグループ ID。空ならば通知しない。
+    pub line_to: String,
+}
+impl Event for Notify {}
+
+/// 写真が撮影され、履歴に追加された。
+#[derive(Debug, Clone)]
+pub struct PictureTaken {
+    /// 履歴内のエントリ名。
+    pub name: String,
+}
+impl Event for PictureTaken {}
+
+/// 動体検知モードで動きを検出し、写真を履歴に追加した。
+///
+/// 同じ写真について [PictureTaken] も発行される。
+#[derive(Debug, Clone)]
+pub struct MotionDetected {
+    /// 履歴内のエントリ名。
+    pub name: String,
+    /// 通知本文。
+    pub text: String,
+    /// サムネイル jpeg のバイナリデータ。
+    pub thumb: Vec<u8>,
+    /// 通知先の Discord チャネル ID。0 ならば通知しない。
+    pub discord_ch: u64,
+}
+impl Event for MotionDetected {}
+
+/// イベントバス本体。
+///
+/// [super::Controller] が1つだけ持つ。
+#[derive(Default)]
+pub struct EventBus {
+    /// [TypeId] から `broadcast::Sender<E>` へのマップ。
+    channels: Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>,
+}
+
+impl EventBus {
+    /// `E` の送信側を取得する。存在しなければ作成する。
+    fn sender<E: Event>(&self) -> broadcast::Sender<E> {
+        let mut channels = self.channels.lock().unwrap();
+        channels
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(broadcast::channel::<E>(CHANNEL_CAPACITY).0))
+            .downcast_ref::<broadcast::Sender<E>>()
+            .unwrap()
+            .clone()
+    }
+
+    /// イベントを発行する。
+    ///
+    /// 受信側が存在しない場合は何もしない。
+    pub fn publish<E: Event>(&self, event: E) {
+        // ペイロード (画像など) は大きいことがあるので型名のみ
+        trace!("[event] publish: {}", std::any::type_name::<E>());
+        // 受信側が 0 の場合はエラーになるが問題ない
+        let _ = self.sender::<E>().send(event);
+    }
+
+    /// `E` の受信を開始する。
+    ///
+    /// これ以降に発行されたイベントを受信できる。
+    pub fn subscribe<E: Event>(&self) -> broadcast::Receiver<E> {
+        self.sender::<E>().subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publish_subscribe() {
+        let bus = EventBus::default();
+        // 受信側がいなくても問題ない
+        bus.publish(BootCompleted);
+
+        let mut rx1 = bus.subscribe::<PictureTaken>();
+        let mut rx2 = bus.subscribe::<PictureTaken>();
+        let mut rx_other = bus.subscribe::<BootCompleted>();
+        bus.publish(PictureTaken {
+            name: "a".to_string(),
+        });
+
+        assert_eq!(rx1.try_recv().unwrap().name, "a");
+        assert_eq!(rx2.try_recv().unwrap().name, "a");
+        assert!(rx1.try_recv().is_err());
+        assert!(rx_other.try_recv().is_err());
+    }
+}
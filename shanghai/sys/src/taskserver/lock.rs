@@ -0,0 +1,277 @@
+//! ロック順序の強制とロック待ちの診断。
+//!
+//! [crate::sysmod::SystemModules] 内の各モジュールは [RankedMutex] で保護される。
+//! [RankedMutex] はそれぞれ順位を持ち、1つのタスク内では順位の小さいものから
+//! 順にしかロックできない。
+//! 順番に違反した場合、デバッグビルドでは panic し、リリースビルドではエラーログを出す。
+//!
+//! また、ロック待ちが [super::TaskServerConfig::lock_wait_warn_sec] を超えるたびに
+//! 現在の保持者のタスク名とともに警告ログを出す。
+//!
+//! 保持中のロックは tokio のタスク ID ごとに記録される。
+//! タスク外 (`block_on` 直下等) でのロックは記録も検査もされない。
+
+use crate::config;
+use log::{error, warn};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as TokioMutex, MutexGuard as TokioMutexGuard};
+use tokio::task::Id;
+
+/// タスクごとの状態。
+#[derive(Default)]
+struct TaskState {
+    /// [register_task_name] で登録された名前。
+    name: Option<String>,
+    /// 保持中のロックの (順位, 名前) のリスト。ロックした順。
+    held: Vec<(u32, &'static str)>,
+}
+
+/// タスク ID から [TaskState] へのマップ。
+///
+/// 名前も保持中のロックもなくなったエントリは削除する。
+static TASKS: LazyLock<Mutex<HashMap<Id, TaskState>>> = LazyLock::new(Default::default);
+
+/// `id` のエントリを操作する。不要になったエントリは削除する。
+fn update_task<F, R>(id: Id, f: F) -> R
+where
+    F: FnOnce(&mut TaskState) -> R,
+{
+    let mut tasks = TASKS.lock().unwrap();
+    let state = tasks.entry(id).or_default();
+    let result = f(state);
+    if state.name.is_none() && state.held.is_empty() {
+        tasks.remove(&id);
+    }
+    result
+}
+
+/// 表示用のタスク名を返す。
+fn task_name(id: Option<Id>) -> String {
+    let Some(id) = id else {
+        return "(no task)".to_string();
+    };
+    let tasks = TASKS.lock().unwrap();
+    match tasks.get(&id).and_then(|state| state.name.as_ref()) {
+        Some(name) => name.clone(),
+        None => format!("(task {id})"),
+    }
+}
+
+/// [register_task_name] の戻り値。drop 時に登録を解除する。
+pub(crate) struct TaskNameGuard(Option<Id>);
+
+impl Drop for TaskNameGuard {
+    fn drop(&mut self) {
+        if let Some(id) = self.0 {
+            update_task(id, |state| state.name = None);
+        }
+    }
+}
+
+/// 現在のタスクに名前を付ける。ロック待ちの診断ログに使われる。
+///
+/// 戻り値はタスクの終了まで保持すること。
+pub(crate) fn register_task_name(name: &str) -> TaskNameGuard {
+    let id = tokio::task::try_id();
+    if let Some(id) = id {
+        update_task(id, |state| state.name = Some(name.to_string()));
+    }
+    TaskNameGuard(id)
+}
+
+/// 順位付きの非同期 Mutex。
+pub struct RankedMutex<T: ?Sized> {
+    /// 診断用の名前。
+    name: &'static str,
+    /// 順位。小さいものから順にロックしなければならない。
+    rank: u32,
+    /// 現在の保持者のタスク ID。
+    holder: Mutex<Option<Id>>,
+    /// 本体。
+    /// unsized coercion のため最後に置く。
+    mutex: TokioMutex<T>,
+}
+
+impl<T> RankedMutex<T> {
+    pub fn new(name: &'static str, rank: u32, value: T) -> Self {
+        Self {
+            name,
+            rank,
+            holder: Mutex::new(None),
+            mutex: TokioMutex::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> RankedMutex<T> {
+    /// 名前を返す。
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// ロックを取得する。
+    ///
+    /// 現在のタスクがこれ以上の順位のロックを保持している場合、
+    /// デバッグビルドでは panic する。
+    pub async fn lock(&self) -> RankedMutexGuard<'_, T> {
+        let id = tokio::task::try_id();
+        if let Some(id) = id {
+            self.check_order(id);
+        }
+
+        let guard = match self.mutex.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => self.lock_slow(id).await,
+        };
+
+        *self.holder.lock().unwrap() = id;
+        if let Some(id) = id {
+            update_task(id, |state| state.held.push((self.rank, self.name)));
+        }
+
+        RankedMutexGuard {
+            owner: self,
+            task: id,
+            guard,
+        }
+    }
+
+    /// ロック順序を検査する。
+    fn check_order(&self, id: Id) {
+        // panic する前に TASKS のロックを解放しておく
+        let violation = update_task(id, |state| {
+            state
+                .held
+                .iter()
+                .find(|(rank, _)| *rank >= self.rank)
+                .map(|(_, name)| *name)
+        });
+        if let Some(held) = violation {
+            let msg = format!(
+                "[lock] lock order violation in {}: lock \"{}\" while holding \"{held}\"",
+                task_name(Some(id)),
+                self.name,
+            );
+            if cfg!(debug_assertions) {
+                panic!("{msg}");
+            } else {
+                error!("{msg}");
+            }
+        }
+    }
+
+    /// ロック待ち。一定時間ごとに警告ログを出す。
+    async fn lock_slow(&self, id: Option<Id>) -> TokioMutexGuard<'_, T> {
+        let warn_sec = config::get(|cfg| cfg.taskserver.lock_wait_warn_sec);
+        if warn_sec == 0 {
+            return self.mutex.lock().await;
+        }
+
+        let start = tokio::time::Instant::now();
+        let lock = self.mutex.lock();
+        tokio::pin!(lock);
+        loop {
+            tokio::select! {
+                guard = &mut lock => {
+                    return guard;
+                }
+                _ = tokio::time::sleep(Duration::from_secs(warn_sec)) => {
+                    let holder = *self.holder.lock().unwrap();
+                    warn!(
+                        "[lock] {} has been waiting for \"{}\" for {} sec (holder: {})",
+                        task_name(id),
+                        self.name,
+                        start.elapsed().as_secs(),
+                        task_name(holder),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// [RankedMutex::lock] の戻り値。drop 時にロックを解放する。
+pub struct RankedMutexGuard<'a, T: ?Sized> {
+    owner: &'a RankedMutex<T>,
+    /// ロックしたタスクの ID。
+    task: Option<Id>,
+    guard: TokioMutexGuard<'a, T>,
+}
+
+impl<T: ?Sized> Deref for RankedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: ?Sized> DerefMut for RankedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: ?Sized> Drop for RankedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        *self.owner.holder.lock().unwrap() = None;
+        if let Some(id) = self.task {
+            let rank = self.owner.rank;
+            update_task(id, |state| {
+                if let Some(pos) = state.held.iter().rposition(|(r, _)| *r == rank) {
+                    state.held.remove(pos);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_order() {
+        let a = std::sync::Arc::new(RankedMutex::new("a", 0, 1));
+        let b = std::sync::Arc::new(RankedMutex::new("b", 1, 2));
+
+        let (a2, b2) = (a.clone(), b.clone());
+        tokio::spawn(async move {
+            let _name = register_task_name("test");
+            let ga = a2.lock().await;
+            let gb = b2.lock().await;
+            assert_eq!(*ga + *gb, 3);
+            drop(gb);
+            drop(ga);
+            // 解放後は逆順でもよい
+            let _gb = b2.lock().await;
+        })
+        .await
+        .unwrap();
+
+        // 別タスクからはロック可能
+        assert_eq!(*a.lock().await, 1);
+    }
+
+    #[cfg(debug_assertions)]
+    #[tokio::test]
+    async fn out_of_order() {
+        let a = std::sync::Arc::new(RankedMutex::new("a", 0, ()));
+        let b = std::sync::Arc::new(RankedMutex::new("b", 1, ()));
+
+        let (a2, b2) = (a.clone(), b.clone());
+        let result = tokio::spawn(async move {
+            let _gb = b2.lock().await;
+            let _ga = a2.lock().await;
+        })
+        .await;
+        assert!(result.unwrap_err().is_panic());
+
+        // panic 時に解放されている
+        let _ga = a.lock().await;
+        let _gb = b.lock().await;
+    }
+}
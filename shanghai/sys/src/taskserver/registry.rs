@@ -0,0 +1,195 @@
+//! 実行されたタスクの記録。
+//!
+//! [super::spawn_periodic_task] 等で生成されたタスクを名前ごとに記録し、
+//! 実行状況を外部から参照できるようにする。
+
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// タスクの種別。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskKind {
+    Periodic,
+    Oneshot,
+    /// [super::spawn_event_task] によるイベント受信タスク。
+    Event,
+}
+
+impl Display for TaskKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            TaskKind::Periodic => "periodic",
+            TaskKind::Oneshot => "oneshot",
+            TaskKind::Event => "event",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// タスクの最後の実行結果。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskResult {
+    Success,
+    /// エラーメッセージ。
+    Error(String),
+}
+
+/// タスク1つ分の記録。
+#[derive(Debug, Clone)]
+pub struct TaskEntry {
+    /// タスク名。
+    pub name: String,
+    /// 種別。
+    pub kind: TaskKind,
+    /// 現在実行中のインスタンス数。
+    pub running: u32,
+    /// 次回起動予定時刻。周期タスクのみ。
+    pub next_wakeup: Option<DateTime<Local>>,
+    /// 最後に実行を開始した時刻。
+    pub last_start: Option<DateTime<Local>>,
+    /// 最後に完了した実行の所要時間。
+    pub last_duration: Option<Duration>,
+    /// 最後に完了した実行の結果。
+    pub last_result: Option<TaskResult>,
+    /// 完了した実行回数。
+    pub run_count: u64,
+    /// そのうちエラーで終了した回数。
+    pub failure_count: u64,
+    /// 直近で連続してエラー終了した回数。
+    pub consecutive_failures: u32,
+}
+
+impl TaskEntry {
+    fn new(name: &str, kind: TaskKind) -> Self {
+        Self {
+            name: name.to_string(),
+            kind,
+            running: 0,
+            next_wakeup: None,
+            last_start: None,
+            last_duration: None,
+            last_result: None,
+            run_count: 0,
+            failure_count: 0,
+            consecutive_failures: 0,
+        }
+    }
+}
+
+/// 全タスクの記録。
+///
+/// [super::Controller] が1つだけ持つ。
+/// 同名のタスクは同じエントリに集計される。
+#[derive(Default)]
+pub struct TaskRegistry {
+    entries: Mutex<BTreeMap<String, TaskEntry>>,
+}
+
+impl TaskRegistry {
+    /// エントリを取得し、存在しなければ作成して `f` を呼ぶ。
+    fn update<F, R>(&self, name: &str, kind: TaskKind, f: F) -> R
+    where
+        F: FnOnce(&mut TaskEntry) -> R,
+    {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .entry(name.to_string())
+            .or_insert_with(|| TaskEntry::new(name, kind));
+        f(entry)
+    }
+
+    /// タスクを登録する。既に存在する場合は何もしない。
+    pub(crate) fn register(&self, name: &str, kind: TaskKind) {
+        self.update(name, kind, |_| ());
+    }
+
+    /// 次回起動予定時刻を設定する。
+    pub(crate) fn set_next_wakeup(&self, name: &str, next: Option<DateTime<Local>>) {
+        self.update(name, TaskKind::Periodic, |entry| entry.next_wakeup = next);
+    }
+
+    /// 実行開始を記録する。
+    ///
+    /// 戻り値は [Self::finish] に渡すこと。
+    pub(crate) fn start(&self, name: &str, kind: TaskKind) -> Instant {
+        self.update(name, kind, |entry| {
+            entry.running += 1;
+            entry.last_start = Some(Local::now());
+        });
+        Instant::now()
+    }
+
+    /// 実行完了を記録する。
+    ///
+    /// * `started` - [Self::start] の戻り値。
+    ///
+    /// 連続エラー回数を返す。
+    pub(crate) fn finish(
+        &self,
+        name: &str,
+        kind: TaskKind,
+        started: Instant,
+        result: &Result<()>,
+    ) -> u32 {
+        let duration = started.elapsed();
+        self.update(name, kind, |entry| {
+            entry.running = entry.running.saturating_sub(1);
+            entry.last_duration = Some(duration);
+            entry.run_count += 1;
+            entry.last_result = Some(match result {
+                Ok(()) => {
+                    entry.consecutive_failures = 0;
+                    TaskResult::Success
+                }
+                Err(e) => {
+                    entry.failure_count += 1;
+                    entry.consecutive_failures += 1;
+                    TaskResult::Error(format!("{e:#}"))
+                }
+            });
+            entry.consecutive_failures
+        })
+    }
+
+    /// 全エントリのスナップショットを名前順で返す。
+    pub fn list(&self) -> Vec<TaskEntry> {
+        self.entries.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn record() {
+        let reg = TaskRegistry::default();
+        reg.register("b", TaskKind::Periodic);
+        let started = reg.start("a", TaskKind::Oneshot);
+        assert_eq!(reg.list()[0].running, 1);
+        reg.finish("a", TaskKind::Oneshot, started, &Ok(()));
+        let started = reg.start("a", TaskKind::Oneshot);
+        let count = reg.finish("a", TaskKind::Oneshot, started, &Err(anyhow!("error")));
+        assert_eq!(count, 1);
+
+        let list = reg.list();
+        assert_eq!(list.len(), 2);
+        let a = &list[0];
+        assert_eq!(a.name, "a");
+        assert_eq!(a.kind, TaskKind::Oneshot);
+        assert_eq!(a.running, 0);
+        assert_eq!(a.run_count, 2);
+        assert_eq!(a.failure_count, 1);
+        assert_eq!(a.consecutive_failures, 1);
+        assert_eq!(a.last_result, Some(TaskResult::Error("error".to_string())));
+        let b = &list[1];
+        assert_eq!(b.name, "b");
+        assert_eq!(b.run_count, 0);
+        assert!(b.last_start.is_none());
+    }
+}
@@ -0,0 +1,424 @@
+//! 周期タスクの起動スケジュール。
+//!
+//! cron 形式 (分 時 日 月 曜日) と、いくつかの省略形をサポートする。
+//! 精度は分単位で、時刻はローカルタイムとして扱う。
+//!
+//! * `"*/5 * * * *"` - 5 分ごと
+//! * `"0 */3 * * *"` - 3 時間ごと
+//! * `"0 9 * * mon-fri"` - 平日の 9:00
+//! * `"30 0 1,15 * *"` - 毎月 1 日と 15 日の 0:30
+//! * `"@hourly"`, `"@daily"`, `"@weekly"`, `"@monthly"`
+//! * `"@every 10m"`, `"@every 6h"` - 0:00 を起点とした一定間隔
+//!
+//! 日と曜日の両方が `*` 以外の場合、cron と同様にどちらか一方に
+//! 一致すれば起動する。
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use std::{fmt::Display, str::FromStr};
+
+/// [Schedule::next_after] の探索日数上限。
+///
+/// 2/29 指定が最大 8 年間隔になるため、それを含む。
+const SEARCH_DAYS: u32 = 366 * 8 + 1;
+
+/// 曜日名。0 = 日曜。
+const WEEKDAY_NAMES: &[&str] = &["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// 月名。1 = 1月。
+const MONTH_NAMES: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// 周期タスクの起動スケジュール。
+///
+/// 文字列から [FromStr] で生成する。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    /// 元の文字列表現。ログ出力用。
+    src: String,
+    /// 分 (bit 0-59)。
+    minutes: u64,
+    /// 時 (bit 0-23)。
+    hours: u64,
+    /// 日 (bit 1-31)。
+    days: u64,
+    /// 月 (bit 1-12)。
+    months: u64,
+    /// 曜日 (bit 0-6, 0 = 日曜)。
+    weekdays: u64,
+    /// 日フィールドが `*` で始まる。
+    days_any: bool,
+    /// 曜日フィールドが `*` で始まる。
+    weekdays_any: bool,
+}
+
+impl Schedule {
+    /// 5 フィールドの cron 形式からなる文字列から生成する。
+    fn from_fields(src: &str, fields: &str) -> Result<Self> {
+        let list: Vec<_> = fields.split_ascii_whitespace().collect();
+        ensure!(
+            list.len() == 5,
+            "cron expression must have 5 fields: \"{fields}\""
+        );
+
+        let minutes = parse_field(list[0], 0, 59, &[], 0).context("minute field")?;
+        let hours = parse_field(list[1], 0, 23, &[], 0).context("hour field")?;
+        let days = parse_field(list[2], 1, 31, &[], 0).context("day field")?;
+        let months = parse_field(list[3], 1, 12, MONTH_NAMES, 1).context("month field")?;
+        // 7 も日曜日として受け付ける
+        let mut weekdays = parse_field(list[4], 0, 7, WEEKDAY_NAMES, 0).context("weekday field")?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+
+        let schedule = Self {
+            src: src.to_string(),
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            days_any: list[2].starts_with('*'),
+            weekdays_any: list[4].starts_with('*'),
+        };
+
+        // 永久に起動しないもの (2/30 等) はエラーとする
+        let origin = NaiveDate::from_ymd_opt(2000, 1, 1)
+            .unwrap()
+            .and_time(NaiveTime::MIN);
+        ensure!(
+            schedule.next_after(origin).is_some(),
+            "schedule never fires: \"{src}\""
+        );
+
+        Ok(schedule)
+    }
+
+    /// `@every` 形式の間隔指定を cron 形式に変換する。
+    ///
+    /// 0:00 を起点とするため、60 分または 24 時間を割り切れる値のみ受け付ける。
+    fn interval_to_fields(interval: &str) -> Result<String> {
+        let interval = interval.trim();
+        let (num, unit) = if let Some(num) = interval.strip_suffix('m') {
+            (num, 1)
+        } else if let Some(num) = interval.strip_suffix('h') {
+            (num, 60)
+        } else {
+            bail!("interval unit must be m or h: \"{interval}\"");
+        };
+        let num: u32 = num
+            .parse()
+            .with_context(|| format!("invalid interval: \"{interval}\""))?;
+        let min = num.saturating_mul(unit);
+        ensure!(min > 0, "interval must not be 0: \"{interval}\"");
+
+        if min <= 60 && 60 % min == 0 {
+            Ok(format!("*/{min} * * * *"))
+        } else if min % 60 == 0 && min / 60 <= 24 && 24 % (min / 60) == 0 {
+            Ok(format!("0 */{} * * *", min / 60))
+        } else {
+            bail!("interval must divide 60 minutes or 24 hours: \"{interval}\"");
+        }
+    }
+
+    /// `dt` (秒以下は無視) がスケジュールに一致するならば true を返す。
+    pub fn matches(&self, dt: NaiveDateTime) -> bool {
+        self.date_matches(dt.date()) && bit(self.hours, dt.hour()) && bit(self.minutes, dt.minute())
+    }
+
+    /// `dt` より後で、スケジュールに一致する最初の日時を返す。
+    ///
+    /// 秒以下は 0 になる。
+    /// 見つからない場合は [None] を返す。
+    pub fn next_after(&self, dt: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = dt.with_second(0)?.with_nanosecond(0)? + chrono::Duration::try_minutes(1)?;
+
+        let mut date = start.date();
+        let (mut hour, mut minute) = (start.hour(), start.minute());
+        for _ in 0..SEARCH_DAYS {
+            if self.date_matches(date)
+                && let Some(time) = self.first_time_from(hour, minute)
+            {
+                return Some(date.and_time(time));
+            }
+            date = date.succ_opt()?;
+            (hour, minute) = (0, 0);
+        }
+
+        None
+    }
+
+    /// `dt` より後の起動日時を順に返すイテレータを返す。
+    pub fn iter_after(&self, dt: NaiveDateTime) -> impl Iterator<Item = NaiveDateTime> + '_ {
+        std::iter::successors(self.next_after(dt), |&prev| self.next_after(prev))
+    }
+
+    fn date_matches(&self, date: NaiveDate) -> bool {
+        if !bit(self.months, date.month()) {
+            return false;
+        }
+        let day = bit(self.days, date.day());
+        let weekday = bit(self.weekdays, date.weekday().num_days_from_sunday());
+        // 両方が指定されている場合はどちらかに一致すればよい (cron 互換)
+        if !self.days_any && !self.weekdays_any {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    /// 同日内で hour:minute 以降の最初の一致時刻を返す。
+    fn first_time_from(&self, hour: u32, minute: u32) -> Option<NaiveTime> {
+        for h in hour..24 {
+            if !bit(self.hours, h) {
+                continue;
+            }
+            let m_start = if h == hour { minute } else { 0 };
+            for m in m_start..60 {
+                if bit(self.minutes, m) {
+                    return NaiveTime::from_hms_opt(h, m, 0);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let src = s.trim();
+        let fields = match src {
+            "@hourly" => "0 * * * *".to_string(),
+            "@daily" | "@midnight" => "0 0 * * *".to_string(),
+            "@weekly" => "0 0 * * 0".to_string(),
+            "@monthly" => "0 0 1 * *".to_string(),
+            "@yearly" | "@annually" => "0 0 1 1 *".to_string(),
+            _ => {
+                if let Some(interval) = src.strip_prefix("@every ") {
+                    Self::interval_to_fields(interval)?
+                } else if src.starts_with('@') {
+                    bail!("unknown schedule: \"{src}\"");
+                } else {
+                    src.to_string()
+                }
+            }
+        };
+
+        Self::from_fields(src, &fields)
+    }
+}
+
+impl Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.src)
+    }
+}
+
+fn bit(bits: u64, n: u32) -> bool {
+    bits & (1 << n) != 0
+}
+
+/// cron の1フィールドをパースしてビットマスクを返す。
+///
+/// `*`, `n`, `a-b`, `*/s`, `a-b/s`, `a/s` を `,` 区切りで並べたもの。
+///
+/// * `names` - 数値の代わりに使える名前のリスト。
+/// * `name_base` - `names[0]` に対応する数値。
+fn parse_field(src: &str, min: u32, max: u32, names: &[&str], name_base: u32) -> Result<u64> {
+    let value = |s: &str| -> Result<u32> {
+        let n = if let Ok(n) = s.parse::<u32>() {
+            n
+        } else {
+            let lower = s.to_ascii_lowercase();
+            let idx = names
+                .iter()
+                .position(|&name| name == lower)
+                .ok_or_else(|| anyhow!("invalid value: \"{s}\""))?;
+            idx as u32 + name_base
+        };
+        ensure!(
+            (min..=max).contains(&n),
+            "out of range ({min}-{max}): \"{s}\""
+        );
+        Ok(n)
+    };
+
+    let mut bits = 0u64;
+    for elem in src.split(',') {
+        let (range, step) = match elem.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .with_context(|| format!("invalid step: \"{elem}\""))?;
+                ensure!(step > 0, "step must not be 0: \"{elem}\"");
+                (range, Some(step))
+            }
+            None => (elem, None),
+        };
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some((a, b)) = range.split_once('-') {
+            (value(a)?, value(b)?)
+        } else {
+            let n = value(range)?;
+            // "a/s" は a から最大値まで
+            if step.is_some() { (n, max) } else { (n, n) }
+        };
+        ensure!(first <= last, "invalid range: \"{elem}\"");
+
+        for n in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << n;
+        }
+    }
+
+    Ok(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, mo, d)
+            .unwrap()
+            .and_hms_opt(h, mi, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn every_minute() {
+        let s: Schedule = "* * * * *".parse().unwrap();
+        assert!(s.matches(dt(2024, 1, 1, 0, 0)));
+        assert!(s.matches(dt(2024, 12, 31, 23, 59)));
+        assert_eq!(
+            s.next_after(dt(2024, 12, 31, 23, 59)),
+            Some(dt(2025, 1, 1, 0, 0))
+        );
+    }
+
+    #[test]
+    fn step_hours() {
+        let s: Schedule = "0 */3 * * *".parse().unwrap();
+        assert!(s.matches(dt(2024, 5, 5, 21, 0)));
+        assert!(!s.matches(dt(2024, 5, 5, 22, 0)));
+        assert!(!s.matches(dt(2024, 5, 5, 21, 1)));
+        assert_eq!(
+            s.next_after(dt(2024, 5, 5, 21, 0)),
+            Some(dt(2024, 5, 6, 0, 0))
+        );
+        let list: Vec<_> = s.iter_after(dt(2024, 5, 5, 23, 59)).take(3).collect();
+        assert_eq!(
+            list,
+            vec![
+                dt(2024, 5, 6, 0, 0),
+                dt(2024, 5, 6, 3, 0),
+                dt(2024, 5, 6, 6, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn list_and_range() {
+        let s: Schedule = "0,30 9-10 * * *".parse().unwrap();
+        let list: Vec<_> = s.iter_after(dt(2024, 1, 1, 0, 0)).take(5).collect();
+        assert_eq!(
+            list,
+            vec![
+                dt(2024, 1, 1, 9, 0),
+                dt(2024, 1, 1, 9, 30),
+                dt(2024, 1, 1, 10, 0),
+                dt(2024, 1, 1, 10, 30),
+                dt(2024, 1, 2, 9, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn weekday() {
+        // 2024-01-06 は土曜日
+        let s: Schedule = "0 9 * * mon-fri".parse().unwrap();
+        assert_eq!(
+            s.next_after(dt(2024, 1, 5, 9, 0)),
+            Some(dt(2024, 1, 8, 9, 0))
+        );
+        // 7 は日曜日
+        let s: Schedule = "0 0 * * 7".parse().unwrap();
+        assert_eq!(
+            s.next_after(dt(2024, 1, 1, 0, 0)),
+            Some(dt(2024, 1, 7, 0, 0))
+        );
+    }
+
+    #[test]
+    fn day_or_weekday() {
+        // 15 日または日曜日
+        let s: Schedule = "0 0 15 * sun".parse().unwrap();
+        let list: Vec<_> = s.iter_after(dt(2024, 1, 1, 0, 0)).take(3).collect();
+        assert_eq!(
+            list,
+            vec![
+                dt(2024, 1, 7, 0, 0),
+                dt(2024, 1, 14, 0, 0),
+                dt(2024, 1, 15, 0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn day_of_month() {
+        let s: Schedule = "0 0 31 * *".parse().unwrap();
+        assert_eq!(
+            s.next_after(dt(2024, 1, 31, 0, 0)),
+            Some(dt(2024, 3, 31, 0, 0))
+        );
+        let s: Schedule = "0 0 29 feb *".parse().unwrap();
+        assert_eq!(
+            s.next_after(dt(2024, 3, 1, 0, 0)),
+            Some(dt(2028, 2, 29, 0, 0))
+        );
+    }
+
+    #[test]
+    fn macros() {
+        let s: Schedule = "@daily".parse().unwrap();
+        assert_eq!(s.to_string(), "@daily");
+        assert_eq!(
+            s.next_after(dt(2024, 1, 1, 0, 0)),
+            Some(dt(2024, 1, 2, 0, 0))
+        );
+
+        let s: Schedule = "@every 10m".parse().unwrap();
+        assert!(s.matches(dt(2024, 1, 1, 12, 50)));
+        assert!(!s.matches(dt(2024, 1, 1, 12, 55)));
+
+        let s: Schedule = "@every 6h".parse().unwrap();
+        let list: Vec<_> = s.iter_after(dt(2024, 1, 1, 0, 0)).take(4).collect();
+        assert_eq!(
+            list,
+            vec![
+                dt(2024, 1, 1, 6, 0),
+                dt(2024, 1, 1, 12, 0),
+                dt(2024, 1, 1, 18, 0),
+                dt(2024, 1, 2, 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("".parse::<Schedule>().is_err());
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("60 * * * *".parse::<Schedule>().is_err());
+        assert!("* 24 * * *".parse::<Schedule>().is_err());
+        assert!("*/0 * * * *".parse::<Schedule>().is_err());
+        assert!("5-1 * * * *".parse::<Schedule>().is_err());
+        assert!("0 0 * * xyz".parse::<Schedule>().is_err());
+        assert!("0 0 30 2 *".parse::<Schedule>().is_err());
+        assert!("@every 7m".parse::<Schedule>().is_err());
+        assert!("@every 5s".parse::<Schedule>().is_err());
+        assert!("@every 0h".parse::<Schedule>().is_err());
+        assert!("@unknown".parse::<Schedule>().is_err());
+    }
+}
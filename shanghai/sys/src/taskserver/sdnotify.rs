@@ -0,0 +1,169 @@
+//! systemd への状態通知 (sd_notify)。
+//!
+//! libsystemd は使わず、`$NOTIFY_SOCKET` の Unix データグラムソケットに
+//! 状態文字列を直接送る。
+//! systemd 管理下でない (`$NOTIFY_SOCKET` がない) 場合は何もしない。
+//!
+//! * `READY=1` - [super::TaskServer::run] で全システムモジュールの on_start が完了し、
+//!   [super::Controller::expect_ready] で登録された起動完了の報告が揃った後。
+//! * `WATCHDOG=1` - `$WATCHDOG_USEC` の半分の間隔で、非同期タスクから送る。
+//!   ランタイムが応答しなくなると途絶え、systemd によりプロセスが再起動される。
+//! * `STOPPING=1` - シャットダウンの開始時。
+//!
+//! <https://www.freedesktop.org/software/systemd/man/latest/sd_notify.html>
+
+use super::Control;
+use crate::config;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+pub const READY: &str = "READY=1";
+pub const WATCHDOG: &str = "WATCHDOG=1";
+pub const STOPPING: &str = "STOPPING=1";
+
+/// `state` を systemd に通知する。
+///
+/// 通知先がない場合は false を返す。
+pub fn notify(state: &str) -> Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let path = path.to_str().context("Invalid NOTIFY_SOCKET")?;
+    notify_to(path, state)?;
+
+    Ok(true)
+}
+
+/// [notify] し、失敗した場合はログのみ出力する。
+pub fn notify_or_warn(state: &str) {
+    match notify(state) {
+        Ok(true) => info!("[sd-notify] {state}"),
+        Ok(false) => {}
+        Err(e) => warn!("[sd-notify] {state} failed: {e:#}"),
+    }
+}
+
+/// `path` のソケットに `state` を送る。
+///
+/// `@` で始まる場合は抽象名前空間のソケットとする。
+fn notify_to(path: &str, state: &str) -> Result<()> {
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes())?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    let sock = UnixDatagram::unbound()?;
+    sock.send_to_addr(state.as_bytes(), &addr)
+        .with_context(|| format!("Failed to send to {path}"))?;
+
+    Ok(())
+}
+
+/// 環境変数から watchdog の通知間隔を決める。
+///
+/// 無効な場合は None を返す。
+fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// systemd の推奨どおり、タイムアウトの半分を通知間隔とする。
+///
+/// `WATCHDOG_PID` が設定されていて自分でない場合は無効。
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, self_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid
+        && pid.parse::<u32>().ok()? != self_pid
+    {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// 起動完了を待って `READY=1` を送り、以降キャンセルまで `WATCHDOG=1` を送り続けるタスク。
+pub(super) async fn notify_task(ctrl: Control) -> Result<()> {
+    let timeout_sec = config::get(|cfg| cfg.taskserver.ready_timeout_sec);
+    tokio::select! {
+        result = tokio::time::timeout(Duration::from_secs(timeout_sec), ctrl.wait_ready()) => {
+            if result.is_err() {
+                warn!(
+                    "[sd-notify] not ready in {timeout_sec} sec: {}",
+                    ctrl.pending_ready().join(", ")
+                );
+            }
+        }
+        _ = ctrl.wait_cancel_rx() => {
+            return Ok(());
+        }
+    }
+    notify_or_warn(READY);
+
+    let Some(interval) = watchdog_interval() else {
+        return Ok(());
+    };
+    info!("[sd-notify] watchdog interval: {} ms", interval.as_millis());
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {
+                if let Err(e) = notify(WATCHDOG) {
+                    warn!("[sd-notify] {WATCHDOG} failed: {e:#}");
+                }
+            }
+            _ = ctrl.wait_cancel_rx() => {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn send() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("notify.sock");
+        let server = UnixDatagram::bind(&path).unwrap();
+
+        notify_to(path.to_str().unwrap(), READY).unwrap();
+        let mut buf = [0u8; 64];
+        let size = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], READY.as_bytes());
+
+        // 抽象名前空間
+        let name = format!("shanghai-test-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let server = UnixDatagram::bind_addr(&addr).unwrap();
+        notify_to(&format!("@{name}"), STOPPING).unwrap();
+        let size = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..size], STOPPING.as_bytes());
+    }
+
+    #[test]
+    fn watchdog() {
+        assert_eq!(
+            parse_watchdog(Some("60000000"), None, 100),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_watchdog(Some("60000000"), Some("100"), 100),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(parse_watchdog(Some("60000000"), Some("101"), 100), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 100), None);
+        assert_eq!(parse_watchdog(Some("abc"), None, 100), None);
+        assert_eq!(parse_watchdog(None, None, 100), None);
+    }
+}
@@ -30,6 +30,15 @@ pub fn share_dir() -> Result<PathBuf> {
     Ok(data_dir.join(APP_NAME))
 }
 
+/// e.g. `$HOME/.local/state/shanghai`
+///
+/// https://specifications.freedesktop.org/basedir/latest/
+pub fn state_dir() -> Result<PathBuf> {
+    let state_dir = dirs::state_dir().context("Cannot get state dir")?;
+
+    Ok(state_dir.join(APP_NAME))
+}
+
 /// e.g. `$HOME/.cache/shanghai
 ///
 /// https://specifications.freedesktop.org/basedir/latest/
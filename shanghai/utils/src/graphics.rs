@@ -1,7 +1,9 @@
+pub mod chart;
+
 use std::io::Cursor;
 
 use anyhow::{Context, Result};
-use image::{DynamicImage, ImageFormat, Rgba};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
 use rusttype::{Font, PositionedGlyph, Scale, point};
 
 pub struct FontRenderer {
@@ -117,6 +119,63 @@ impl FontRenderer {
         filebuf
     }
 
+    /// 1行の高さ (ピクセル)。
+    pub fn line_height(&self, scale: u32) -> u32 {
+        let vmet = self.font.v_metrics(Scale::uniform(scale as f32));
+
+        (vmet.ascent - vmet.descent).ceil() as u32
+    }
+
+    /// 1行の `text` を描画した時の横幅 (ピクセル)。
+    pub fn text_width(&self, text: &str, scale: u32) -> u32 {
+        let scale = Scale::uniform(scale as f32);
+        let (w, _) = self.calc_line_w(text, scale, 0.0, 0.0);
+
+        w
+    }
+
+    /// `image` の (`x`, `y`) を左上として1行の `text` を描画する。
+    ///
+    /// 背景とはアルファブレンドする。画像からはみ出た部分は描画しない。
+    pub fn draw_text(
+        &self,
+        image: &mut RgbaImage,
+        color: (u8, u8, u8),
+        x: i32,
+        y: i32,
+        scale: u32,
+        text: &str,
+    ) {
+        let scale = Scale::uniform(scale as f32);
+        let vmet = self.font.v_metrics(scale);
+        let (width, height) = image.dimensions();
+
+        for glyph in self
+            .font
+            .layout(text, scale, point(x as f32, y as f32 + vmet.ascent))
+        {
+            let Some(bounding_box) = glyph.pixel_bounding_box() else {
+                continue;
+            };
+            glyph.draw(|gx, gy, v| {
+                let px = gx as i32 + bounding_box.min.x;
+                let py = gy as i32 + bounding_box.min.y;
+                if px < 0 || py < 0 || px >= width as i32 || py >= height as i32 {
+                    return;
+                }
+                let pixel = image.get_pixel_mut(px as u32, py as u32);
+                let [r1, g1, b1, a1] = pixel.0;
+                let blend = |c1: u8, c2: u8| (c1 as f32 * (1.0 - v) + c2 as f32 * v) as u8;
+                *pixel = Rgba([
+                    blend(r1, color.0),
+                    blend(g1, color.1),
+                    blend(b1, color.2),
+                    a1,
+                ]);
+            });
+        }
+    }
+
     fn calc_line_w(
         &self,
         text: &str,
@@ -0,0 +1,408 @@
+//! 折れ線グラフの描画。
+//!
+//! 軸、目盛り、凡例と複数の系列を持つ折れ線グラフを [image] 上に描画する。
+//! 文字の描画には [FontRenderer] を使う。
+
+use super::FontRenderer;
+use anyhow::Result;
+use image::{ImageFormat, Rgba, RgbaImage};
+use std::io::Cursor;
+
+const BG_COLOR: Rgba<u8> = Rgba([0xff, 0xff, 0xff, 0xff]);
+const AXIS_COLOR: Rgba<u8> = Rgba([0x40, 0x40, 0x40, 0xff]);
+const GRID_COLOR: Rgba<u8> = Rgba([0xe0, 0xe0, 0xe0, 0xff]);
+const TEXT_COLOR: (u8, u8, u8) = (0x20, 0x20, 0x20);
+
+/// 文字の大きさ。
+const FONT_SCALE: u32 = 14;
+/// y 軸の目盛りの目安の数。
+const Y_TICKS: usize = 5;
+/// 余白 (ピクセル)。
+const PADDING: u32 = 8;
+/// y 軸の目盛りラベル領域の幅 (ピクセル)。
+const Y_LABEL_W: u32 = 48;
+/// 凡例の色見本の大きさ (ピクセル)。
+const LEGEND_BOX: u32 = 10;
+
+/// グラフの系列。
+#[derive(Debug, Clone)]
+pub struct Series {
+    /// 凡例に表示する名前。
+    pub label: String,
+    pub color: (u8, u8, u8),
+    /// x 順に並んだ (x, y)。y が None の点で線を切る。
+    pub points: Vec<(f64, Option<f64>)>,
+}
+
+/// 折れ線グラフ。
+#[derive(Debug, Clone)]
+pub struct LineChart {
+    pub title: String,
+    /// 全体の横幅 (ピクセル)。
+    pub width: u32,
+    /// 全体の高さ (ピクセル)。
+    pub height: u32,
+    /// x 軸の範囲 (min, max)。
+    pub x_range: (f64, f64),
+    /// y 軸の範囲 (min, max)。None ならばデータから決める。
+    pub y_range: Option<(f64, f64)>,
+    /// x 軸の目盛りの位置とラベル。
+    pub x_labels: Vec<(f64, String)>,
+    pub series: Vec<Series>,
+}
+
+/// 描画領域上の座標変換。
+#[derive(Debug, Clone, Copy)]
+struct Plot {
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+    x_range: (f64, f64),
+    y_range: (f64, f64),
+}
+
+impl Plot {
+    fn x(&self, x: f64) -> f64 {
+        let (min, max) = self.x_range;
+        self.left + (x - min) / (max - min) * self.width
+    }
+
+    fn y(&self, y: f64) -> f64 {
+        let (min, max) = self.y_range;
+        self.top + self.height - (y - min) / (max - min) * self.height
+    }
+}
+
+impl LineChart {
+    /// 単独の PNG ファイルとして描画する。
+    pub fn render_png(&self, font: &FontRenderer) -> Result<Vec<u8>> {
+        let mut image = RgbaImage::from_pixel(self.width, self.height, BG_COLOR);
+        self.draw(&mut image, 0, 0, font);
+
+        encode_png(&image)
+    }
+
+    /// `image` の (`x0`, `y0`) を左上として描画する。
+    ///
+    /// 複数のグラフを1枚の画像に並べる場合に使う。
+    pub fn draw(&self, image: &mut RgbaImage, x0: u32, y0: u32, font: &FontRenderer) {
+        let line_h = font.line_height(FONT_SCALE);
+
+        // タイトルと凡例
+        font.draw_text(
+            image,
+            TEXT_COLOR,
+            (x0 + PADDING) as i32,
+            (y0 + PADDING) as i32,
+            FONT_SCALE,
+            &self.title,
+        );
+        let legend_y = y0 + PADDING + line_h;
+        let mut legend_x = x0 + PADDING + Y_LABEL_W;
+        for s in &self.series {
+            let box_y = legend_y + (line_h - LEGEND_BOX) / 2;
+            fill_rect(
+                image,
+                legend_x,
+                box_y,
+                LEGEND_BOX,
+                LEGEND_BOX,
+                rgba(s.color),
+            );
+            legend_x += LEGEND_BOX + 4;
+            font.draw_text(
+                image,
+                TEXT_COLOR,
+                legend_x as i32,
+                legend_y as i32,
+                FONT_SCALE,
+                &s.label,
+            );
+            legend_x += font.text_width(&s.label, FONT_SCALE) + 16;
+        }
+
+        let top = legend_y + line_h + PADDING;
+        let left = x0 + PADDING + Y_LABEL_W;
+        let right = (x0 + self.width).saturating_sub(PADDING * 3);
+        let bottom = (y0 + self.height).saturating_sub(PADDING + line_h + 4);
+        if right <= left || bottom <= top {
+            return;
+        }
+
+        let (ymin, ymax) = self.y_range.unwrap_or_else(|| self.data_y_range());
+        let ticks = nice_ticks(ymin, ymax, Y_TICKS);
+        let y_range = match (ticks.first(), ticks.last()) {
+            (Some(&first), Some(&last)) if first < last => (first, last),
+            _ => (ymin, ymin + 1.0),
+        };
+        let plot = Plot {
+            left: left as f64,
+            top: top as f64,
+            width: (right - left) as f64,
+            height: (bottom - top) as f64,
+            x_range: self.x_range,
+            y_range,
+        };
+
+        // 目盛りとグリッド
+        let decimals = tick_decimals(&ticks);
+        for &tick in &ticks {
+            let y = plot.y(tick).round() as u32;
+            hline(image, left, right, y, GRID_COLOR);
+            let label = format!("{tick:.decimals$}");
+            let w = font.text_width(&label, FONT_SCALE);
+            font.draw_text(
+                image,
+                TEXT_COLOR,
+                left as i32 - w as i32 - 4,
+                y as i32 - line_h as i32 / 2,
+                FONT_SCALE,
+                &label,
+            );
+        }
+        for (x, label) in &self.x_labels {
+            if *x < self.x_range.0 || *x > self.x_range.1 {
+                continue;
+            }
+            let x = plot.x(*x).round() as u32;
+            vline(image, x, top, bottom, GRID_COLOR);
+            vline(image, x, bottom, bottom + 4, AXIS_COLOR);
+            let w = font.text_width(label, FONT_SCALE);
+            font.draw_text(
+                image,
+                TEXT_COLOR,
+                x as i32 - w as i32 / 2,
+                (bottom + 4) as i32,
+                FONT_SCALE,
+                label,
+            );
+        }
+
+        // 系列
+        for s in &self.series {
+            let color = rgba(s.color);
+            let mut prev: Option<(f64, f64)> = None;
+            for &(x, y) in &s.points {
+                let Some(y) = y else {
+                    prev = None;
+                    continue;
+                };
+                let p = (plot.x(x), plot.y(y));
+                match prev {
+                    Some(prev) => draw_line(image, prev, p, color),
+                    None => draw_line(image, p, p, color),
+                }
+                prev = Some(p);
+            }
+        }
+
+        // 軸
+        vline(image, left, top, bottom, AXIS_COLOR);
+        hline(image, left, right, bottom, AXIS_COLOR);
+    }
+
+    /// 全系列の y の (min, max)。データがなければ (0, 1)。
+    fn data_y_range(&self) -> (f64, f64) {
+        let mut values = self
+            .series
+            .iter()
+            .flat_map(|s| s.points.iter().filter_map(|&(_, y)| y))
+            .filter(|y| y.is_finite())
+            .peekable();
+        if values.peek().is_none() {
+            return (0.0, 1.0);
+        }
+        values.fold((f64::MAX, f64::MIN), |(min, max), y| {
+            (min.min(y), max.max(y))
+        })
+    }
+}
+
+/// 複数のグラフを縦に並べて1枚の PNG ファイルとして描画する。
+pub fn render_column_png(charts: &[LineChart], font: &FontRenderer) -> Result<Vec<u8>> {
+    let width = charts.iter().map(|c| c.width).max().unwrap_or(1);
+    let height = charts.iter().map(|c| c.height).sum::<u32>().max(1);
+    let mut image = RgbaImage::from_pixel(width, height, BG_COLOR);
+    let mut y = 0;
+    for chart in charts {
+        chart.draw(&mut image, 0, y, font);
+        y += chart.height;
+    }
+
+    encode_png(&image)
+}
+
+/// PNG にエンコードする。
+pub fn encode_png(image: &RgbaImage) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    image.write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)?;
+
+    Ok(buf)
+}
+
+/// `min` から `max` を含む、きりのよい間隔の目盛りを `count` 個程度返す。
+pub fn nice_ticks(min: f64, max: f64, count: usize) -> Vec<f64> {
+    if !min.is_finite() || !max.is_finite() || count == 0 {
+        return Vec::new();
+    }
+    let (min, max) = if (max - min).abs() < f64::EPSILON {
+        (min - 1.0, max + 1.0)
+    } else {
+        (min, max)
+    };
+
+    let raw = (max - min) / count as f64;
+    let mag = 10f64.powf(raw.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|m| m * mag)
+        .find(|&step| step >= raw)
+        .unwrap_or(10.0 * mag);
+
+    let start = (min / step).floor() as i64;
+    let end = (max / step).ceil() as i64;
+    (start..=end).map(|i| i as f64 * step).collect()
+}
+
+/// 目盛りのラベルに必要な小数点以下の桁数。
+fn tick_decimals(ticks: &[f64]) -> usize {
+    match ticks {
+        [a, b, ..] => (-(b - a).log10().floor()).max(0.0) as usize,
+        _ => 0,
+    }
+}
+
+fn rgba(color: (u8, u8, u8)) -> Rgba<u8> {
+    Rgba([color.0, color.1, color.2, 0xff])
+}
+
+fn put(image: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>) {
+    let (w, h) = image.dimensions();
+    if x >= 0 && y >= 0 && x < w as i64 && y < h as i64 {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+fn fill_rect(image: &mut RgbaImage, x: u32, y: u32, w: u32, h: u32, color: Rgba<u8>) {
+    for py in y..y + h {
+        for px in x..x + w {
+            put(image, px as i64, py as i64, color);
+        }
+    }
+}
+
+fn hline(image: &mut RgbaImage, x1: u32, x2: u32, y: u32, color: Rgba<u8>) {
+    fill_rect(image, x1, y, x2 - x1 + 1, 1, color);
+}
+
+fn vline(image: &mut RgbaImage, x: u32, y1: u32, y2: u32, color: Rgba<u8>) {
+    fill_rect(image, x, y1, 1, y2 - y1 + 1, color);
+}
+
+/// 太さ 2 ピクセルの線分を描く (Bresenham)。
+fn draw_line(image: &mut RgbaImage, p1: (f64, f64), p2: (f64, f64), color: Rgba<u8>) {
+    let (mut x, mut y) = (p1.0.round() as i64, p1.1.round() as i64);
+    let (x2, y2) = (p2.0.round() as i64, p2.1.round() as i64);
+    let dx = (x2 - x).abs();
+    let dy = -(y2 - y).abs();
+    let sx = if x < x2 { 1 } else { -1 };
+    let sy = if y < y2 { 1 } else { -1 };
+    let mut err = dx + dy;
+    loop {
+        for (ox, oy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            put(image, x + ox, y + oy, color);
+        }
+        if x == x2 && y == y2 {
+            break;
+        }
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn ticks() {
+        assert_eq!(
+            nice_ticks(0.0, 100.0, 5),
+            [0.0, 20.0, 40.0, 60.0, 80.0, 100.0]
+        );
+        assert_eq!(nice_ticks(41.3, 58.9, 5), [40.0, 45.0, 50.0, 55.0, 60.0]);
+        // 幅がない場合は広げる
+        assert_eq!(nice_ticks(50.0, 50.0, 2), [49.0, 50.0, 51.0]);
+        assert!(nice_ticks(f64::NAN, 1.0, 5).is_empty());
+
+        assert_eq!(tick_decimals(&[0.0, 20.0]), 0);
+        assert_eq!(tick_decimals(&[0.0, 0.5]), 1);
+        assert_eq!(tick_decimals(&[0.0, 0.02]), 2);
+    }
+
+    #[test]
+    fn line() {
+        let mut image = RgbaImage::from_pixel(10, 10, BG_COLOR);
+        let color = rgba((0xff, 0, 0));
+        // はみ出した部分は描画しない
+        draw_line(&mut image, (-5.0, 0.0), (9.0, 9.0), color);
+        assert_eq!(*image.get_pixel(9, 9), color);
+        assert_eq!(*image.get_pixel(0, 9), BG_COLOR);
+    }
+
+    #[test]
+    #[ignore]
+    // sudo apt install fonts-ipafont
+    // cargo test chart -- --ignored
+    fn chart() -> Result<()> {
+        let fname = "/usr/share/fonts/truetype/fonts-japanese-gothic.ttf";
+        let font = FontRenderer::new(fs::read(fname)?)?;
+
+        let points = |f: fn(f64) -> f64| {
+            (0..=100)
+                .map(|i| {
+                    let x = i as f64;
+                    (x, (!(40..45).contains(&i)).then(|| f(x)))
+                })
+                .collect()
+        };
+        let chart = LineChart {
+            title: "テスト".to_string(),
+            width: 640,
+            height: 320,
+            x_range: (0.0, 100.0),
+            y_range: None,
+            x_labels: (0..=4)
+                .map(|i| (i as f64 * 25.0, format!("{}", i * 25)))
+                .collect(),
+            series: vec![
+                Series {
+                    label: "sin".to_string(),
+                    color: (0xe0, 0x40, 0x40),
+                    points: points(|x| 50.0 + 30.0 * (x / 10.0).sin()),
+                },
+                Series {
+                    label: "linear".to_string(),
+                    color: (0x40, 0x40, 0xe0),
+                    points: points(|x| x * 0.8),
+                },
+            ],
+        };
+        let png = chart.render_png(&font)?;
+
+        let fname = "chart_test.png";
+        println!("Write image to: {fname}");
+        fs::write(fname, png)?;
+
+        Ok(())
+    }
+}